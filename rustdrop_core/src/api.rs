@@ -1,5 +1,8 @@
 mod discovery_handle;
 pub mod events;
 mod pairing;
+mod transfer;
 pub use discovery_handle::DiscoveryHandle;
 pub use pairing::PairingRequest;
+pub(crate) use transfer::TransferTracker;
+pub use transfer::{DeclineReason, MultiOutcome, MultiTransfer, Transfer, TransferOutcome};
@@ -1,80 +1,155 @@
-use std::ops::ControlFlow;
+use std::sync::Arc;
 
-use flume::Receiver;
-use tokio::runtime::Handle;
-use tracing::{error, info, info_span, instrument, span};
+use futures::future::join_all;
+use tokio::{join, runtime::Handle, select, sync::Semaphore};
+use tracing::{error, info, instrument};
 
+use super::TransferTracker;
 use crate::{
-    mediums::{Discover, Discovery},
-    Context, Device, Outgoing, RustdropResult, SenderEvent,
+    core::RustdropError,
+    mediums::{Discover, Route},
+    runner::KnownDevices,
+    Context, Device, MultiTransfer, Outgoing, RustdropResult, Transfer, TransferOutcome,
 };
+// Chunks read at once when sending to several devices.
+const MAX_READS: usize = 2;
 
 #[derive(Debug)]
 pub struct DiscoveryHandle {
     device: Device,
     context: Context,
-    discoveries: Receiver<Discover>,
+    devices: KnownDevices,
 }
 impl DiscoveryHandle {
-    pub fn new(device: Device, context: Context, discoveries: Receiver<Discover>) -> Self {
+    pub(crate) fn new(device: Device, context: Context, devices: KnownDevices) -> Self {
         Self {
             device,
             context,
-            discoveries,
+            devices,
         }
     }
-    pub fn send_file(
-        &self,
-        outgoing: Outgoing,
-        handle: &Handle,
-    ) -> RustdropResult<Receiver<SenderEvent>> {
+    // Every known route to the device, in the order they are tried
+    pub fn routes(&self) -> Vec<Route> {
+        self.ranked().iter().map(Discover::route).collect()
+    }
+    fn ranked(&self) -> Vec<Discover> {
+        self.devices
+            .ranked(self.device.endpoint_id, self.context.config.prefer_ipv6)
+    }
+    pub fn send_file(&self, outgoing: Outgoing, handle: &Handle) -> RustdropResult<Transfer> {
         info!("Running client");
-        let (tx, rx) = flume::unbounded();
-        let cloned = self.context.clone();
-        let discoveries = self.discoveries.clone();
-        self.context.spawn_on(
+        let (tracker, transfer) = TransferTracker::new();
+        self.context
+            .spawn_on(self.sending().run(outgoing, tracker), handle);
+        Ok(transfer)
+    }
+    // Sends the same share to every device at once, with a transfer for each.
+    pub fn send_to_all(
+        targets: &[&DiscoveryHandle],
+        mut outgoing: Outgoing,
+        handle: &Handle,
+    ) -> RustdropResult<MultiTransfer> {
+        let Some(first) = targets.first() else {
+            return Ok(MultiTransfer::new(Vec::new()));
+        };
+        outgoing.limit_reads(Arc::new(Semaphore::new(MAX_READS)));
+        let limit = first.context.config.max_buffered_reader;
+        let mut sendings = Vec::new();
+        let mut transfers = Vec::new();
+        for target in targets {
+            let (tracker, transfer) = TransferTracker::new();
+            sendings.push((target.sending(), tracker));
+            transfers.push((target.device.clone(), transfer));
+        }
+        first.context.spawn_on(
             async move {
-                while let Ok(discovery) = discoveries.recv_async().await {
-                    if let ControlFlow::Break(_) = send_to(discovery, &cloned, &outgoing, &tx).await
-                    {
-                        break;
+                if let Err(e) = outgoing.buffer_readers(limit).await {
+                    error!("Unable to read the share: {}", e);
+                    for (_, tracker) in sendings {
+                        tracker.finish(TransferOutcome::Failed(e.to_string()));
                     }
+                    return;
                 }
+                let sendings = sendings
+                    .into_iter()
+                    .map(|(sending, tracker)| sending.run(outgoing.clone(), tracker));
+                join_all(sendings).await;
             },
             handle,
         );
-        info!("Done sending");
-        Ok(rx)
+        Ok(MultiTransfer::new(transfers))
+    }
+    fn sending(&self) -> Sending {
+        Sending {
+            discoveries: self.ranked(),
+            context: self.context.clone(),
+            devices: self.devices.clone(),
+            endpoint_id: self.device.endpoint_id,
+        }
     }
     pub fn device(&self) -> &Device {
         &self.device
     }
 }
-#[instrument(fields(discovery=?discovery), skip_all)]
-async fn send_to(
-    discovery: Discover,
-    cloned: &Context,
+// A send to one device, which no longer needs its handle.
+struct Sending {
+    discoveries: Vec<Discover>,
+    context: Context,
+    devices: KnownDevices,
+    endpoint_id: u32,
+}
+impl Sending {
+    async fn run(self, outgoing: Outgoing, tracker: TransferTracker) {
+        let cancelled = tracker.cancelled();
+        let outcome = select! {
+            _ = cancelled.cancelled() => TransferOutcome::Cancelled,
+            outcome = send_over_routes(self.discoveries, &self.context, &outgoing, &tracker) => {
+                match outcome {
+                    Ok(discovery) => {
+                        self.devices.succeeded(self.endpoint_id, &discovery);
+                        TransferOutcome::Failed("Disconnected before finishing".into())
+                    }
+                    Err(e) => {
+                        error!("Unable to send to {}: {}", self.endpoint_id, e);
+                        TransferOutcome::Failed(e.to_string())
+                    }
+                }
+            },
+        };
+        tracker.finish(outcome);
+    }
+}
+// Tries each route in turn, returning the one the share went through.
+// Once the handshake is done the receiver may have asked its user, so a later failure is final.
+async fn send_over_routes(
+    discoveries: Vec<Discover>,
+    context: &Context,
     outgoing: &Outgoing,
-    tx: &flume::Sender<SenderEvent>,
-) -> ControlFlow<()> {
-    let context = cloned.clone();
-    let res = match discovery {
-        Discover::Wlan(discovery) => {
-            discovery
-                .send_to(context, outgoing.clone(), tx.clone())
-                .await
+    tracker: &TransferTracker,
+) -> RustdropResult<Discover> {
+    let mut last = RustdropError::Connection().into();
+    for discovery in discoveries {
+        let (res, reached) = send_to(&discovery, context, outgoing, tracker).await;
+        match res {
+            Ok(()) => return Ok(discovery),
+            Err(e) if reached => return Err(e),
+            Err(e) => {
+                error!("{}", e);
+                last = e;
+            }
         }
-        Discover::Bluetooth(discovery) => {
-            discovery
-                .send_to(context, outgoing.clone(), tx.clone())
-                .await
-        }
-    };
-
-    if let Err(e) = res {
-        error!("{}", e);
-    } else {
-        return ControlFlow::Break(());
     }
-    ControlFlow::Continue(())
+    Err(last)
+}
+// Also tells whether the handshake with the device was done.
+#[instrument(fields(discovery=?discovery), skip_all)]
+async fn send_to(
+    discovery: &Discover,
+    context: &Context,
+    outgoing: &Outgoing,
+    tracker: &TransferTracker,
+) -> (RustdropResult<()>, bool) {
+    let (tx, rx) = flume::unbounded();
+    let sending = discovery.send_to(context.clone(), outgoing.clone(), tx);
+    join!(sending, tracker.forward(rx))
 }
@@ -1,22 +1,41 @@
-use super::discovery_handle::DiscoveryHandle;
-use crate::{IncomingText, IncomingWifi, PairingRequest};
+use super::{discovery_handle::DiscoveryHandle, DeclineReason};
+use crate::{
+    core::RejectionReason,
+    mediums::{MediumType, Route},
+    Device, IncomingText, IncomingWifi, PairingRequest, ReceivedFile, ReceivedTransfer,
+};
 
 #[derive(Debug)]
 pub enum DiscoveryEvent {
-    Discovered(DiscoveryHandle),
-    Removed(),
-    //Device
+    Discovered(Box<DiscoveryHandle>),
+    // The name, type or the routes a device can be reached over changed
+    Updated(Device, Vec<MediumType>),
+    // The device is no longer reachable over any medium, by endpoint id
+    Removed(u32),
 }
 #[derive(Debug)]
 pub enum ReceiveEvent {
     Text(IncomingText),
     Wifi(IncomingWifi),
     PairingRequest(PairingRequest),
+    AutoRejected(RejectionReason),
+    // A sender which scanned our QR code, describing what it is sharing
+    AutoAccepted(String),
+    // A file was written to disk
+    File(ReceivedFile),
+    // Everything the sender announced arrived
+    TransferCompleted(ReceivedTransfer),
 }
 #[derive(Debug)]
 pub enum SenderEvent {
+    // Connected to the device over this route
+    Connected(Route),
+    // The code the receiver shows as well, for the user to compare
+    Pin(String),
     AwaitingResponse(),
     Accepted(),
-    Rejected(),
+    // Bytes of the share sent so far
+    Progress(u64),
+    Rejected(DeclineReason),
     Finished(),
 }
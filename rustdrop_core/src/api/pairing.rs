@@ -10,6 +10,7 @@ use crate::{
 
 #[derive(Debug)]
 pub struct PairingRequest {
+    endpoint_id: Option<u32>,
     device_name: String,
     device_type: DeviceType,
     incoming: Incoming,
@@ -17,7 +18,8 @@ pub struct PairingRequest {
 }
 
 impl PairingRequest {
-    pub fn new(
+    pub(crate) fn new(
+        endpoint_id: Option<u32>,
         endpoint_info: &[u8],
         incoming: Incoming,
     ) -> RustdropResult<(Self, PairingResponse)> {
@@ -25,6 +27,7 @@ impl PairingRequest {
         let info = EndpointInfo::decode_raw(endpoint_info)?;
         Ok((
             PairingRequest {
+                endpoint_id,
                 device_name: info.name.clone(),
                 device_type: info.devtype(),
                 incoming,
@@ -40,12 +43,23 @@ impl PairingRequest {
         format!(
             "{} wants to share {} with you",
             self.device_name,
-            self.incoming.meta_type()
+            self.incoming.describe()
         )
     }
+    // Not every sender tells its endpoint id.
+    pub fn endpoint_id(&self) -> Option<u32> {
+        self.endpoint_id
+    }
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
     pub fn device_type(&self) -> DeviceType {
         self.device_type
     }
+    // Everything the sender announced, to be shown before accepting.
+    pub fn incoming(&self) -> &Incoming {
+        &self.incoming
+    }
     pub fn respond(self, response: bool) {
         self.tx.send(response).unwrap()
     }
@@ -0,0 +1,187 @@
+use flume::Receiver;
+use futures::future::join_all;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::{mediums::Route, Device, SenderEvent};
+
+// Why the receiver turned a share down, as far as it tells the sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclineReason {
+    // Declined by the user, or for a reason the receiver kept to itself
+    Declined,
+    NotEnoughSpace,
+    // Too large, or something the receiver does not take
+    Unsupported,
+    TimedOut,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferOutcome {
+    Completed,
+    Rejected(DeclineReason),
+    Failed(String),
+    Cancelled,
+}
+#[derive(Debug, Clone, Default)]
+struct Progress {
+    route: Option<Route>,
+    pin: Option<String>,
+    sent: u64,
+    outcome: Option<TransferOutcome>,
+}
+// A share on its way to a device.
+#[derive(Debug)]
+pub struct Transfer {
+    events: Receiver<SenderEvent>,
+    progress: watch::Receiver<Progress>,
+    cancel: CancellationToken,
+}
+impl Transfer {
+    pub fn events(&self) -> &Receiver<SenderEvent> {
+        &self.events
+    }
+    // Stops the transfer, closing the connection if there is one.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+    // The route of the current attempt, once connected.
+    pub fn route(&self) -> Option<Route> {
+        self.progress.borrow().route.clone()
+    }
+    // The code the receiver shows as well, once the handshake is done.
+    pub fn pin(&self) -> Option<String> {
+        self.progress.borrow().pin.clone()
+    }
+    // Bytes sent over the current attempt.
+    pub fn sent(&self) -> u64 {
+        self.progress.borrow().sent
+    }
+    pub async fn outcome(&self) -> TransferOutcome {
+        let mut progress = self.progress.clone();
+        let outcome = match progress
+            .wait_for(|progress| progress.outcome.is_some())
+            .await
+        {
+            Ok(progress) => progress.outcome.clone().unwrap(),
+            Err(_) => TransferOutcome::Failed("Transfer stopped".into()),
+        };
+        outcome
+    }
+}
+// A share on its way to several devices, each with a transfer of its own.
+#[derive(Debug)]
+pub struct MultiTransfer {
+    transfers: Vec<(Device, Transfer)>,
+}
+impl MultiTransfer {
+    pub(crate) fn new(transfers: Vec<(Device, Transfer)>) -> Self {
+        Self { transfers }
+    }
+    pub fn transfers(&self) -> &[(Device, Transfer)] {
+        &self.transfers
+    }
+    pub fn cancel(&self) {
+        for (_, transfer) in &self.transfers {
+            transfer.cancel();
+        }
+    }
+    // Bytes sent to all the devices together.
+    pub fn sent(&self) -> u64 {
+        self.transfers
+            .iter()
+            .map(|(_, transfer)| transfer.sent())
+            .sum()
+    }
+    // Waits for every device to be done.
+    pub async fn outcome(&self) -> MultiOutcome {
+        let outcomes = self
+            .transfers
+            .iter()
+            .map(|(_, transfer)| transfer.outcome());
+        let outcomes = join_all(outcomes).await;
+        let devices = self.transfers.iter().map(|(device, _)| device.clone());
+        MultiOutcome {
+            outcomes: devices.zip(outcomes).collect(),
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub struct MultiOutcome {
+    pub outcomes: Vec<(Device, TransferOutcome)>,
+}
+impl MultiOutcome {
+    pub fn completed(&self) -> impl Iterator<Item = &Device> {
+        self.outcomes
+            .iter()
+            .filter(|(_, outcome)| *outcome == TransferOutcome::Completed)
+            .map(|(device, _)| device)
+    }
+    pub fn is_completed(&self) -> bool {
+        self.completed().count() == self.outcomes.len()
+    }
+}
+// The side of a transfer its task holds on to.
+#[derive(Debug)]
+pub(crate) struct TransferTracker {
+    events: flume::Sender<SenderEvent>,
+    progress: watch::Sender<Progress>,
+    cancel: CancellationToken,
+}
+impl TransferTracker {
+    pub fn new() -> (Self, Transfer) {
+        let (events, events_rx) = flume::unbounded();
+        let (progress, progress_rx) = watch::channel(Progress::default());
+        let cancel = CancellationToken::new();
+        let transfer = Transfer {
+            events: events_rx,
+            progress: progress_rx,
+            cancel: cancel.clone(),
+        };
+        let tracker = Self {
+            events,
+            progress,
+            cancel,
+        };
+        (tracker, transfer)
+    }
+    pub fn cancelled(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+    // Passes the event on, keeping track of what it says about the transfer.
+    pub async fn event(&self, event: SenderEvent) {
+        self.progress.send_modify(|progress| match &event {
+            SenderEvent::Connected(route) => {
+                progress.route = Some(route.clone());
+                progress.pin = None;
+                progress.sent = 0;
+            }
+            SenderEvent::Pin(pin) => progress.pin = Some(pin.clone()),
+            SenderEvent::Progress(sent) => progress.sent = *sent,
+            SenderEvent::Rejected(reason) => {
+                progress.outcome = Some(TransferOutcome::Rejected(*reason))
+            }
+            SenderEvent::Finished() => progress.outcome = Some(TransferOutcome::Completed),
+            _ => (),
+        });
+        let _ = self.events.send_async(event).await;
+    }
+    // Passes on every event of an attempt, telling whether its handshake was done.
+    pub async fn forward(&self, events: Receiver<SenderEvent>) -> bool {
+        let mut reached = false;
+        while let Ok(event) = events.recv_async().await {
+            reached |= matches!(event, SenderEvent::Pin(_));
+            self.event(event).await;
+        }
+        reached
+    }
+    // Settles the outcome, unless an event already did.
+    pub fn finish(&self, outcome: TransferOutcome) {
+        self.progress.send_if_modified(|progress| {
+            if progress.outcome.is_some() {
+                return false;
+            }
+            progress.outcome = Some(outcome);
+            true
+        });
+    }
+}
@@ -1,16 +1,35 @@
 pub mod bits;
+pub mod capture;
 mod config;
 mod errors;
 pub(crate) mod handlers;
+pub(crate) mod identity;
+pub mod inspect;
 pub(crate) mod io;
+mod limits;
+mod network;
 mod payload;
 pub(crate) mod protocol;
+mod qr;
 pub(crate) mod ukey2;
 pub(crate) mod util;
+pub(crate) use capture::Session;
 pub use config::Config;
 pub use errors::RustdropError;
+pub use limits::RejectionReason;
+pub(crate) use limits::{check_limits, Limits};
+pub use network::{InterfaceMatch, MdnsProvider, NetworkConfig};
 pub use payload::{
-    file::IncomingFile, incoming::Incoming, outgoing::Outgoing, text::IncomingText,
+    file::{FileOptions, IncomingFile, ReceivedFile},
+    incoming::{Incoming, ReceivedTransfer},
+    outgoing::Outgoing,
+    sink::{DirectorySink, IncomingSink, SinkWriter},
+    text::IncomingText,
     wifi::IncomingWifi,
 };
-pub(crate) use payload::{Payload, PayloadReciever, PayloadRecieverHandle, PayloadSender};
+pub(crate) use payload::{
+    incoming::{Received, Receiving},
+    Chunk, Payload, PayloadReciever, PayloadRecieverHandle, PayloadSender,
+};
+pub use qr::{QrCode, ScannedQrCode};
+pub(crate) use qr::{QrCodes, QR_CODE_TTL};
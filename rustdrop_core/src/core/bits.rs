@@ -11,6 +11,11 @@ mod service;
 mod uwb_address;
 pub use devtype::DeviceType;
 pub(crate) use {
-    bitfield::Bitfield, ble::BleName, bluetooth::Name as BluetoothName, endpoint::EndpointInfo,
+    bitfield::Bitfield,
+    ble::{BleFastName, BleName},
+    ble_v2::BleAdvertisement,
+    ble_v2_header::BleHeader,
+    bluetooth::Name as BluetoothName,
+    endpoint::EndpointInfo,
     mdns::Name as MdnsName,
 };
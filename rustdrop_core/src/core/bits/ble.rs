@@ -2,152 +2,213 @@ use std::io::Cursor;
 
 use bytes::{Buf, BufMut};
 use modular_bitfield::prelude::*;
-use tracing::info;
 
-use crate::{core::RustdropError, Config, RustdropResult};
+use crate::{
+    core::{protocol::Device, RustdropError},
+    RustdropResult,
+};
 
-use super::{pcp_version::VersionPcp, service::Service, Bitfield, EndpointInfo};
+use super::{
+    pcp_version::PcpVersion, service::Service, uwb_address::UwbAddress, Bitfield, EndpointInfo,
+};
+// Fast advertisements have no room for the device name.
+const MAX_FAST_ENDPOINT_INFO: usize = 17;
+const MAC_LEN: usize = 6;
 #[bitfield]
 #[derive(Debug)]
 struct BleNameBits {
-    pcp_version: VersionPcp,
+    pcp_version: PcpVersion,
     service: Service,
     endpoint_id: u32,
 }
 #[bitfield]
 #[derive(Debug)]
 struct BleFastNameBits {
-    pcp_version: VersionPcp,
+    pcp_version: PcpVersion,
     endpoint_id: u32,
 }
 impl BleFastNameBits {
-    pub fn from_config(config: &Config) -> Self {
+    pub fn from_endpoint_id(endpoint_id: u32) -> Self {
         Self::new()
-            .with_pcp_version(VersionPcp::default())
-            .with_endpoint_id(config.endpoint_id)
+            .with_pcp_version(PcpVersion::default())
+            .with_endpoint_id(endpoint_id)
     }
 }
 impl BleNameBits {
-    pub fn from_config(config: &Config) -> Self {
+    pub fn from_endpoint_id(endpoint_id: u32) -> Self {
         Self::new()
             .with_service(Service::default())
-            .with_pcp_version(VersionPcp::default())
-            .with_endpoint_id(config.endpoint_id)
+            .with_pcp_version(PcpVersion::default())
+            .with_endpoint_id(endpoint_id)
+    }
+}
+fn decode_endpoint_info(raw: &mut Cursor<&[u8]>) -> RustdropResult<EndpointInfo> {
+    if !raw.has_remaining() {
+        Err(RustdropError::InvalidEndpointId())?;
+    }
+    let size = raw.get_u8() as usize;
+    if size > raw.remaining() {
+        Err(RustdropError::InvalidEndpointId())?;
+    }
+    EndpointInfo::decode_raw(&raw.copy_to_bytes(size))
+}
+fn decode_uwb_address(raw: &mut Cursor<&[u8]>) -> RustdropResult<UwbAddress> {
+    // Anything after the address is an optional extra field we don't use.
+    if raw.has_remaining() {
+        UwbAddress::decode(raw)
+    } else {
+        Ok(UwbAddress::default())
+    }
+}
+fn put_uwb_address(data: &mut Vec<u8>, uwb_address: UwbAddress) {
+    if !uwb_address.is_empty() {
+        data.append(&mut uwb_address.to_vec());
+    }
+}
+fn to_device(endpoint_id: u32, endpoint_info: &EndpointInfo) -> Device {
+    Device {
+        endpoint_id,
+        device_name: endpoint_info.name.clone(),
+        device_type: endpoint_info.devtype(),
     }
 }
 #[derive(Debug)]
 pub struct BleFastName {
     bits: BleFastNameBits,
     pub endpoint_info: EndpointInfo,
-    pub name: String,
-    mac: String,
+    uwb_address: UwbAddress,
 }
 impl BleFastName {
-    pub(crate) fn new(config: &Config, mac: String, endpoint_info: EndpointInfo) -> Self {
-        let bits = BleFastNameBits::from_config(config);
-        let name = config.name.clone();
+    pub(crate) fn new(endpoint_id: u32, endpoint_info: EndpointInfo) -> Self {
+        let bits = BleFastNameBits::from_endpoint_id(endpoint_id);
         Self {
             bits,
-            mac,
-            name,
             endpoint_info,
+            uwb_address: UwbAddress::default(),
         }
     }
+    pub fn endpoint_id(&self) -> u32 {
+        self.bits.endpoint_id()
+    }
+    pub fn device(&self) -> Device {
+        to_device(self.endpoint_id(), &self.endpoint_info)
+    }
 }
 impl Bitfield for BleFastName {
     fn to_vec(self) -> Vec<u8> {
         let mut data = self.bits.into_bytes().to_vec();
-        // data.extend_from_slice(&self.reserved);
-        let mut encoded = self.name.as_bytes().to_vec();
+        let mut encoded = self.endpoint_info.to_vec();
+        encoded.truncate(MAX_FAST_ENDPOINT_INFO);
         data.put_u8(encoded.len() as u8);
         data.append(&mut encoded);
-        data.append(&mut self.mac.as_bytes().to_vec());
+        put_uwb_address(&mut data, self.uwb_address);
         data
     }
     fn decode(name: &mut Cursor<&[u8]>) -> RustdropResult<Self> {
-        // if name.remaining() < 16 {
-        //     Err(RustdropError::InvalidEndpointId())?;
-        // }
         let mut raw_name: [u8; 5] = [0; 5];
+        if name.remaining() < raw_name.len() {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
         name.copy_to_slice(&mut raw_name);
         let bits = BleFastNameBits::from_bytes(raw_name);
-        info!("{:?}", bits);
-        let size = name.get_u8() as usize;
-        if size <= name.remaining() {
-            let endpoint_info_raw = name.copy_to_bytes(size);
-
-            let endpoint_info = EndpointInfo::decode_raw(&endpoint_info_raw)?;
-        }
-        Err(RustdropError::InvalidEndpointId())?;
-
-        todo!()
-        // let (first, second) = endpoint_id.split_at(18);
-        // let (raw_bits, reserved) = first.split_first().unwrap();
-        // let bitfield = BitField::from(*raw_bits);
-        // let name =
-        //     String::from_utf8(second.to_vec()).map_err(|_| RustdropError::InvalidEndpointId())?;
-        // Ok(Self {
-        //     bitfield,
-        //     reserved: Bytes::copy_from_slice(reserved),
-        //     name,
-        // })
+        let endpoint_info = decode_endpoint_info(name)?;
+        let uwb_address = decode_uwb_address(name)?;
+        Ok(Self {
+            bits,
+            endpoint_info,
+            uwb_address,
+        })
     }
 }
 #[derive(Debug)]
 pub struct BleName {
     bits: BleNameBits,
     pub endpoint_info: EndpointInfo,
-    pub name: String,
-    mac: String,
+    mac: [u8; MAC_LEN],
+    uwb_address: UwbAddress,
 }
 impl BleName {
-    pub(crate) fn new(config: &Config, mac: String, endpoint_info: EndpointInfo) -> Self {
-        let bits = BleNameBits::from_config(config);
-        let name = config.name.clone();
+    pub(crate) fn new(endpoint_id: u32, mac: Option<[u8; 6]>, endpoint_info: EndpointInfo) -> Self {
+        let bits = BleNameBits::from_endpoint_id(endpoint_id);
         Self {
             bits,
-            mac,
-            name,
+            mac: mac.unwrap_or_default(),
             endpoint_info,
+            uwb_address: UwbAddress::default(),
         }
     }
+    pub fn endpoint_id(&self) -> u32 {
+        self.bits.endpoint_id()
+    }
+    pub fn device(&self) -> Device {
+        to_device(self.endpoint_id(), &self.endpoint_info)
+    }
+    // The classic Bluetooth address of the advertiser, if it has one.
+    pub fn mac(&self) -> Option<[u8; 6]> {
+        (self.mac != [0; MAC_LEN]).then_some(self.mac)
+    }
 }
 impl Bitfield for BleName {
     fn to_vec(self) -> Vec<u8> {
         let mut data = self.bits.into_bytes().to_vec();
-        // data.extend_from_slice(&self.reserved);
-        let mut encoded = self.name.as_bytes().to_vec();
+        let mut encoded = self.endpoint_info.to_vec();
         data.put_u8(encoded.len() as u8);
         data.append(&mut encoded);
-        data.append(&mut self.mac.as_bytes().to_vec());
+        data.extend_from_slice(&self.mac);
+        put_uwb_address(&mut data, self.uwb_address);
         data
     }
     fn decode(name: &mut Cursor<&[u8]>) -> RustdropResult<Self> {
-        // if name.remaining() < 16 {
-        //     Err(RustdropError::InvalidEndpointId())?;
-        // }
         let mut raw_name: [u8; 8] = [0; 8];
+        if name.remaining() < raw_name.len() {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
         name.copy_to_slice(&mut raw_name);
         let bits = BleNameBits::from_bytes(raw_name);
-        info!("{:?}", bits);
-        let size = name.get_u8() as usize;
-        if size <= name.remaining() {
-            let endpoint_info_raw = name.copy_to_bytes(size);
-
-            let endpoint_info = EndpointInfo::decode_raw(&endpoint_info_raw)?;
+        if bits.service().into_bytes() != Service::default().into_bytes() {
+            Err(RustdropError::InvalidEndpointId())?;
         }
-        Err(RustdropError::InvalidEndpointId())?;
+        let endpoint_info = decode_endpoint_info(name)?;
+        let mut mac = [0; MAC_LEN];
+        if name.remaining() < MAC_LEN {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        name.copy_to_slice(&mut mac);
+        let uwb_address = decode_uwb_address(name)?;
+        Ok(Self {
+            bits,
+            endpoint_info,
+            mac,
+            uwb_address,
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use rand::thread_rng;
+
+    use super::*;
+    use crate::{core::identity::random_endpoint_id, Config};
+
+    #[test]
+    fn test_round_trip() {
+        let config = Config::with_dest(temp_dir());
+        let endpoint_id = random_endpoint_id();
+        let info = EndpointInfo::new(&config, &mut thread_rng());
+        let mac = [1, 2, 3, 4, 5, 6];
+        let raw = BleName::new(endpoint_id, Some(mac), info.clone()).to_vec();
+        let name = BleName::decode_raw(&raw).unwrap();
+        assert_eq!(name.endpoint_id(), endpoint_id);
+        assert_eq!(name.device().device_name, config.name);
+        assert_eq!(name.mac(), Some(mac));
 
-        todo!()
-        // let (first, second) = endpoint_id.split_at(18);
-        // let (raw_bits, reserved) = first.split_first().unwrap();
-        // let bitfield = BitField::from(*raw_bits);
-        // let name =
-        //     String::from_utf8(second.to_vec()).map_err(|_| RustdropError::InvalidEndpointId())?;
-        // Ok(Self {
-        //     bitfield,
-        //     reserved: Bytes::copy_from_slice(reserved),
-        //     name,
-        // })
+        let raw = BleFastName::new(endpoint_id, info).to_vec();
+        assert_eq!(raw.len(), 6 + MAX_FAST_ENDPOINT_INFO);
+        let name = BleFastName::decode_raw(&raw).unwrap();
+        assert_eq!(name.endpoint_id(), endpoint_id);
+        assert_eq!(name.device().device_type, config.devtype);
+        assert!(BleFastName::decode_raw(&raw[..10]).is_err());
     }
 }
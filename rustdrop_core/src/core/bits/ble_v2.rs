@@ -2,30 +2,32 @@ use std::io::Cursor;
 
 use bytes::{Buf, BufMut, Bytes};
 use modular_bitfield::prelude::*;
-use tracing::info;
 
-use crate::{core::RustdropError, Config, RustdropResult};
-
-use super::{
-    pcp_version::{PcpVersion, VersionPcp},
-    service::Service,
-    Bitfield, EndpointInfo,
+use crate::{
+    core::{protocol::Device, RustdropError},
+    RustdropResult,
 };
 
+use super::{ble, service::Service, Bitfield, EndpointInfo};
+
+const VERSION: u8 = 2;
+const SOCKET_VERSION: u8 = 2;
+// Fields are listed from the least significant bit, the header reads version first.
 #[bitfield]
 #[derive(Debug, BitfieldSpecifier)]
 struct Header {
-    version: B3,
-    socket_version: B3,
+    #[skip]
+    __: B1,
     fast_adv: bool,
-    reserved: B1,
+    socket_version: B3,
+    version: B3,
 }
 impl Header {
     fn default(fast_adv: bool) -> Self {
         Self::new()
-            .with_version(2)
+            .with_version(VERSION)
             .with_fast_adv(fast_adv)
-            .with_socket_version(1)
+            .with_socket_version(SOCKET_VERSION)
     }
 }
 #[bitfield]
@@ -39,127 +41,159 @@ struct BleNameBits {
 struct BleFastNameBits {
     header: Header,
 }
-impl BleFastNameBits {
-    pub fn from_config(config: &Config) -> Self {
-        Self::new().with_header(Header::default(true))
-    }
+fn decode_token(name: &mut Cursor<&[u8]>) -> Option<u16> {
+    (name.remaining() >= 2).then(|| name.get_u16())
 }
-impl BleNameBits {
-    pub fn from_config(config: &Config) -> Self {
-        Self::new()
-            .with_header(Header::default(false))
-            .with_service(Service::default())
+fn check_header(header: &Header, fast_adv: bool) -> RustdropResult<()> {
+    // Headers served for GATT share the version, the socket version tells them apart.
+    if header.version() != VERSION
+        || header.socket_version() != SOCKET_VERSION
+        || header.fast_adv() != fast_adv
+    {
+        Err(RustdropError::InvalidEndpointId())?;
     }
+    Ok(())
 }
 
 #[derive(Debug)]
 pub struct BleFastName {
     bits: BleFastNameBits,
-    data: Bytes,
+    pub data: Bytes,
     token: Option<u16>,
 }
 impl BleFastName {
-    pub(crate) fn new(config: &Config, data: Bytes) -> Self {
-        let bits = BleFastNameBits::from_config(config);
-        let name = config.name.clone();
-        Self {
-            bits,
-            data,
-            token: None,
-        }
+    pub(crate) fn new(data: Bytes, token: Option<u16>) -> Self {
+        let bits = BleFastNameBits::new().with_header(Header::default(true));
+        Self { bits, data, token }
     }
 }
 
 impl Bitfield for BleFastName {
     fn to_vec(self) -> Vec<u8> {
         let mut data = self.bits.into_bytes().to_vec();
-        let mut encoded = self.data.to_vec();
-        data.put_u8(encoded.len() as u8);
-        data.append(&mut encoded);
+        data.put_u8(self.data.len() as u8);
+        data.extend_from_slice(&self.data);
+        if let Some(token) = self.token {
+            data.put_u16(token);
+        }
         data
     }
     fn decode(name: &mut Cursor<&[u8]>) -> RustdropResult<Self> {
-        let mut raw_name: [u8; 1] = [0; 1];
-        name.copy_to_slice(&mut raw_name);
-        let bits = BleFastNameBits::from_bytes(raw_name);
-        info!("{:?}", bits);
-        let size = name.get_i8() as usize;
+        if name.remaining() < 2 {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        let bits = BleFastNameBits::from_bytes([name.get_u8()]);
+        check_header(&bits.header(), true)?;
+        let size = name.get_u8() as usize;
         if size > name.remaining() {
             Err(RustdropError::InvalidEndpointId())?;
         }
         let data = name.copy_to_bytes(size);
-        let token = if 2 <= name.remaining() {
-            Some(name.get_u16())
-        } else {
-            None
-        };
-        info!("{:?}, {:?}", data, token);
+        let token = decode_token(name);
         Ok(Self { bits, data, token })
     }
 }
 #[derive(Debug)]
 pub struct BleName {
     bits: BleNameBits,
-    pub endpoint_info: EndpointInfo,
-    pub name: String,
-    mac: String,
+    pub data: Bytes,
+    token: Option<u16>,
 }
 impl BleName {
-    pub(crate) fn new(config: &Config, mac: String, endpoint_info: EndpointInfo) -> Self {
-        let bits = BleNameBits::from_config(config);
-        let name = config.name.clone();
-        Self {
-            bits,
-            mac,
-            name,
-            endpoint_info,
-        }
+    pub(crate) fn new(data: Bytes, token: Option<u16>) -> Self {
+        let bits = BleNameBits::new()
+            .with_header(Header::default(false))
+            .with_service(Service::default());
+        Self { bits, data, token }
     }
 }
 impl Bitfield for BleName {
     fn to_vec(self) -> Vec<u8> {
         let mut data = self.bits.into_bytes().to_vec();
-        // data.extend_from_slice(&self.reserved);
-        let mut encoded = self.name.as_bytes().to_vec();
-        data.put_u8(encoded.len() as u8);
-        data.append(&mut encoded);
-        data.append(&mut self.mac.as_bytes().to_vec());
+        data.put_u32(self.data.len() as u32);
+        data.extend_from_slice(&self.data);
+        if let Some(token) = self.token {
+            data.put_u16(token);
+        }
         data
     }
     fn decode(name: &mut Cursor<&[u8]>) -> RustdropResult<Self> {
-        // if name.remaining() < 16 {
-        //     Err(RustdropError::InvalidEndpointId())?;
-        // }
         let mut raw_name: [u8; 4] = [0; 4];
+        if name.remaining() < raw_name.len() + 4 {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
         name.copy_to_slice(&mut raw_name);
         let bits = BleNameBits::from_bytes(raw_name);
-        info!("{:?}", bits);
-        let size = name.get_u8() as usize;
-        if size <= name.remaining() {
-            let endpoint_info_raw = name.copy_to_bytes(size);
-
-            let endpoint_info = EndpointInfo::decode_raw(&endpoint_info_raw)?;
+        check_header(&bits.header(), false)?;
+        if bits.service().into_bytes() != Service::default().into_bytes() {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        let size = name.get_u32() as usize;
+        if size > name.remaining() {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        let data = name.copy_to_bytes(size);
+        let token = decode_token(name);
+        Ok(Self { bits, data, token })
+    }
+}
+// Either kind of advertisement, told apart by the flag in the header.
+#[derive(Debug)]
+pub enum BleAdvertisement {
+    Fast(BleFastName),
+    Regular(BleName),
+}
+impl BleAdvertisement {
+    pub(crate) fn fast(endpoint_id: u32, endpoint_info: EndpointInfo) -> Self {
+        let inner = ble::BleFastName::new(endpoint_id, endpoint_info).to_vec();
+        Self::Fast(BleFastName::new(inner.into(), None))
+    }
+    pub(crate) fn regular(
+        endpoint_id: u32,
+        mac: Option<[u8; 6]>,
+        endpoint_info: EndpointInfo,
+    ) -> Self {
+        let inner = ble::BleName::new(endpoint_id, mac, endpoint_info).to_vec();
+        Self::Regular(BleName::new(inner.into(), None))
+    }
+    // Decodes the advertisement carried inside.
+    pub fn device(&self) -> RustdropResult<(Device, Option<[u8; 6]>)> {
+        Ok(match self {
+            Self::Fast(name) => (ble::BleFastName::decode_raw(&name.data)?.device(), None),
+            Self::Regular(name) => {
+                let inner = ble::BleName::decode_raw(&name.data)?;
+                (inner.device(), inner.mac())
+            }
+        })
+    }
+}
+impl Bitfield for BleAdvertisement {
+    fn to_vec(self) -> Vec<u8> {
+        match self {
+            Self::Fast(name) => name.to_vec(),
+            Self::Regular(name) => name.to_vec(),
+        }
+    }
+    fn decode(raw: &mut Cursor<&[u8]>) -> RustdropResult<Self> {
+        let Some(first) = raw.chunk().first() else {
+            return Err(RustdropError::InvalidEndpointId().into());
+        };
+        if Header::from_bytes([*first]).fast_adv() {
+            Ok(Self::Fast(BleFastName::decode(raw)?))
+        } else {
+            Ok(Self::Regular(BleName::decode(raw)?))
         }
-        Err(RustdropError::InvalidEndpointId())?;
-
-        todo!()
-        // let (first, second) = endpoint_id.split_at(18);
-        // let (raw_bits, reserved) = first.split_first().unwrap();
-        // let bitfield = BitField::from(*raw_bits);
-        // let name =
-        //     String::from_utf8(second.to_vec()).map_err(|_| RustdropError::InvalidEndpointId())?;
-        // Ok(Self {
-        //     bitfield,
-        //     reserved: Bytes::copy_from_slice(reserved),
-        //     name,
-        // })
     }
 }
 #[cfg(test)]
 mod tests {
+    use std::env::temp_dir;
+
+    use rand::thread_rng;
     use tracing_test::traced_test;
 
-    use crate::core::bits::{ble_v2::BleFastName, Bitfield, EndpointInfo};
+    use super::*;
+    use crate::{core::identity::random_endpoint_id, Config};
 
     #[traced_test]
     #[test]
@@ -169,7 +203,44 @@ mod tests {
             203, 111, 138, 118, 60, 177, 156,
         ];
         let name = BleFastName::decode_raw(&raw).unwrap();
-        EndpointInfo::decode_raw(&name.data).unwrap();
-        // SocketControlFrame::decode(name.data).unwrap();
+        assert_eq!(name.data.len(), 23);
+        assert_eq!(name.token, Some(0xB19C));
+        let inner = ble::BleFastName::decode_raw(&name.data).unwrap();
+        assert_eq!(inner.endpoint_id().to_le_bytes(), *b"0MXF");
+        let (device, mac) = BleAdvertisement::decode_raw(&raw)
+            .unwrap()
+            .device()
+            .unwrap();
+        assert_eq!(device.endpoint_id, inner.endpoint_id());
+        assert_eq!(mac, None);
+        assert_eq!(BleFastName::decode_raw(&raw).unwrap().to_vec(), raw);
+    }
+    #[test]
+    fn test_round_trip() {
+        let config = Config::with_dest(temp_dir());
+        let endpoint_id = random_endpoint_id();
+        let info = EndpointInfo::new(&config, &mut thread_rng());
+        let mac = [6, 5, 4, 3, 2, 1];
+        let inner = ble::BleName::new(endpoint_id, Some(mac), info.clone()).to_vec();
+        let raw = BleName::new(inner.into(), None).to_vec();
+        let BleAdvertisement::Regular(_) = BleAdvertisement::decode_raw(&raw).unwrap() else {
+            panic!("Expected a regular advertisement");
+        };
+        let (device, found) = BleAdvertisement::decode_raw(&raw)
+            .unwrap()
+            .device()
+            .unwrap();
+        assert_eq!(device.endpoint_id, endpoint_id);
+        assert_eq!(device.device_name, config.name);
+        assert_eq!(found, Some(mac));
+
+        let inner = ble::BleFastName::new(endpoint_id, info).to_vec();
+        let raw = BleFastName::new(inner.into(), Some(7)).to_vec();
+        let (device, _) = BleAdvertisement::decode_raw(&raw)
+            .unwrap()
+            .device()
+            .unwrap();
+        assert_eq!(device.endpoint_id, endpoint_id);
+        assert!(BleName::decode_raw(&raw).is_err());
     }
 }
@@ -1,12 +1,147 @@
+use std::io::Cursor;
+
+use bytes::{Buf, BufMut};
 use modular_bitfield::prelude::*;
 
+use crate::{
+    core::{
+        ukey2::{Crypto, CryptoImpl},
+        RustdropError,
+    },
+    RustdropResult,
+};
+
+use super::Bitfield;
+
+const VERSION: u8 = 2;
+const BLOOM_FILTER_LEN: usize = 10;
+const ADV_HASH_LEN: usize = 4;
+const HASH_COUNT: i32 = 5;
+// Fields are listed from the least significant bit, the header reads version first.
 #[bitfield]
-#[derive(Debug)]
-struct BleHeader {
-    version: B3,
-    extended: bool,
+#[derive(Debug, Clone, Copy)]
+struct HeaderBits {
     slots: B4,
-    bloom_filter: B80,
-    adv_hash: B32,
-    psm: B16,
+    #[skip(getters)]
+    extended: bool,
+    version: B3,
+}
+// Which service ids a device advertises over GATT, without listing them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BloomFilter([u8; BLOOM_FILTER_LEN]);
+impl BloomFilter {
+    fn positions(service_id: &str) -> impl Iterator<Item = usize> {
+        let hash = CryptoImpl::sha256(service_id.as_bytes());
+        let first = i32::from_le_bytes(hash[..4].try_into().unwrap());
+        let second = i32::from_le_bytes(hash[4..8].try_into().unwrap());
+        (1..=HASH_COUNT).map(move |i| {
+            let combined = first.wrapping_add(i.wrapping_mul(second));
+            let combined = if combined < 0 { !combined } else { combined };
+            combined as usize % (BLOOM_FILTER_LEN * 8)
+        })
+    }
+    pub fn add(&mut self, service_id: &str) {
+        for pos in Self::positions(service_id) {
+            self.0[pos / 8] |= 1 << (pos % 8);
+        }
+    }
+    pub fn contains(&self, service_id: &str) -> bool {
+        Self::positions(service_id).all(|pos| self.0[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+}
+fn adv_hash<T: AsRef<[u8]>>(advertisements: &[T]) -> [u8; ADV_HASH_LEN] {
+    let all: Vec<u8> = advertisements
+        .iter()
+        .flat_map(|adv| adv.as_ref().iter().copied())
+        .collect();
+    CryptoImpl::sha256(&all)[..ADV_HASH_LEN].try_into().unwrap()
+}
+// Sent in the service data when the advertisements themselves are served over GATT.
+#[derive(Debug, Clone)]
+pub struct BleHeader {
+    bits: HeaderBits,
+    pub bloom_filter: BloomFilter,
+    adv_hash: [u8; ADV_HASH_LEN],
+    pub psm: u16,
+}
+impl BleHeader {
+    pub(crate) fn new<T: AsRef<[u8]>>(
+        service_ids: &[&str],
+        advertisements: &[T],
+        psm: u16,
+    ) -> Self {
+        let mut bloom_filter = BloomFilter::default();
+        for id in service_ids {
+            bloom_filter.add(id);
+        }
+        let bits = HeaderBits::new()
+            .with_version(VERSION)
+            .with_slots(advertisements.len() as u8);
+        Self {
+            bits,
+            bloom_filter,
+            adv_hash: adv_hash(advertisements),
+            psm,
+        }
+    }
+    pub fn slots(&self) -> usize {
+        self.bits.slots().into()
+    }
+    // Whether these are the advertisements the header was made for.
+    #[cfg_attr(not(feature = "bluetooth"), allow(dead_code))]
+    pub fn matches<T: AsRef<[u8]>>(&self, advertisements: &[T]) -> bool {
+        self.adv_hash == adv_hash(advertisements)
+    }
+}
+impl Bitfield for BleHeader {
+    fn to_vec(self) -> Vec<u8> {
+        let mut data = self.bits.into_bytes().to_vec();
+        data.extend_from_slice(&self.bloom_filter.0);
+        data.extend_from_slice(&self.adv_hash);
+        data.put_u16(self.psm);
+        data
+    }
+    fn decode(raw: &mut Cursor<&[u8]>) -> RustdropResult<Self> {
+        if raw.remaining() < 1 + BLOOM_FILTER_LEN + ADV_HASH_LEN {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        let bits = HeaderBits::from_bytes([raw.get_u8()]);
+        if bits.version() != VERSION {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        let mut bloom_filter = BloomFilter::default();
+        raw.copy_to_slice(&mut bloom_filter.0);
+        let mut adv_hash = [0; ADV_HASH_LEN];
+        raw.copy_to_slice(&mut adv_hash);
+        // Older headers stop before the PSM.
+        let psm = if raw.remaining() >= 2 {
+            raw.get_u16()
+        } else {
+            0
+        };
+        Ok(Self {
+            bits,
+            bloom_filter,
+            adv_hash,
+            psm,
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let advs = [vec![1, 2, 3], vec![4, 5]];
+        let raw = BleHeader::new(&["NearbySharing"], &advs, 0x80).to_vec();
+        assert_eq!(raw.len(), 17);
+        let header = BleHeader::decode_raw(&raw).unwrap();
+        assert_eq!(header.slots(), 2);
+        assert_eq!(header.psm, 0x80);
+        assert!(header.bloom_filter.contains("NearbySharing"));
+        assert!(!header.bloom_filter.contains("NearbyConnections"));
+        assert!(header.matches(&advs));
+        assert!(!header.matches(&advs[..1]));
+    }
 }
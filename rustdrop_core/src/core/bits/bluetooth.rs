@@ -2,9 +2,11 @@ use std::io::Cursor;
 
 use bytes::Buf;
 use modular_bitfield::prelude::*;
-use tracing::info;
 
-use crate::{Config, RustdropResult};
+use crate::{
+    core::{protocol::Device, RustdropError},
+    RustdropResult,
+};
 
 use super::{pcp_version::PcpVersion, service::Service, Bitfield, EndpointInfo};
 
@@ -18,10 +20,10 @@ struct NameBits {
     reserved: B48,
 }
 impl NameBits {
-    fn from_config(config: &Config) -> Self {
+    fn from_endpoint_id(endpoint_id: u32) -> Self {
         Self::new()
             .with_pcp_version(PcpVersion::default())
-            .with_endpoint_id(config.endpoint_id)
+            .with_endpoint_id(endpoint_id)
             .with_service(Service::default())
             .with_webrtc_state(0x0)
     }
@@ -29,19 +31,26 @@ impl NameBits {
 #[derive(Debug)]
 pub struct Name {
     bits: NameBits,
-    endpoint_info: EndpointInfo,
-    pub name: String,
+    pub endpoint_info: EndpointInfo,
 }
 impl Name {
-    pub fn new(config: &Config, endpoint_info: EndpointInfo) -> Self {
-        let bits = NameBits::from_config(config);
-        let name = config.name.clone();
+    pub fn new(endpoint_id: u32, endpoint_info: EndpointInfo) -> Self {
+        let bits = NameBits::from_endpoint_id(endpoint_id);
         Self {
-            name,
             endpoint_info,
             bits,
         }
     }
+    pub fn endpoint_id(&self) -> u32 {
+        self.bits.endpoint_id()
+    }
+    pub fn device(&self) -> Device {
+        Device {
+            endpoint_id: self.endpoint_id(),
+            device_name: self.endpoint_info.name.clone(),
+            device_type: self.endpoint_info.devtype(),
+        }
+    }
 }
 impl Bitfield for Name {
     fn to_vec(self) -> Vec<u8> {
@@ -49,36 +58,49 @@ impl Bitfield for Name {
         let mut encoded = self.endpoint_info.to_vec();
         data.push(encoded.len() as u8);
         data.append(&mut encoded);
-        let name = self.name.as_bytes();
-        data.push((data.len() + 1).try_into().unwrap());
         data
     }
     fn decode(raw: &mut Cursor<&[u8]>) -> RustdropResult<Self> {
         let mut raw_name: [u8; 15] = [0; 15];
+        if raw.remaining() < raw_name.len() + 1 {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
         raw.copy_to_slice(&mut raw_name);
         let bits = NameBits::from_bytes(raw_name);
-        info!("{:?}", bits);
-        Err(crate::core::RustdropError::InvalidEndpointId())?;
-        let name = todo!();
-        let endpoint_info = todo!();
+        if bits.service().into_bytes() != Service::default().into_bytes() {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        let size = raw.get_u8() as usize;
+        if size > raw.remaining() {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        // Anything after the endpoint info is optional and unused.
+        let endpoint_info = EndpointInfo::decode_raw(&raw.copy_to_bytes(size))?;
         Ok(Self {
             bits,
-            name,
             endpoint_info,
         })
     }
 }
-// pub(super) fn get_name(config: &Config) -> String {
-//     let mut result = BytesMut::new();
-//     result.put_u8(PCP);
-//     result.extend_from_slice(config.endpoint_id.as_bytes());
-//     result.extend_from_slice(&SERVICE_ID);
-//     result.put_u8(0x0);
-//     result.extend_from_slice(&BytesMut::zeroed(6));
-//     let endpoint_info = get_endpoint_info(config);
-//     info!("{:?}", endpoint_info);
-//     result.put_u8(endpoint_info.len().try_into().unwrap());
-//     result.extend_from_slice(&endpoint_info);
-//     result.put_u8((result.len() + 1).try_into().unwrap());
-//     BASE64_URL_SAFE.encode(result)
-// }
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use rand::thread_rng;
+
+    use super::*;
+    use crate::{core::identity::random_endpoint_id, Config};
+
+    #[test]
+    fn test_round_trip() {
+        let config = Config::with_dest(temp_dir());
+        let endpoint_id = random_endpoint_id();
+        let info = EndpointInfo::new(&config, &mut thread_rng());
+        let encoded = Name::new(endpoint_id, info).to_base64();
+        let name = Name::decode_base64(encoded.as_bytes()).unwrap();
+        assert_eq!(name.endpoint_id(), endpoint_id);
+        assert_eq!(name.device().device_name, config.name);
+        assert_eq!(name.device().device_type, config.devtype);
+        assert!(Name::decode_base64(b"My Phone").is_err());
+    }
+}
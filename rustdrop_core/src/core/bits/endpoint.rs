@@ -25,11 +25,15 @@ impl BitField {
             .with_reserved(0)
     }
 }
+// Follows the name, for senders which scanned a QR code to find us.
+const QR_TOKEN_TLV: u8 = 1;
+
 #[derive(Debug, Clone)]
 pub struct EndpointInfo {
     bitfield: BitField,
     reserved: Bytes,
     pub name: String,
+    pub(crate) qr_token: Option<Bytes>,
 }
 impl EndpointInfo {
     pub(crate) fn new<R: Rng>(config: &Config, rng: &mut R) -> Self {
@@ -40,6 +44,16 @@ impl EndpointInfo {
             bitfield,
             reserved,
             name,
+            qr_token: None,
+        }
+    }
+    // Keeps the reserved bytes, so the info stays the same across restarts.
+    pub(crate) fn with_salt(config: &Config, salt: Bytes) -> Self {
+        Self {
+            bitfield: BitField::from_config(config),
+            reserved: salt,
+            name: config.name.clone(),
+            qr_token: None,
         }
     }
     pub fn devtype(&self) -> DeviceType {
@@ -53,34 +67,62 @@ impl Bitfield for EndpointInfo {
         let mut encoded = self.name.as_bytes().to_vec();
         data.push(encoded.len() as u8);
         data.append(&mut encoded);
+        if let Some(token) = self.qr_token {
+            data.extend_from_slice(&[QR_TOKEN_TLV, token.len() as u8]);
+            data.extend_from_slice(&token);
+        }
         data
     }
     #[instrument]
     fn decode(endpoint_id: &mut Cursor<&[u8]>) -> RustdropResult<Self> {
         info!("{:?}", endpoint_id);
-        // if endpoint_id.remaining() < 18 {
-        //     Err(RustdropError::InvalidEndpointId())?;
-        // }
+        if endpoint_id.remaining() < 17 {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
         let raw_bits = endpoint_id.get_u8();
         let reserved = endpoint_id.copy_to_bytes(16);
         let bitfield = BitField::from_bytes([raw_bits]);
         let name = if endpoint_id.has_remaining() {
-            let size = endpoint_id.get_i8() as usize;
+            let size = endpoint_id.get_u8() as usize;
+            if size > endpoint_id.remaining() {
+                Err(RustdropError::InvalidEndpointId())?;
+            }
             let raw_name = endpoint_id.copy_to_bytes(size);
             String::from_utf8(raw_name.to_vec()).map_err(|_| RustdropError::InvalidEndpointId())?
         } else {
             "".into()
         };
+        let mut qr_token = None;
+        while endpoint_id.remaining() >= 2 {
+            let kind = endpoint_id.get_u8();
+            let size = endpoint_id.get_u8() as usize;
+            if size > endpoint_id.remaining() {
+                Err(RustdropError::InvalidEndpointId())?;
+            }
+            let value = endpoint_id.copy_to_bytes(size);
+            if kind == QR_TOKEN_TLV {
+                qr_token = Some(value);
+            }
+        }
         Ok(Self {
             bitfield,
             reserved,
             name,
+            qr_token,
         })
     }
 }
 #[cfg(test)]
 mod tests {
-    use crate::core::bits::{Bitfield, EndpointInfo};
+    use std::env::temp_dir;
+
+    use bytes::Bytes;
+    use rand::thread_rng;
+
+    use crate::{
+        core::bits::{Bitfield, EndpointInfo},
+        Config,
+    };
 
     #[test]
     fn test_win() {
@@ -92,4 +134,16 @@ mod tests {
         let raw = "MnOfGXWt4xYOEvqYHBptzjc";
         EndpointInfo::decode_base64(raw.as_bytes()).unwrap();
     }
+    #[test]
+    fn test_qr_token() {
+        let config = Config::with_dest(temp_dir());
+        let mut info = EndpointInfo::new(&config, &mut thread_rng());
+        info.qr_token = Some(Bytes::from_static(&[7; 16]));
+        let decoded = EndpointInfo::decode_raw(&info.clone().to_vec()).unwrap();
+        assert_eq!(decoded.name, config.name);
+        assert_eq!(decoded.qr_token, info.qr_token);
+        let mut truncated = info.to_vec();
+        truncated.pop();
+        assert!(EndpointInfo::decode_raw(&truncated).is_err());
+    }
 }
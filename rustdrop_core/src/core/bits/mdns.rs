@@ -3,7 +3,7 @@ use std::io::Cursor;
 use bytes::Buf;
 use modular_bitfield::prelude::*;
 
-use crate::{Config, RustdropResult};
+use crate::RustdropResult;
 
 use super::{pcp_version::PcpVersion, service::Service, Bitfield};
 
@@ -17,10 +17,10 @@ pub struct Name {
     reserved: B15,
 }
 impl Name {
-    pub fn from_config(config: &Config) -> Self {
+    pub fn from_endpoint_id(endpoint_id: u32) -> Self {
         Self::new()
             .with_pcp(PcpVersion::default())
-            .with_endpoint_id(config.endpoint_id)
+            .with_endpoint_id(endpoint_id)
             .with_service(Service::default())
             .with_reserved(0x0)
     }
@@ -1,25 +1,34 @@
 use std::io::Cursor;
 
-use bytes::Buf;
+use bytes::{Buf, Bytes};
 
 use super::Bitfield;
+use crate::core::RustdropError;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct UwbAddress {
-    size: u8,
+    address: Bytes,
+}
+impl UwbAddress {
+    pub fn is_empty(&self) -> bool {
+        self.address.is_empty()
+    }
 }
 impl Bitfield for UwbAddress {
     fn to_vec(self) -> Vec<u8> {
-        let res = vec![self.size];
+        let mut res = vec![self.address.len() as u8];
+        res.extend_from_slice(&self.address);
         res
     }
     fn decode(raw: &mut Cursor<&[u8]>) -> crate::RustdropResult<Self> {
-        let size = raw.get_u8();
-        Ok(UwbAddress { size })
-    }
-}
-impl Default for UwbAddress {
-    fn default() -> Self {
-        Self { size: 0 }
+        if !raw.has_remaining() {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        let size = raw.get_u8() as usize;
+        if size > raw.remaining() {
+            Err(RustdropError::InvalidEndpointId())?;
+        }
+        let address = raw.copy_to_bytes(size);
+        Ok(UwbAddress { address })
     }
 }
@@ -0,0 +1,402 @@
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, Write},
+    mem,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use bytes::Bytes;
+use prost::Message;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{info, warn};
+
+use crate::{
+    core::{
+        ukey2::{Crypto, CryptoImpl},
+        util::{get_iv, get_random, iv_from_vec},
+        RustdropError,
+    },
+    protobuf::location::nearby::connections::OfflineFrame,
+    Context, RustdropResult,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+// Where in the protocol a frame was captured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    // Sent in the clear, before the UKEY2 handshake finished
+    Plain,
+    // Offline frames, before being encrypted or after being decrypted
+    Secure,
+    // Sharing frames, carried inside payload transfers
+    Online,
+    // Secure frames as they were put on the wire
+    Encrypted,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Frame(Direction, Layer, Bytes),
+    // Random bytes drawn by the recorded side
+    Random(Bytes),
+    // A secret key generated by the recorded side, DER encoded
+    Key(Bytes),
+    // The IV a secure frame was encrypted with
+    Iv(Bytes),
+}
+// One line of a capture file: `<micros> <kind> <base64>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub at: Duration,
+    pub entry: Entry,
+}
+impl Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, data) = match &self.entry {
+            Entry::Frame(direction, layer, data) => {
+                let direction = match direction {
+                    Direction::Sent => ">",
+                    Direction::Received => "<",
+                };
+                let layer = match layer {
+                    Layer::Plain => "plain",
+                    Layer::Secure => "secure",
+                    Layer::Online => "online",
+                    Layer::Encrypted => "encrypted",
+                };
+                (format!("{}{}", direction, layer), data)
+            }
+            Entry::Random(data) => ("random".into(), data),
+            Entry::Key(data) => ("key".into(), data),
+            Entry::Iv(data) => ("iv".into(), data),
+        };
+        write!(
+            f,
+            "{} {} {}",
+            self.at.as_micros(),
+            kind,
+            BASE64_STANDARD.encode(data)
+        )
+    }
+}
+impl FromStr for Record {
+    type Err = RustdropError;
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || RustdropError::InvalidMessage(format!("Invalid capture line {:?}", line));
+        let mut parts = line.split_whitespace();
+        let (Some(at), Some(kind), data) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let at = Duration::from_micros(at.parse().map_err(|_| invalid())?);
+        let data: Bytes = BASE64_STANDARD
+            .decode(data.unwrap_or_default())
+            .map_err(|_| invalid())?
+            .into();
+        let entry = match kind {
+            "random" => Entry::Random(data),
+            "key" => Entry::Key(data),
+            "iv" => Entry::Iv(data),
+            frame => {
+                let direction = match frame.get(..1) {
+                    Some(">") => Direction::Sent,
+                    Some("<") => Direction::Received,
+                    _ => return Err(invalid()),
+                };
+                let layer = match frame.get(1..) {
+                    Some("plain") => Layer::Plain,
+                    Some("secure") => Layer::Secure,
+                    Some("online") => Layer::Online,
+                    Some("encrypted") => Layer::Encrypted,
+                    _ => return Err(invalid()),
+                };
+                Entry::Frame(direction, layer, data)
+            }
+        };
+        Ok(Self { at, entry })
+    }
+}
+// Everything one side of a session sent, received and drew at random.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub records: Vec<Record>,
+}
+impl Transcript {
+    pub fn load(path: &Path) -> RustdropResult<Self> {
+        Ok(fs::read_to_string(path)?.parse()?)
+    }
+    // The frames the recorded side put on the wire, in order.
+    pub fn sent(&self) -> Vec<Bytes> {
+        self.wire()
+            .filter(|(direction, _)| *direction == Direction::Sent)
+            .map(|(_, data)| data)
+            .collect()
+    }
+    // Secure and online frames are left out, they travel encrypted.
+    pub(crate) fn wire(&self) -> impl Iterator<Item = (Direction, Bytes)> + '_ {
+        self.records
+            .iter()
+            .filter_map(|record| match &record.entry {
+                Entry::Frame(direction, Layer::Plain | Layer::Encrypted, data) => {
+                    Some((*direction, data.clone()))
+                }
+                _ => None,
+            })
+    }
+}
+impl FromStr for Transcript {
+    type Err = RustdropError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let records = s
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(Record::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { records })
+    }
+}
+static SESSIONS: AtomicU32 = AtomicU32::new(0);
+#[derive(Debug, Clone)]
+struct Recorder {
+    file: Arc<Mutex<File>>,
+    start: Instant,
+}
+impl Recorder {
+    fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!(
+            "{}-{}.capture",
+            started.as_millis(),
+            SESSIONS.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = dir.join(name);
+        info!("Capturing session to {}", path.display());
+        Ok(Self {
+            file: Arc::new(Mutex::new(File::create(path)?)),
+            start: Instant::now(),
+        })
+    }
+    fn record(&self, entry: Entry) {
+        let record = Record {
+            at: self.start.elapsed(),
+            entry,
+        };
+        if let Err(e) = writeln!(self.file.lock().unwrap(), "{}", record) {
+            warn!("Unable to capture: {}", e);
+        }
+    }
+}
+#[derive(Debug, Default)]
+struct Draws {
+    entries: VecDeque<Entry>,
+    // The first draw which didn't match the transcript
+    diverged: Option<String>,
+}
+// What a single connection records, and where its randomness comes from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Session {
+    recorder: Option<Recorder>,
+    // Drawn instead of fresh randomness when replaying
+    draws: Option<Arc<Mutex<Draws>>>,
+}
+impl Session {
+    pub fn new(context: &Context) -> Self {
+        let recorder = context.config.capture.as_deref().and_then(|dir| {
+            Recorder::create(dir)
+                .inspect_err(|e| warn!("Unable to capture to {}: {}", dir.display(), e))
+                .ok()
+        });
+        Self {
+            recorder,
+            draws: None,
+        }
+    }
+    pub fn replay(transcript: &Transcript) -> Self {
+        let entries = transcript
+            .records
+            .iter()
+            .map(|record| record.entry.clone())
+            .filter(|entry| matches!(entry, Entry::Random(_) | Entry::Key(_) | Entry::Iv(_)))
+            .collect();
+        let draws = Draws {
+            entries,
+            diverged: None,
+        };
+        Self {
+            recorder: None,
+            draws: Some(Arc::new(Mutex::new(draws))),
+        }
+    }
+    // Why the replay stopped drawing what was recorded, if it did.
+    pub fn diverged(&self) -> Option<String> {
+        self.draws.as_ref()?.lock().unwrap().diverged.clone()
+    }
+    pub fn record(&self, direction: Direction, layer: Layer, data: &[u8]) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Entry::Frame(direction, layer, Bytes::copy_from_slice(data)));
+        }
+    }
+    // The next recorded draw of a kind, each kind is drawn in order but they may interleave
+    // differently. Fresh randomness is used from the first one which doesn't match.
+    fn draw(&self, kind: fn(Bytes) -> Entry, len: Option<usize>) -> Option<Bytes> {
+        let mut draws = self.draws.as_ref()?.lock().unwrap();
+        if draws.diverged.is_some() {
+            return None;
+        }
+        let wanted = mem::discriminant(&kind(Bytes::new()));
+        let found = draws
+            .entries
+            .iter()
+            .position(|entry| mem::discriminant(entry) == wanted)
+            .and_then(|at| draws.entries.remove(at));
+        match found {
+            Some(Entry::Random(data) | Entry::Key(data) | Entry::Iv(data))
+                if len.is_none_or(|len| data.len() == len) =>
+            {
+                Some(data)
+            }
+            found => {
+                let drew = match kind(Bytes::new()) {
+                    Entry::Key(_) => "a key".into(),
+                    Entry::Iv(_) => "an IV".into(),
+                    _ => format!("{} random bytes", len.unwrap_or_default()),
+                };
+                let recorded = match found {
+                    Some(Entry::Random(data) | Entry::Key(data) | Entry::Iv(data)) => {
+                        format!("{} bytes", data.len())
+                    }
+                    _ => "nothing left".into(),
+                };
+                let diverged = format!("Drew {} where the transcript has {}", drew, recorded);
+                warn!("Replay diverged: {}", diverged);
+                draws.diverged = Some(diverged);
+                None
+            }
+        }
+    }
+    pub fn random(&self, len: usize) -> Vec<u8> {
+        let random = match self.draw(Entry::Random, Some(len)) {
+            Some(data) => data.to_vec(),
+            None => get_random(len),
+        };
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Entry::Random(random.clone().into()));
+        }
+        random
+    }
+    pub fn random_id(&self) -> i64 {
+        let random = self.random(8);
+        let mut id = [0; 8];
+        id.copy_from_slice(&random[..8]);
+        i64::from_be_bytes(id)
+    }
+    pub fn iv(&self) -> [u8; 16] {
+        let iv = match self.draw(Entry::Iv, Some(16)) {
+            Some(data) => iv_from_vec(data.to_vec()),
+            None => get_iv(),
+        };
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Entry::Iv(Bytes::copy_from_slice(&iv)));
+        }
+        iv
+    }
+    pub fn secret_key(&self) -> <CryptoImpl as Crypto>::SecretKey {
+        let key = self
+            .draw(Entry::Key, None)
+            .and_then(|raw| CryptoImpl::secret_from_bytes(&raw))
+            .unwrap_or_else(CryptoImpl::genkey);
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Entry::Key(CryptoImpl::secret_to_bytes(&key)));
+        }
+        key
+    }
+    // Records offline frames on their way to be encrypted.
+    pub fn record_sending(
+        &self,
+        send: UnboundedSender<OfflineFrame>,
+        context: &Context,
+    ) -> UnboundedSender<OfflineFrame> {
+        if self.recorder.is_none() {
+            return send;
+        }
+        let (tx, mut rx) = mpsc::unbounded_channel::<OfflineFrame>();
+        let session = self.clone();
+        context.spawn(async move {
+            while let Some(frame) = rx.recv().await {
+                session.record(Direction::Sent, Layer::Secure, &frame.encode_to_vec());
+                if send.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+        tx
+    }
+    // Records offline frames once they are decrypted.
+    pub fn record_receiving(
+        &self,
+        mut recv: UnboundedReceiver<OfflineFrame>,
+        context: &Context,
+    ) -> UnboundedReceiver<OfflineFrame> {
+        if self.recorder.is_none() {
+            return recv;
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        let session = self.clone();
+        context.spawn(async move {
+            while let Some(frame) = recv.recv().await {
+                session.record(Direction::Received, Layer::Secure, &frame.encode_to_vec());
+                if tx.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let transcript = Transcript {
+            records: vec![
+                Record {
+                    at: Duration::from_micros(12),
+                    entry: Entry::Frame(Direction::Sent, Layer::Plain, Bytes::from_static(b"a")),
+                },
+                Record {
+                    at: Duration::from_micros(40),
+                    entry: Entry::Random(Bytes::from_static(&[1, 2, 3])),
+                },
+                Record {
+                    at: Duration::from_millis(3),
+                    entry: Entry::Frame(Direction::Received, Layer::Online, Bytes::new()),
+                },
+            ],
+        };
+        let text: String = transcript
+            .records
+            .iter()
+            .map(|record| format!("{}\n", record))
+            .collect();
+        assert_eq!(text, "12 >plain YQ==\n40 random AQID\n3000 <online \n");
+        assert_eq!(text.parse::<Transcript>().unwrap(), transcript);
+        assert_eq!(transcript.sent(), vec![Bytes::from_static(b"a")]);
+        assert!("12 >wire YQ==".parse::<Record>().is_err());
+    }
+}
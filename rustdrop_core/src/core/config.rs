@@ -1,32 +1,79 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-
-use crate::DeviceType;
+use crate::{DeviceType, IncomingSink, MediumType, NetworkConfig};
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub devtype: DeviceType,
     pub name: String,
     pub dest: PathBuf,
-    pub(crate) endpoint_id: u32,
+    // Where received files go, written into `dest` when unset.
+    pub sink: Option<Arc<dyn IncomingSink>>,
+    // Shares containing a file larger than this are refused without asking.
+    pub max_file_size: Option<u64>,
+    // Shares whose files add up to more than this are refused without asking.
+    pub max_transfer_size: Option<u64>,
+    // Readers sent to several devices are held in memory, larger ones fail the send.
+    pub max_buffered_reader: Option<u64>,
+    // Bluetooth devices which have not advertised for this long are considered gone.
+    pub discovery_expiry: Duration,
+    // Try IPv6 addresses of a device before its IPv4 ones.
+    pub prefer_ipv6: bool,
+    // Interfaces and ports used by the Wi-Fi LAN medium.
+    pub network: NetworkConfig,
+    // Devices to connect to directly while discovering, for networks which block multicast.
+    pub peers: Vec<SocketAddr>,
+    // Built in mediums to start, the ones which fail to start are skipped.
+    pub mediums: Vec<MediumType>,
+    // Writes a transcript of every session to a file in this directory, for debugging.
+    pub capture: Option<PathBuf>,
+    // Keeps the identity in this file for peers to recognise, `None` for a new one every start.
+    pub state: Option<PathBuf>,
+    // Advertises a new endpoint id and endpoint info once the current ones are this old.
+    pub rotate_identity: Option<Duration>,
 }
-impl Default for Config {
-    fn default() -> Self {
-        let rng = thread_rng();
-        let endpoint: [u8; 4] = rng
-            .sample_iter(Alphanumeric)
-            .take(4)
-            .collect::<Vec<u8>>()
-            .try_into()
-            .unwrap();
+impl Config {
+    // Without a `state` file, unlike `Config::default`, so a new identity on every start.
+    pub fn with_dest(dest: PathBuf) -> Self {
         Config {
             devtype: DeviceType::Laptop,
             name: hostname::get().unwrap().to_str().unwrap().into(),
-            dest: dirs::download_dir()
+            dest,
+            sink: None,
+            max_file_size: None,
+            max_transfer_size: None,
+            max_buffered_reader: Some(64 * 1024 * 1024),
+            discovery_expiry: Duration::from_secs(60),
+            prefer_ipv6: false,
+            network: NetworkConfig::default(),
+            peers: Vec::new(),
+            mediums: default_mediums(),
+            capture: None,
+            state: None,
+            rotate_identity: None,
+        }
+    }
+    // Where an application keeps its identity, for `state`. Apps which run at once need their own.
+    pub fn state_file(app: &str) -> Option<PathBuf> {
+        dirs::state_dir().map(|dir| dir.join(app).join("identity"))
+    }
+}
+// Every medium compiled in.
+fn default_mediums() -> Vec<MediumType> {
+    let mut mediums = vec![MediumType::Wlan];
+    if cfg!(feature = "bluetooth") {
+        mediums.push(MediumType::Bluetooth);
+    }
+    mediums
+}
+impl Default for Config {
+    fn default() -> Self {
+        let mut config = Self::with_dest(
+            dirs::download_dir()
                 .expect("Set an XDG download directory, see isue #3")
                 .join("nearby"),
-            endpoint_id: u32::from_be_bytes(endpoint),
-        }
+        );
+        config.state = Self::state_file("rustdrop");
+        config
     }
 }
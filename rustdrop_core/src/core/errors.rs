@@ -1,8 +1,10 @@
+use std::path::PathBuf;
+
 use openssl::error::ErrorStack;
 use thiserror::Error;
 use tokio::io;
 
-use crate::protobuf::securegcm::Ukey2Alert;
+use crate::{protobuf::securegcm::Ukey2Alert, RejectionReason};
 #[derive(Error, Debug)]
 pub enum RustdropError {
     #[error("Encryption Error")]
@@ -19,4 +21,14 @@ pub enum RustdropError {
     Connection(),
     #[error("Ukey Error {0:?}")]
     UkeyError(Ukey2Alert),
+    #[error("Not a regular file: {0:?}")]
+    InvalidFile(PathBuf),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Invalid QR code: {0}")]
+    InvalidQrCode(String),
+    #[error("Replay diverged from the transcript: {0}")]
+    ReplayDiverged(String),
+    #[error("Transfer aborted: {0}")]
+    LimitExceeded(RejectionReason),
 }
@@ -1,12 +1,13 @@
 use crate::{
     core::{
-        bits::{Bitfield, EndpointInfo},
+        bits::{Bitfield, DeviceType, EndpointInfo},
         protocol::get_offline_frame,
-        util::{get_osinfo, get_random},
+        util::get_osinfo,
+        Session,
     },
     protobuf::location::nearby::connections::{
-        connection_response_frame::ResponseStatus, v1_frame::FrameType, ConnectionRequestFrame,
-        ConnectionResponseFrame, KeepAliveFrame, OfflineFrame, V1Frame,
+        connection_response_frame::ResponseStatus, os_info::OsType, v1_frame::FrameType,
+        ConnectionRequestFrame, ConnectionResponseFrame, KeepAliveFrame, OfflineFrame, V1Frame,
     },
 };
 
@@ -22,11 +23,11 @@ pub(crate) fn keep_alive(seq: u32) -> OfflineFrame {
     };
     get_offline_frame(v1)
 }
-pub fn get_conn_response() -> OfflineFrame {
+pub(crate) fn get_conn_response(session: &Session) -> OfflineFrame {
     let conn = ConnectionResponseFrame {
         response: Some(ResponseStatus::Accept.into()),
         os_info: Some(get_osinfo()),
-        handshake_data: Some(get_random(10)),
+        handshake_data: Some(session.random(10)),
         nearby_connections_version: Some(1),
         ..Default::default()
     };
@@ -37,8 +38,9 @@ pub fn get_conn_response() -> OfflineFrame {
     };
     get_offline_frame(v1)
 }
-pub(crate) fn get_con_request(endpoint_info: EndpointInfo) -> OfflineFrame {
+pub(crate) fn get_con_request(endpoint_id: u32, endpoint_info: EndpointInfo) -> OfflineFrame {
     let init = ConnectionRequestFrame {
+        endpoint_id: Some(String::from_utf8_lossy(&endpoint_id.to_be_bytes()).into()),
         endpoint_name: Some(endpoint_info.name.clone()),
         endpoint_info: Some(endpoint_info.to_vec()),
         ..Default::default()
@@ -50,3 +52,11 @@ pub(crate) fn get_con_request(endpoint_info: EndpointInfo) -> OfflineFrame {
     };
     get_offline_frame(v1)
 }
+// Receivers do not send their endpoint info, so the OS is all there is to go on.
+pub(crate) fn guess_device_type(response: &ConnectionResponseFrame) -> DeviceType {
+    match response.os_info.as_ref().map(|info| info.r#type()) {
+        Some(OsType::Android) => DeviceType::Phone,
+        Some(OsType::Windows | OsType::Linux | OsType::ChromeOs) => DeviceType::Laptop,
+        _ => DeviceType::Unknown,
+    }
+}
@@ -4,21 +4,20 @@ use crate::{
         connection_response_frame::Status, v1_frame::FrameType, ConnectionResponseFrame, Frame,
         V1Frame,
     },
+    DeclineReason,
 };
-pub(crate) fn process_transfer_response(frame: Frame) -> bool {
+pub(crate) fn process_transfer_response(frame: Frame) -> Result<(), DeclineReason> {
     let resp = frame.v1.unwrap().connection_response.unwrap();
     match resp.status() {
-        Status::Accept => true,
-        _ => false,
+        Status::Accept => Ok(()),
+        Status::NotEnoughSpace => Err(DeclineReason::NotEnoughSpace),
+        Status::UnsupportedAttachmentType => Err(DeclineReason::Unsupported),
+        Status::TimedOut => Err(DeclineReason::TimedOut),
+        Status::Reject | Status::Unknown => Err(DeclineReason::Declined),
     }
 }
 
-pub(crate) fn transfer_response(accept: bool) -> Frame {
-    let status = if accept {
-        Status::Accept
-    } else {
-        Status::Reject
-    };
+pub(crate) fn transfer_response(status: Status) -> Frame {
     // TODO: attachment_details
     let resp = ConnectionResponseFrame {
         status: Some(status.into()),
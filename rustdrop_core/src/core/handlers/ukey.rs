@@ -3,7 +3,7 @@ use prost::Message;
 use crate::{
     core::{
         ukey2::{get_generic_pubkey, Crypto, CryptoImpl},
-        util::get_random,
+        Session,
     },
     protobuf::securegcm::{
         ukey2_client_init::CipherCommitment, ukey2_message::Type, Ukey2ClientFinished,
@@ -40,11 +40,12 @@ use crate::{
 //     Ok(client_pub_key)
 // }
 fn get_ukey_finish(
+    session: &Session,
     cipher: Ukey2HandshakeCipher,
 ) -> (Ukey2ClientFinished, <CryptoImpl as Crypto>::SecretKey) {
     assert_eq!(cipher, Ukey2HandshakeCipher::P256Sha512);
     let mut res = Ukey2ClientFinished::default();
-    let key = CryptoImpl::genkey();
+    let key = session.secret_key();
     res.public_key = Some(get_generic_pubkey::<CryptoImpl>(&key).encode_to_vec());
     (res, key)
 }
@@ -56,13 +57,15 @@ fn get_commitment<C: Crypto>(cipher: Ukey2HandshakeCipher, frame: &[u8]) -> Ciph
     commitment.commitment = Some(sha.to_vec());
     commitment
 }
-pub fn get_ukey_init_finish() -> (
+pub(crate) fn get_ukey_init_finish(
+    session: &Session,
+) -> (
     Ukey2ClientInit,
     Ukey2Message,
     <CryptoImpl as Crypto>::SecretKey,
 ) {
     let cipher: Ukey2HandshakeCipher = Ukey2HandshakeCipher::P256Sha512;
-    let (finish, key) = get_ukey_finish(cipher);
+    let (finish, key) = get_ukey_finish(session, cipher);
     let frame = Ukey2Message {
         message_data: Some(finish.encode_to_vec()),
         message_type: Some(Type::ClientFinish.into()),
@@ -70,7 +73,7 @@ pub fn get_ukey_init_finish() -> (
     let cipher_commit = get_commitment::<CryptoImpl>(cipher, &frame.encode_to_vec());
     let init = Ukey2ClientInit {
         version: Some(1),
-        random: Some(get_random(32)),
+        random: Some(session.random(32)),
         cipher_commitments: vec![cipher_commit],
         next_protocol: Some(cipher.as_str_name().to_string()),
     };
@@ -0,0 +1,215 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use bytes::Bytes;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use tracing::info;
+
+use super::{
+    ukey2::{Crypto, CryptoImpl},
+    RustdropError,
+};
+use crate::RustdropResult;
+
+const SALT_LEN: usize = 16;
+
+// What peers recognise this device by, kept in a file across restarts.
+#[derive(Debug, Clone)]
+pub(crate) struct Identity {
+    pub endpoint_id: u32,
+    // The reserved bytes of the endpoint info
+    pub salt: Bytes,
+    // Long-term key, QR codes have keys of their own
+    pub key: <CryptoImpl as Crypto>::SecretKey,
+    // When the endpoint id and salt were picked
+    pub since: SystemTime,
+}
+pub(crate) fn random_endpoint_id() -> u32 {
+    let endpoint: [u8; 4] = thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(4)
+        .collect::<Vec<u8>>()
+        .try_into()
+        .unwrap();
+    u32::from_be_bytes(endpoint)
+}
+fn random_salt() -> Bytes {
+    thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(SALT_LEN)
+        .collect()
+}
+impl Identity {
+    pub fn generate() -> Self {
+        Self {
+            endpoint_id: random_endpoint_id(),
+            salt: random_salt(),
+            key: CryptoImpl::genkey(),
+            since: SystemTime::now(),
+        }
+    }
+    // Picks what is advertised anew, keeping the key.
+    pub fn rotate(&mut self) {
+        self.endpoint_id = random_endpoint_id();
+        self.salt = random_salt();
+        self.since = SystemTime::now();
+    }
+    // Creates the file when missing, and rotates the identity once it is older than `rotation`.
+    pub fn load_or_create(path: &Path, rotation: Option<Duration>) -> RustdropResult<Self> {
+        let mut identity = match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("Creating an identity in {}", path.display());
+                let identity = Self::generate();
+                identity.save(path)?;
+                return Ok(identity);
+            }
+            Err(e) => Err(e)?,
+        };
+        let age = identity.since.elapsed().unwrap_or_default();
+        if rotation.is_some_and(|rotation| age >= rotation) {
+            info!("Rotating the identity in {}", path.display());
+            identity.rotate();
+            identity.save(path)?;
+        }
+        Ok(identity)
+    }
+    // Only readable by the user, as it holds the key.
+    pub fn save(&self, path: &Path) -> RustdropResult<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(self.to_text().as_bytes())?;
+        Ok(())
+    }
+    // One `<name> <value>` line for each field.
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        let endpoint_id = self.endpoint_id.to_be_bytes();
+        let _ = writeln!(
+            text,
+            "endpoint_id {}",
+            String::from_utf8_lossy(&endpoint_id)
+        );
+        let _ = writeln!(text, "salt {}", BASE64_STANDARD.encode(&self.salt));
+        let key = CryptoImpl::secret_to_bytes(&self.key);
+        let _ = writeln!(text, "key {}", BASE64_STANDARD.encode(key));
+        let since = self.since.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let _ = writeln!(text, "since {}", since.as_secs());
+        text
+    }
+    fn parse(text: &str) -> RustdropResult<Self> {
+        let invalid =
+            |line: &str| RustdropError::InvalidMessage(format!("Invalid identity {:?}", line));
+        let (mut endpoint_id, mut salt, mut key, mut since) = (None, None, None, None);
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (name, value) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            match name {
+                "endpoint_id" => {
+                    let raw: [u8; 4] = value.as_bytes().try_into().map_err(|_| invalid(line))?;
+                    endpoint_id = Some(u32::from_be_bytes(raw));
+                }
+                "salt" => {
+                    let raw = BASE64_STANDARD.decode(value).map_err(|_| invalid(line))?;
+                    if raw.len() != SALT_LEN {
+                        Err(invalid(line))?;
+                    }
+                    salt = Some(raw.into());
+                }
+                "key" => {
+                    let raw = BASE64_STANDARD.decode(value).map_err(|_| invalid(line))?;
+                    key = Some(CryptoImpl::secret_from_bytes(&raw).ok_or_else(|| invalid(line))?);
+                }
+                "since" => {
+                    let secs = value.parse().map_err(|_| invalid(line))?;
+                    since = Some(UNIX_EPOCH + Duration::from_secs(secs));
+                }
+                _ => Err(invalid(line))?,
+            }
+        }
+        match (endpoint_id, salt, key, since) {
+            (Some(endpoint_id), Some(salt), Some(key), Some(since)) => Ok(Self {
+                endpoint_id,
+                salt,
+                key,
+                since,
+            }),
+            _ => Err(invalid(text))?,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{core::bits::Bitfield, test_util::temp_dir, Config, Context};
+
+    #[tokio::test]
+    async fn test_load_or_create() {
+        let path = temp_dir("rustdrop_identity").await.join("identity");
+        let created = Identity::load_or_create(&path, None).unwrap();
+        let loaded = Identity::load_or_create(&path, None).unwrap();
+        assert_eq!(loaded.endpoint_id, created.endpoint_id);
+        assert_eq!(loaded.salt, created.salt);
+        let key = |identity: &Identity| CryptoImpl::secret_to_bytes(&identity.key);
+        assert_eq!(key(&loaded), key(&created));
+
+        let rotated = Identity::load_or_create(&path, Some(Duration::ZERO)).unwrap();
+        assert_ne!(rotated.salt, created.salt);
+        assert_eq!(key(&rotated), key(&created));
+        let loaded = Identity::load_or_create(&path, Some(Duration::from_secs(3600))).unwrap();
+        assert_eq!(loaded.endpoint_id, rotated.endpoint_id);
+        assert!(Identity::parse("endpoint_id Ab12\n").is_err());
+        let unknown = format!("{}other 1\n", loaded.to_text());
+        assert!(Identity::parse(&unknown).is_err());
+    }
+    #[tokio::test]
+    async fn test_context() {
+        let dir = temp_dir("rustdrop_identity_context").await;
+        let mut config = Config::with_dest(dir.clone());
+        config.state = Some(dir.join("identity"));
+        let first = Context::new(config.clone());
+        let second = Context::new(config);
+        assert_eq!(first.endpoint_id(), second.endpoint_id());
+        let info = |context: &Context| context.endpoint_info().to_vec();
+        assert_eq!(info(&first), info(&second));
+        let url = |context: &Context| context.qr_codes.generate().url();
+        assert_ne!(url(&first), url(&second));
+    }
+    #[tokio::test]
+    async fn test_rotate_periodically() {
+        let dir = temp_dir("rustdrop_identity_rotate").await;
+        let path = dir.join("identity");
+        let mut config = Config::with_dest(dir);
+        config.state = Some(path.clone());
+        config.rotate_identity = Some(Duration::from_millis(100));
+        let context = Context::new(config);
+        let first = context.endpoint_info().to_vec();
+        let created = Identity::load_or_create(&path, None).unwrap();
+        let mut changes = context.watch_advertised();
+        context.rotate_periodically();
+        let rotated = context.advertised_change(&mut changes);
+        let rotated = timeout(Duration::from_secs(5), rotated).await.unwrap();
+        let rotated = rotated.unwrap();
+        assert_ne!(rotated.info.to_vec(), first);
+        let kept = Identity::load_or_create(&path, None).unwrap();
+        assert_eq!(kept.endpoint_id, rotated.endpoint_id);
+        let key = |identity: &Identity| CryptoImpl::secret_to_bytes(&identity.key);
+        assert_eq!(key(&kept), key(&created));
+        context.shutdown().await;
+    }
+}
@@ -0,0 +1,261 @@
+use std::{fmt::Write, path::PathBuf, str::FromStr};
+
+use prost::Message;
+use rand::thread_rng;
+
+use crate::{
+    core::{
+        bits::{
+            Bitfield, BleAdvertisement, BleFastName, BleHeader, BleName, BluetoothName,
+            EndpointInfo, MdnsName,
+        },
+        capture::{Entry, Layer, Record},
+        protocol::Device,
+        RustdropError,
+    },
+    protobuf::{
+        location::nearby::connections::OfflineFrame,
+        nearby::sharing::service::Frame,
+        securegcm::{
+            ukey2_message::Type, Ukey2Alert, Ukey2ClientFinished, Ukey2ClientInit, Ukey2Message,
+            Ukey2ServerInit,
+        },
+        securemessage::SecureMessage,
+    },
+    Config, RustdropResult,
+};
+
+// Devices sharing files list it in the bloom filter of their BLE header.
+const SERVICE_ID: &str = "NearbySharing";
+
+// Something devices exchange, which can be decoded on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    // The instance name of the mDNS service
+    Mdns,
+    // The `n` TXT record, also carried inside the other names
+    EndpointInfo,
+    // BLE service data
+    Ble,
+    BleFast,
+    // The newer BLE advertisement wrapping either of the above, and the header served instead
+    // when the advertisements are read over GATT
+    BleAdvertisement,
+    BleHeader,
+    Bluetooth,
+    // Frames sent before the connection is encrypted, and the ones inside it
+    Offline,
+    Ukey2,
+    // Frames of the sharing protocol, carried inside payload transfers
+    Sharing,
+}
+impl Kind {
+    pub const ALL: [Kind; 10] = [
+        Kind::Mdns,
+        Kind::EndpointInfo,
+        Kind::Ble,
+        Kind::BleFast,
+        Kind::BleAdvertisement,
+        Kind::BleHeader,
+        Kind::Bluetooth,
+        Kind::Offline,
+        Kind::Ukey2,
+        Kind::Sharing,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Mdns => "mdns",
+            Kind::EndpointInfo => "endpoint",
+            Kind::Ble => "ble",
+            Kind::BleFast => "ble-fast",
+            Kind::BleAdvertisement => "ble-v2",
+            Kind::BleHeader => "ble-v2-header",
+            Kind::Bluetooth => "bluetooth",
+            Kind::Offline => "offline",
+            Kind::Ukey2 => "ukey2",
+            Kind::Sharing => "sharing",
+        }
+    }
+}
+impl FromStr for Kind {
+    type Err = RustdropError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Kind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| RustdropError::InvalidMessage(format!("Unknown kind {}", s)))
+    }
+}
+// Pretty prints what the bytes contain.
+pub fn decode(kind: Kind, raw: &[u8]) -> RustdropResult<String> {
+    Ok(match kind {
+        Kind::Mdns => {
+            let name = MdnsName::decode_raw(raw)?;
+            format!(
+                "endpoint id: {}\n{:#?}",
+                endpoint_id(name.endpoint_id()),
+                name
+            )
+        }
+        Kind::EndpointInfo => {
+            let info = EndpointInfo::decode_raw(raw)?;
+            format!(
+                "name: {}\ndevice type: {:?}\n{:#?}",
+                info.name,
+                info.devtype(),
+                info
+            )
+        }
+        Kind::Ble => {
+            let name = BleName::decode_raw(raw)?;
+            format!("{}\n{:#?}", device(&name.device()), name)
+        }
+        Kind::BleFast => {
+            let name = BleFastName::decode_raw(raw)?;
+            format!("{}\n{:#?}", device(&name.device()), name)
+        }
+        Kind::BleAdvertisement => {
+            let adv = BleAdvertisement::decode_raw(raw)?;
+            let (found, mac) = adv.device()?;
+            let mac = mac.map_or("none".into(), |mac| hex(&mac));
+            format!("{}\nmac: {}\n{:#?}", device(&found), mac, adv)
+        }
+        Kind::BleHeader => {
+            let header = BleHeader::decode_raw(raw)?;
+            format!(
+                "slots: {}\npsm: {}\nsharing: {}\n{:#?}",
+                header.slots(),
+                header.psm,
+                header.bloom_filter.contains(SERVICE_ID),
+                header
+            )
+        }
+        Kind::Bluetooth => {
+            let name = BluetoothName::decode_raw(raw)?;
+            format!("{}\n{:#?}", device(&name.device()), name)
+        }
+        Kind::Offline => format!("{:#?}", OfflineFrame::decode(raw)?),
+        Kind::Ukey2 => decode_ukey2(raw)?,
+        Kind::Sharing => format!("{:#?}", Frame::decode(raw)?),
+    })
+}
+// The bytes another device would advertise, for crafting test inputs.
+pub fn encode(kind: Kind, device: &Device) -> RustdropResult<Vec<u8>> {
+    let mut config = Config::with_dest(PathBuf::new());
+    config.name = device.device_name.clone();
+    config.devtype = device.device_type;
+    let info = EndpointInfo::new(&config, &mut thread_rng());
+    Ok(match kind {
+        Kind::Mdns => MdnsName::from_endpoint_id(device.endpoint_id).to_vec(),
+        Kind::EndpointInfo => info.to_vec(),
+        Kind::Ble => BleName::new(device.endpoint_id, None, info).to_vec(),
+        Kind::BleFast => BleFastName::new(device.endpoint_id, info).to_vec(),
+        Kind::BleAdvertisement => BleAdvertisement::fast(device.endpoint_id, info).to_vec(),
+        // A header for a single slot holding the regular advertisement
+        Kind::BleHeader => {
+            let adv = BleAdvertisement::regular(device.endpoint_id, None, info).to_vec();
+            BleHeader::new(&[SERVICE_ID], &[adv], 0).to_vec()
+        }
+        Kind::Bluetooth => BluetoothName::new(device.endpoint_id, info).to_vec(),
+        Kind::Offline | Kind::Ukey2 | Kind::Sharing => Err(RustdropError::InvalidMessage(
+            format!("{} frames can't be encoded", kind.name()),
+        ))?,
+    })
+}
+// Pretty prints a line of a capture file.
+pub fn describe(record: &Record) -> String {
+    let (kind, decoded) = match &record.entry {
+        Entry::Frame(direction, layer, data) => {
+            let decoded = match layer {
+                Layer::Plain if is_ukey2(data) => decode(Kind::Ukey2, data),
+                Layer::Plain | Layer::Secure => decode(Kind::Offline, data),
+                Layer::Online => decode(Kind::Sharing, data),
+                Layer::Encrypted => SecureMessage::decode(data.clone())
+                    .map(|message| format!("{:#?}", message))
+                    .map_err(Into::into),
+            };
+            let decoded = decoded.unwrap_or_else(|e| format!("{} ({})", e, hex(data)));
+            (format!("{:?} {:?}", direction, layer), decoded)
+        }
+        Entry::Random(data) => ("Random".into(), hex(data)),
+        Entry::Key(data) => ("Key".into(), hex(data)),
+        Entry::Iv(data) => ("Iv".into(), hex(data)),
+    };
+    format!("[{:?}] {}\n{}", record.at, kind, decoded)
+}
+// Endpoint ids are four alphanumeric characters.
+pub fn endpoint_id(id: u32) -> String {
+    String::from_utf8_lossy(&id.to_be_bytes()).into_owned()
+}
+fn device(device: &Device) -> String {
+    format!(
+        "endpoint id: {}\nname: {}\ndevice type: {:?}",
+        endpoint_id(device.endpoint_id),
+        device.device_name,
+        device.device_type
+    )
+}
+// Handshake messages look like offline frames on the wire, their type tells them apart.
+fn is_ukey2(raw: &[u8]) -> bool {
+    Ukey2Message::decode(raw).is_ok_and(|message| {
+        matches!(
+            message.message_type(),
+            Type::ClientInit | Type::ServerInit | Type::ClientFinish
+        )
+    })
+}
+fn decode_ukey2(raw: &[u8]) -> RustdropResult<String> {
+    let message = Ukey2Message::decode(raw)?;
+    let data = message.message_data();
+    let inner = match message.message_type() {
+        Type::Alert => format!("{:#?}", Ukey2Alert::decode(data)?),
+        Type::ClientInit => format!("{:#?}", Ukey2ClientInit::decode(data)?),
+        Type::ServerInit => format!("{:#?}", Ukey2ServerInit::decode(data)?),
+        Type::ClientFinish => format!("{:#?}", Ukey2ClientFinished::decode(data)?),
+        Type::UnknownDoNotUse => hex(data),
+    };
+    Ok(format!("{:?}\n{}", message.message_type(), inner))
+}
+fn hex(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{:02x}", byte);
+        out
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+
+    #[test]
+    fn test_round_trip() {
+        let device = Device {
+            endpoint_id: u32::from_be_bytes(*b"Ab12"),
+            device_name: "Test phone".into(),
+            device_type: DeviceType::Phone,
+        };
+        for kind in [
+            Kind::Ble,
+            Kind::BleFast,
+            Kind::BleAdvertisement,
+            Kind::Bluetooth,
+        ] {
+            let decoded = decode(kind, &encode(kind, &device).unwrap()).unwrap();
+            assert!(decoded.starts_with("endpoint id: Ab12\n"), "{}", decoded);
+            assert!(decoded.contains("device type: Phone"), "{}", decoded);
+        }
+        let decoded = decode(Kind::Mdns, &encode(Kind::Mdns, &device).unwrap()).unwrap();
+        assert!(decoded.starts_with("endpoint id: Ab12\n"));
+        let decoded = decode(
+            Kind::EndpointInfo,
+            &encode(Kind::EndpointInfo, &device).unwrap(),
+        );
+        assert!(decoded.unwrap().starts_with("name: Test phone\n"));
+        let decoded = decode(Kind::BleHeader, &encode(Kind::BleHeader, &device).unwrap());
+        assert!(decoded
+            .unwrap()
+            .starts_with("slots: 1\npsm: 0\nsharing: true\n"));
+        assert!(encode(Kind::Offline, &device).is_err());
+        assert_eq!("ble-fast".parse::<Kind>().unwrap(), Kind::BleFast);
+    }
+}
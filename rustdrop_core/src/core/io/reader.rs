@@ -1,8 +1,6 @@
 use bytes::{Bytes, BytesMut};
 use flume::{Receiver, Sender};
-use prost::Message;
 use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
-use tracing::{debug, trace};
 
 use crate::{core::errors::RustdropError, Context, RustdropResult};
 #[derive(Debug)]
@@ -57,15 +55,6 @@ impl ReaderRecv {
             .await
             .map_err(|_| RustdropError::StreamClosed().into())
     }
-    pub async fn next_message<T: Message + Default>(&self) -> RustdropResult<T> {
-        let raw = self.next().await?;
-        trace!("Raw message {:?}", raw);
-        let res = T::decode(raw)?;
-        if res.encoded_len() < 1000 {
-            debug!("Recieved {:?}", res);
-        }
-        Ok(res)
-    }
 }
 impl From<Receiver<Bytes>> for ReaderRecv {
     fn from(value: Receiver<Bytes>) -> Self {
@@ -0,0 +1,211 @@
+use std::{fmt::Display, io, path::Path};
+
+use crate::{
+    protobuf::nearby::sharing::service::connection_response_frame::Status, Config, Incoming,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    NotEnoughSpace { required: u64, available: u64 },
+    FileTooLarge { name: String, size: u64, limit: u64 },
+    TransferTooLarge { size: u64, limit: u64 },
+}
+impl RejectionReason {
+    pub(crate) fn status(&self) -> Status {
+        match self {
+            RejectionReason::NotEnoughSpace { .. } => Status::NotEnoughSpace,
+            RejectionReason::FileTooLarge { .. } | RejectionReason::TransferTooLarge { .. } => {
+                Status::UnsupportedAttachmentType
+            }
+        }
+    }
+}
+impl Display for RejectionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectionReason::NotEnoughSpace {
+                required,
+                available,
+            } => write!(
+                f,
+                "Not enough space: {} bytes needed, {} bytes available",
+                required, available
+            ),
+            RejectionReason::FileTooLarge { name, size, limit } => write!(
+                f,
+                "{} is {} bytes, larger than the {} byte limit",
+                name, size, limit
+            ),
+            RejectionReason::TransferTooLarge { size, limit } => write!(
+                f,
+                "Transfer is {} bytes, larger than the {} byte limit",
+                size, limit
+            ),
+        }
+    }
+}
+// Checked again against what is written, as streams declare no size and senders can lie.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Limits {
+    max_file_size: Option<u64>,
+    max_transfer_size: Option<u64>,
+    // Measured once the share is accepted
+    available: Option<u64>,
+}
+impl Limits {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_file_size: config.max_file_size,
+            max_transfer_size: config.max_transfer_size,
+            available: available_space(&config.dest).ok(),
+        }
+    }
+    // The bytes written to the file so far.
+    pub fn check_file(&self, name: &str, written: u64) -> Result<(), RejectionReason> {
+        match self.max_file_size {
+            Some(limit) if written > limit => Err(RejectionReason::FileTooLarge {
+                name: name.into(),
+                size: written,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+    // The bytes written for the whole share so far.
+    pub fn check_total(&self, total: u64) -> Result<(), RejectionReason> {
+        if let Some(limit) = self.max_transfer_size {
+            if total > limit {
+                return Err(RejectionReason::TransferTooLarge { size: total, limit });
+            }
+        }
+        if let Some(available) = self.available {
+            if total > available {
+                return Err(RejectionReason::NotEnoughSpace {
+                    required: total,
+                    available,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+// The destination is created lazily, so measure the closest directory that exists.
+fn available_space(dest: &Path) -> io::Result<u64> {
+    let mut path = dest;
+    while !path.exists() {
+        match path.parent() {
+            Some(parent) => path = parent,
+            None => break,
+        }
+    }
+    fs4::available_space(path)
+}
+pub(crate) fn check_limits(config: &Config, incoming: &Incoming) -> Result<(), RejectionReason> {
+    let mut total: u64 = 0;
+    for file in incoming.files() {
+        let size = file.size.max(0) as u64;
+        if let Some(limit) = config.max_file_size {
+            if size > limit {
+                return Err(RejectionReason::FileTooLarge {
+                    name: file.name.clone(),
+                    size,
+                    limit,
+                });
+            }
+        }
+        total = total.saturating_add(size);
+    }
+    if let Some(limit) = config.max_transfer_size {
+        if total > limit {
+            return Err(RejectionReason::TransferTooLarge { size: total, limit });
+        }
+    }
+    if total > 0 {
+        if let Ok(available) = available_space(&config.dest) {
+            if total > available {
+                return Err(RejectionReason::NotEnoughSpace {
+                    required: total,
+                    available,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protobuf::nearby::sharing::service::{FileMetadata, IntroductionFrame},
+        test_util::temp_dir,
+    };
+
+    fn incoming(sizes: &[i64]) -> Incoming {
+        let file_metadata = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| FileMetadata {
+                name: Some(format!("file{}", i)),
+                payload_id: Some(i as i64),
+                size: Some(*size),
+                ..Default::default()
+            })
+            .collect();
+        Incoming::from(IntroductionFrame {
+            file_metadata,
+            ..Default::default()
+        })
+    }
+    #[tokio::test]
+    async fn test_caps() {
+        let mut config = Config::with_dest(temp_dir("rustdrop_limits").await);
+        assert_eq!(check_limits(&config, &incoming(&[10, 20])), Ok(()));
+        config.max_file_size = Some(15);
+        assert!(matches!(
+            check_limits(&config, &incoming(&[10, 20])),
+            Err(RejectionReason::FileTooLarge { size: 20, .. })
+        ));
+        config.max_file_size = None;
+        config.max_transfer_size = Some(25);
+        let reason = check_limits(&config, &incoming(&[10, 20])).unwrap_err();
+        assert_eq!(
+            reason,
+            RejectionReason::TransferTooLarge {
+                size: 30,
+                limit: 25
+            }
+        );
+        assert_eq!(reason.status(), Status::UnsupportedAttachmentType);
+    }
+    #[tokio::test]
+    async fn test_not_enough_space() {
+        let dest = temp_dir("rustdrop_limits_space").await.join("nested");
+        let config = Config::with_dest(dest);
+        let reason = check_limits(&config, &incoming(&[i64::MAX])).unwrap_err();
+        assert!(matches!(reason, RejectionReason::NotEnoughSpace { .. }));
+        assert_eq!(reason.status(), Status::NotEnoughSpace);
+    }
+    #[tokio::test]
+    async fn test_written() {
+        let dir = temp_dir("rustdrop_limits_written").await;
+        let mut config = Config::with_dest(dir.clone());
+        config.max_file_size = Some(15);
+        config.max_transfer_size = Some(25);
+        let limits = Limits::new(&config);
+        assert_eq!(limits.check_file("file0", 15), Ok(()));
+        assert!(matches!(
+            limits.check_file("file0", 16),
+            Err(RejectionReason::FileTooLarge { size: 16, .. })
+        ));
+        assert_eq!(limits.check_total(25), Ok(()));
+        assert!(matches!(
+            limits.check_total(26),
+            Err(RejectionReason::TransferTooLarge { size: 26, .. })
+        ));
+        let limits = Limits::new(&Config::with_dest(dir));
+        assert!(matches!(
+            limits.check_total(u64::MAX),
+            Err(RejectionReason::NotEnoughSpace { .. })
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+use std::{net::IpAddr, ops::RangeInclusive, str::FromStr};
+
+use crate::core::RustdropError;
+
+// Name prefixes of VPN tunnels, container bridges and virtual machine networks.
+const VIRTUAL_PREFIXES: [&str; 16] = [
+    "tun",
+    "tap",
+    "wg",
+    "ppp",
+    "utun",
+    "ipsec",
+    "tailscale",
+    "zt",
+    "docker",
+    "br-",
+    "veth",
+    "virbr",
+    "vmnet",
+    "vboxnet",
+    "lxcbr",
+    "cni",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceMatch {
+    Name(String),
+    Subnet { addr: IpAddr, prefix: u8 },
+}
+impl InterfaceMatch {
+    pub fn matches(&self, name: &str, ip: IpAddr) -> bool {
+        match self {
+            InterfaceMatch::Name(expected) => expected == name,
+            InterfaceMatch::Subnet { addr, prefix } => match (addr, ip) {
+                (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                    let mask = u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0);
+                    u32::from(*addr) & mask == u32::from(ip) & mask
+                }
+                (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                    let mask = u128::MAX.checked_shl(128 - u32::from(*prefix)).unwrap_or(0);
+                    u128::from(*addr) & mask == u128::from(ip) & mask
+                }
+                _ => false,
+            },
+        }
+    }
+}
+// Either an interface name like "wlan0" or a subnet like "192.168.1.0/24".
+impl FromStr for InterfaceMatch {
+    type Err = RustdropError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((addr, prefix)) = s.split_once('/') else {
+            return Ok(InterfaceMatch::Name(s.into()));
+        };
+        let invalid = || RustdropError::InvalidConfig(format!("Invalid subnet {}", s));
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+        if prefix > if addr.is_ipv4() { 32 } else { 128 } {
+            return Err(invalid());
+        }
+        Ok(InterfaceMatch::Subnet { addr, prefix })
+    }
+}
+// Which mDNS implementation announces this device and browses for others.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MdnsProvider {
+    // Built in, answering on its own socket.
+    #[default]
+    Builtin,
+    // The system avahi-daemon, over D-Bus.
+    Avahi,
+}
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    // Only use interfaces matching one of these, or every interface when empty.
+    pub include: Vec<InterfaceMatch>,
+    pub exclude: Vec<InterfaceMatch>,
+    // Skip VPN tunnels and container bridges unless they are explicitly included.
+    pub skip_virtual: bool,
+    // Listen on the first free port in this range instead of an ephemeral one.
+    pub ports: Option<RangeInclusive<u16>>,
+    pub mdns: MdnsProvider,
+}
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            skip_virtual: true,
+            ports: None,
+            mdns: MdnsProvider::default(),
+        }
+    }
+}
+impl NetworkConfig {
+    pub fn allows(&self, name: &str, ip: IpAddr) -> bool {
+        let included = self.include.iter().any(|m| m.matches(name, ip));
+        if self.exclude.iter().any(|m| m.matches(name, ip)) {
+            return false;
+        }
+        if !self.include.is_empty() {
+            return included;
+        }
+        !(self.skip_virtual && VIRTUAL_PREFIXES.iter().any(|p| name.starts_with(p)))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        let ip: IpAddr = "192.168.1.5".parse().unwrap();
+        let mut config = NetworkConfig::default();
+        assert!(config.allows("wlan0", ip));
+        assert!(!config.allows("wg0", ip));
+        assert!(!config.allows("docker0", ip));
+        config.include = vec!["192.168.1.0/24".parse().unwrap(), "wg0".parse().unwrap()];
+        assert!(config.allows("wlan0", ip));
+        assert!(config.allows("wg0", "10.0.0.2".parse().unwrap()));
+        assert!(!config.allows("eth0", "10.0.0.2".parse().unwrap()));
+        config.exclude = vec!["wlan0".parse().unwrap()];
+        assert!(!config.allows("wlan0", ip));
+        let v6: InterfaceMatch = "fe80::/10".parse().unwrap();
+        assert!(v6.matches("eth0", "fe80::1".parse().unwrap()));
+        assert!(!v6.matches("eth0", ip));
+        assert!("10.0.0.0/33".parse::<InterfaceMatch>().is_err());
+    }
+}
@@ -1,7 +1,7 @@
 pub mod file;
-mod id;
 pub mod incoming;
 pub mod outgoing;
+pub mod sink;
 pub mod text;
 pub mod traits;
 pub mod wifi;
@@ -15,7 +15,6 @@ use tokio::sync::{
 };
 use tracing::{debug, error, info};
 
-use self::id::get_payload;
 use super::{protocol::get_offline_frame, RustdropError};
 use crate::{
     protobuf::{
@@ -36,31 +35,38 @@ use crate::{
 struct Incoming {
     pub data: BytesMut,
     pub remaining_bytes: i64,
-    pub is_finished: bool,
 }
 #[derive(Debug)]
 pub struct Payload {
     pub data: Bytes,
     pub id: i64,
 }
+// Part of a payload, as it arrived.
+#[derive(Debug)]
+pub struct Chunk {
+    pub id: i64,
+    pub total_size: i64,
+    pub offset: i64,
+    pub data: Bytes,
+    pub last: bool,
+}
 impl Incoming {
     pub fn new(size: i64) -> Self {
         Incoming {
             data: BytesMut::zeroed(size.try_into().unwrap()),
             remaining_bytes: size,
-            is_finished: false,
         }
     }
 }
 #[derive(Debug)]
 pub struct PayloadReciever {
-    incoming: HashMap<i64, Incoming>,
-    send: UnboundedSender<Payload>,
+    send: UnboundedSender<Chunk>,
     disconnect: Sender<DisconnectionFrame>,
 }
 #[derive(Debug)]
 pub struct PayloadRecieverHandle {
-    recv: UnboundedReceiver<Payload>,
+    incoming: HashMap<i64, Incoming>,
+    recv: UnboundedReceiver<Chunk>,
     disconnect: Receiver<DisconnectionFrame>,
 }
 #[derive(Debug)]
@@ -130,8 +136,32 @@ impl PayloadSender {
         self.send_encrypted(construct_payload_transfer_first(&data, header.clone()));
         self.send_encrypted(construct_payload_transfer_end(header, len))
     }
-    pub fn send_message(&mut self, message: &Frame) {
-        let id = get_payload();
+    // Streams have no size, they are told apart from byte payloads by their type.
+    pub fn send_chunk(&mut self, chunk: Chunk) {
+        let mut header = get_payload_header(chunk.id, chunk.total_size);
+        if chunk.total_size == -1 {
+            header.set_type(PayloadType::Stream);
+        }
+        let flags = if chunk.last {
+            Flags::LastChunk.into()
+        } else {
+            0
+        };
+        let data = PayloadChunk {
+            body: (!chunk.data.is_empty()).then(|| chunk.data.to_vec()),
+            offset: Some(chunk.offset),
+            flags: Some(flags),
+            ..Default::default()
+        };
+        let payload = PayloadTransferFrame {
+            packet_type: Some(PacketType::Data.into()),
+            payload_header: Some(header),
+            payload_chunk: Some(data),
+            ..Default::default()
+        };
+        self.send_encrypted(payload_to_offline(payload));
+    }
+    pub fn send_message(&mut self, message: &Frame, id: i64) {
         let body = Bytes::from(message.encode_to_vec());
         self.send_raw(body, id);
     }
@@ -144,12 +174,12 @@ impl PayloadReciever {
         let (send, recv) = mpsc::unbounded_channel();
         let (tx, rx) = oneshot::channel();
         let handle = PayloadRecieverHandle {
+            incoming: HashMap::default(),
             recv,
             disconnect: rx,
         };
         context.spawn(async {
             let reciver = PayloadReciever {
-                incoming: HashMap::default(),
                 send,
                 disconnect: tx,
             };
@@ -173,7 +203,6 @@ impl PayloadReciever {
                     todo!()
                 }
             };
-            self.get_next_payload();
         }
         debug!("No more frames to handle");
     }
@@ -182,50 +211,50 @@ impl PayloadReciever {
     fn push_data(&mut self, data: PayloadTransferFrame) {
         let header = data.payload_header.unwrap();
         let chunk = data.payload_chunk.unwrap();
-        let id = header.id();
-        let size = header.total_size();
-        let default = Incoming::new(size);
-        self.incoming.entry(id).or_insert(default);
-
-        let incoming = self.incoming.get_mut(&id).unwrap();
-        let offset = chunk.offset();
-        let flags = chunk.flags();
-        if let Some(data) = chunk.body {
-            let len: i64 = data.len().try_into().unwrap();
-            incoming.remaining_bytes -= len;
-            let start: usize = offset.try_into().unwrap();
-            for i in 0..data.len() {
-                incoming.data[i + start] = data[i];
-            }
-        }
-        if flags == i32::from(Flags::LastChunk) && incoming.remaining_bytes == 0 {
-            incoming.is_finished = true;
-        }
-    }
-    pub fn get_next_payload(&mut self) {
-        let mut to_remove = Vec::default();
-        for (id, payload) in self.incoming.iter() {
-            if payload.is_finished {
-                to_remove.push(*id);
-            }
-        }
-        for id in to_remove {
-            let incoming = self.incoming.remove(&id).unwrap();
-            let payload = Payload {
-                data: incoming.data.into(),
-                id,
-            };
-            self.send.send(payload).unwrap();
-        }
+        let chunk = Chunk {
+            id: header.id(),
+            total_size: header.total_size(),
+            offset: chunk.offset(),
+            last: chunk.flags() == i32::from(Flags::LastChunk),
+            data: chunk.body.map(Bytes::from).unwrap_or_default(),
+        };
+        let _ = self.send.send(chunk);
     }
 }
 impl PayloadRecieverHandle {
-    pub async fn get_next_raw(&mut self) -> RustdropResult<Payload> {
+    pub async fn next_chunk(&mut self) -> RustdropResult<Chunk> {
         self.recv
             .recv()
             .await
             .ok_or(RustdropError::StreamClosed().into())
     }
+    // Collects the chunk, returning the payload once all of it arrived.
+    pub fn assemble(&mut self, chunk: Chunk) -> Option<Payload> {
+        let incoming = self
+            .incoming
+            .entry(chunk.id)
+            .or_insert_with(|| Incoming::new(chunk.total_size));
+        let len: i64 = chunk.data.len().try_into().unwrap();
+        incoming.remaining_bytes -= len;
+        let start: usize = chunk.offset.try_into().unwrap();
+        incoming.data[start..start + chunk.data.len()].copy_from_slice(&chunk.data);
+        if !chunk.last || incoming.remaining_bytes != 0 {
+            return None;
+        }
+        let incoming = self.incoming.remove(&chunk.id).unwrap();
+        Some(Payload {
+            data: incoming.data.into(),
+            id: chunk.id,
+        })
+    }
+    pub async fn get_next_raw(&mut self) -> RustdropResult<Payload> {
+        loop {
+            let chunk = self.next_chunk().await?;
+            if let Some(payload) = self.assemble(chunk) {
+                return Ok(payload);
+            }
+        }
+    }
     pub async fn wait_for_disconnect(self) -> RustdropResult<DisconnectionFrame> {
         self.disconnect
             .await
@@ -1,7 +1,24 @@
-use std::{fs, os::unix::fs::MetadataExt, path::PathBuf};
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
 
 use super::traits::IncomingMeta;
-use crate::protobuf::nearby::sharing::service::{file_metadata::Type, FileMetadata};
+use crate::{
+    core::RustdropError,
+    protobuf::nearby::sharing::service::{file_metadata::Type, FileMetadata},
+    RustdropResult,
+};
+const DEFAULT_MIME: &str = "application/octet-stream";
+const DOCUMENT_MIMES: [&str; 6] = [
+    "application/pdf",
+    "application/rtf",
+    "application/msword",
+    "application/epub+zip",
+    "application/vnd.ms-",
+    "application/vnd.o",
+];
 
 #[derive(Debug, Clone)]
 pub struct IncomingFile {
@@ -10,6 +27,86 @@ pub struct IncomingFile {
     pub size: i64,
     pub file_type: Type,
 }
+// A file which was received and written to the sink.
+#[derive(Debug, Clone)]
+pub struct ReceivedFile {
+    // Where it was saved, the name is changed if one was already taken
+    pub path: Option<PathBuf>,
+    // The name the sender gave it
+    pub name: String,
+    pub size: u64,
+    pub mime_type: String,
+    // The name of the device which sent it
+    pub sender: String,
+}
+// Overrides for what is announced to the receiver, detected from the file when unset.
+#[derive(Debug, Clone, Default)]
+pub struct FileOptions {
+    pub name: Option<String>,
+    pub mime_type: Option<String>,
+}
+fn detect_mime(path: &Path) -> RustdropResult<String> {
+    if let Some(kind) = infer::get_from_path(path)? {
+        return Ok(kind.mime_type().into());
+    }
+    Ok(mime_guess::from_path(path)
+        .first_raw()
+        .unwrap_or(DEFAULT_MIME)
+        .into())
+}
+// Sniffs the data, falling back to the extension of the name.
+pub(crate) fn detect_mime_bytes(name: &str, data: &[u8]) -> String {
+    if let Some(kind) = infer::get(data) {
+        return kind.mime_type().into();
+    }
+    mime_guess::from_path(name)
+        .first_raw()
+        .unwrap_or(DEFAULT_MIME)
+        .into()
+}
+pub(crate) fn file_type(mime_type: &str) -> Type {
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+    match essence.split_once('/') {
+        _ if essence == "application/vnd.android.package-archive" => Type::AndroidApp,
+        Some(("image", _)) => Type::Image,
+        Some(("video", _)) => Type::Video,
+        Some(("audio", _)) => Type::Audio,
+        Some(("text", _)) => Type::Document,
+        _ if DOCUMENT_MIMES
+            .iter()
+            .any(|prefix| essence.starts_with(prefix)) =>
+        {
+            Type::Document
+        }
+        _ => Type::Unknown,
+    }
+}
+impl IncomingFile {
+    pub(crate) fn from_path(path: &Path, options: FileOptions) -> RustdropResult<Self> {
+        let metadata = fs::metadata(path)?;
+        if !metadata.is_file() {
+            Err(RustdropError::InvalidFile(path.to_path_buf()))?;
+        }
+        let name = match options.name {
+            Some(name) => name,
+            None => path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| RustdropError::InvalidFile(path.to_path_buf()))?
+                .into(),
+        };
+        let mime_type = match options.mime_type {
+            Some(mime_type) => mime_type,
+            None => detect_mime(path)?,
+        };
+        Ok(Self {
+            name,
+            size: metadata.size().try_into()?,
+            file_type: file_type(&mime_type),
+            mime_type,
+        })
+    }
+}
 impl IncomingMeta for IncomingFile {
     type ProtoType = FileMetadata;
     fn into_proto_type_with_id(self, payload_id: i64, id: i64) -> Self::ProtoType {
@@ -31,25 +128,10 @@ impl IncomingMeta for IncomingFile {
         }
     }
 }
-impl From<PathBuf> for IncomingFile {
-    fn from(path: PathBuf) -> Self {
-        let name = path.file_name().unwrap().to_str().unwrap().into();
-        let metadata = fs::metadata(&path).unwrap();
-        let size = metadata.size().try_into().unwrap();
-        let mime_type = infer::get_from_path(path).unwrap().unwrap();
-        let file_type = match mime_type.matcher_type() {
-            // infer::MatcherType::App => Type::App,
-            infer::MatcherType::Audio => Type::Audio,
-            infer::MatcherType::Image => Type::Image,
-            infer::MatcherType::Video => Type::Video,
-            _ => Type::Unknown,
-        };
-        Self {
-            name,
-            size,
-            mime_type: mime_type.mime_type().into(),
-            file_type,
-        }
+impl TryFrom<PathBuf> for IncomingFile {
+    type Error = color_eyre::Report;
+    fn try_from(path: PathBuf) -> RustdropResult<Self> {
+        Self::from_path(&path, FileOptions::default())
     }
 }
 impl From<FileMetadata> for IncomingFile {
@@ -62,3 +144,46 @@ impl From<FileMetadata> for IncomingFile {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn test_file_type() {
+        assert_eq!(file_type("image/png"), Type::Image);
+        assert_eq!(file_type("video/mp4"), Type::Video);
+        assert_eq!(file_type("audio/ogg"), Type::Audio);
+        assert_eq!(
+            file_type("application/vnd.android.package-archive"),
+            Type::AndroidApp
+        );
+        assert_eq!(file_type("text/plain; charset=utf-8"), Type::Document);
+        assert_eq!(file_type("application/pdf"), Type::Document);
+        assert_eq!(
+            file_type("application/vnd.oasis.opendocument.text"),
+            Type::Document
+        );
+        assert_eq!(file_type(DEFAULT_MIME), Type::Unknown);
+    }
+    #[tokio::test]
+    async fn test_from_path() {
+        let dir = temp_dir("rustdrop_file").await;
+        let path = dir.join("rustdrop_file_test.txt");
+        fs::write(&path, "hello").unwrap();
+        let file = IncomingFile::try_from(path.clone()).unwrap();
+        assert_eq!(file.name, "rustdrop_file_test.txt");
+        assert_eq!(file.mime_type, "text/plain");
+        assert_eq!(file.file_type, Type::Document);
+        assert_eq!(file.size, 5);
+        let options = FileOptions {
+            name: Some("notes".into()),
+            mime_type: Some("text/markdown".into()),
+        };
+        let file = IncomingFile::from_path(&path, options).unwrap();
+        assert_eq!(file.name, "notes");
+        assert_eq!(file.mime_type, "text/markdown");
+        assert!(IncomingFile::try_from(dir.clone()).is_err());
+        assert!(IncomingFile::try_from(dir.join("rustdrop_missing")).is_err());
+    }
+}
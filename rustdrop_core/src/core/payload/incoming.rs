@@ -1,18 +1,37 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use flume::Sender;
-use tokio::{
-    fs::{create_dir_all, File},
-    io::AsyncWriteExt,
-};
+use bytes::BytesMut;
+use prost::Message;
+use tokio::io::AsyncWriteExt;
 use tracing::debug;
 
-use super::{traits::IncomingMeta, Payload};
+use super::{
+    sink::{IncomingSink, SinkWriter},
+    traits::IncomingMeta,
+    Chunk,
+};
 use crate::{
-    core::{IncomingFile, IncomingWifi},
-    protobuf::nearby::sharing::service::IntroductionFrame,
-    Context, IncomingText, ReceiveEvent,
+    core::{IncomingFile, IncomingWifi, Limits},
+    protobuf::nearby::sharing::service::{IntroductionFrame, WifiCredentials},
+    IncomingText, ReceivedFile, RustdropError, RustdropResult,
 };
+// Wi-Fi credentials, and texts without a size, are not declared any larger
+const MAX_BUFFERED: u64 = 64 * 1024;
+// Everything received in a share, once it is complete.
+#[derive(Debug, Clone)]
+pub struct ReceivedTransfer {
+    // The name of the device which sent it
+    pub sender: String,
+    pub files: Vec<ReceivedFile>,
+    // The texts which were passed on in events
+    pub texts: Vec<IncomingText>,
+    pub wifi: Vec<IncomingWifi>,
+}
+pub(crate) enum Received {
+    File(ReceivedFile),
+    Text(IncomingText),
+    Wifi(IncomingWifi),
+}
 #[derive(Debug, Clone, Default)]
 pub struct Incoming {
     files: HashMap<i64, IncomingFile>,
@@ -32,52 +51,184 @@ impl Incoming {
             self.wifi.insert(wifi.payload_id(), wifi.into());
         }
     }
-    async fn write_file(&mut self, payload: &mut Payload, context: &Context) {
-        debug!("Writing payload {:?}", payload.id);
-        let incoming = self.files.remove(&payload.id).unwrap();
-        let dest = context.config.dest.clone();
-        create_dir_all(dest.clone()).await.unwrap();
-        let filepath = dest.join(incoming.name);
-        let mut file = File::create(filepath).await.unwrap();
-        file.write_all_buf(&mut payload.data).await.unwrap();
-    }
-    pub(crate) async fn process_payload(
-        &mut self,
-        payload: &mut Payload,
-        context: &Context,
-        events: &Sender<ReceiveEvent>,
-    ) -> bool {
-        if self.files.contains_key(&payload.id) {
-            self.write_file(payload, context).await;
-            return true;
-        }
-        if let Some(mut incoming) = self.text.remove(&payload.id) {
-            incoming
-                .text
-                .extend(String::from_utf8(payload.data.to_vec()));
-            let event = ReceiveEvent::Text(incoming);
-            events.send_async(event).await.unwrap();
-            return true;
-        }
-        if self.wifi.contains_key(&payload.id) {
-            todo!();
-            // let event = ReceiveEvent::Wifi(incoming);
-            // events.send_async(event).await.unwrap();
-            return true;
-        }
-        false
+    pub fn files(&self) -> impl Iterator<Item = &IncomingFile> {
+        self.files.values()
+    }
+    pub fn texts(&self) -> impl Iterator<Item = &IncomingText> {
+        self.text.values()
+    }
+    pub fn wifi(&self) -> impl Iterator<Item = &IncomingWifi> {
+        self.wifi.values()
+    }
+    // Bytes of files and text to be received.
+    pub fn total_size(&self) -> u64 {
+        let files = self.files.values().map(|file| file.size);
+        let texts = self.text.values().map(|text| text.size);
+        files.chain(texts).map(|size| size.max(0) as u64).sum()
     }
     pub(crate) fn is_finished(&self) -> bool {
         self.files.is_empty() && self.wifi.is_empty() && self.text.is_empty()
     }
-    pub(crate) fn meta_type(&self) -> String {
+    // What the share contains, such as "a link and 2 files".
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
         if let Some(text) = self.text.values().next() {
-            return text.describe(self.text.len());
+            let same_type = self
+                .text
+                .values()
+                .all(|other| other.text_type == text.text_type);
+            parts.push(if same_type {
+                text.describe(self.text.len())
+            } else {
+                format!("{} texts", self.text.len())
+            });
         }
         if let Some(file) = self.files.values().next() {
-            return file.describe(self.files.len());
+            parts.push(file.describe(self.files.len()));
+        }
+        if let Some(wifi) = self.wifi.values().next() {
+            parts.push(wifi.describe(self.wifi.len()));
+        }
+        match parts.split_last() {
+            None => "nothing".into(),
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
         }
-        todo!();
+    }
+}
+struct Streaming {
+    writer: SinkWriter,
+    written: u64,
+    // Streamed texts are left to the sink
+    file: Option<IncomingFile>,
+}
+// A share being received, its items are written to the sink as they arrive.
+pub(crate) struct Receiving {
+    incoming: Incoming,
+    sink: Arc<dyn IncomingSink>,
+    sender: String,
+    streaming: HashMap<i64, Streaming>,
+    limits: Limits,
+    // Bytes of the share written so far
+    written: u64,
+    // Texts and Wi-Fi credentials are small, they are kept until complete
+    buffers: HashMap<i64, BytesMut>,
+}
+impl Receiving {
+    pub fn new(
+        incoming: Incoming,
+        sink: Arc<dyn IncomingSink>,
+        sender: String,
+        limits: Limits,
+    ) -> Self {
+        Self {
+            incoming,
+            sink,
+            sender,
+            streaming: HashMap::new(),
+            limits,
+            written: 0,
+            buffers: HashMap::new(),
+        }
+    }
+    pub fn is_finished(&self) -> bool {
+        self.incoming.is_finished() && self.streaming.is_empty()
+    }
+    // Whether the payload is one of the items, rather than a frame.
+    pub fn contains(&self, id: i64) -> bool {
+        self.streaming.contains_key(&id)
+            || self.incoming.files.contains_key(&id)
+            || self.incoming.text.contains_key(&id)
+            || self.incoming.wifi.contains_key(&id)
+    }
+    // The item the chunk completed, if any.
+    pub async fn process_chunk(&mut self, chunk: Chunk) -> RustdropResult<Option<Received>> {
+        if !self.streaming.contains_key(&chunk.id) && !self.buffers.contains_key(&chunk.id) {
+            self.open(chunk.id).await?;
+        }
+        if let Some(streaming) = self.streaming.get_mut(&chunk.id) {
+            let written = streaming.written + chunk.data.len() as u64;
+            let total = self.written + chunk.data.len() as u64;
+            let checked = match &streaming.file {
+                Some(file) => self.limits.check_file(&file.name, written),
+                None => Ok(()),
+            };
+            if let Err(reason) = checked.and(self.limits.check_total(total)) {
+                Err(RustdropError::LimitExceeded(reason))?;
+            }
+            streaming.writer.writer.write_all(&chunk.data).await?;
+            streaming.written = written;
+            self.written = total;
+            if !chunk.last {
+                return Ok(None);
+            }
+            let mut streaming = self.streaming.remove(&chunk.id).unwrap();
+            streaming.writer.writer.shutdown().await?;
+            debug!("Wrote payload {:?}", chunk.id);
+            return Ok(streaming.file.map(|file| {
+                Received::File(ReceivedFile {
+                    path: streaming.writer.path,
+                    name: file.name,
+                    size: streaming.written,
+                    mime_type: file.mime_type,
+                    sender: self.sender.clone(),
+                })
+            }));
+        }
+        let limit = self.buffer_limit(chunk.id);
+        let buffer = self.buffers.get_mut(&chunk.id).unwrap();
+        if (buffer.len() + chunk.data.len()) as u64 > limit {
+            Err(RustdropError::InvalidMessage(format!(
+                "Payload {} is larger than the {} bytes declared",
+                chunk.id, limit
+            )))?;
+        }
+        buffer.extend_from_slice(&chunk.data);
+        if !chunk.last {
+            return Ok(None);
+        }
+        let data = self.buffers.remove(&chunk.id).unwrap().freeze();
+        if let Some(mut text) = self.incoming.text.remove(&chunk.id) {
+            text.text.push_str(&String::from_utf8_lossy(&data));
+            return Ok(Some(Received::Text(text)));
+        }
+        let mut wifi = self.incoming.wifi.remove(&chunk.id).unwrap();
+        let credentials = WifiCredentials::decode(data)?;
+        wifi.password = credentials.password().into();
+        wifi.hidden = credentials.hidden_ssid();
+        Ok(Some(Received::Wifi(wifi)))
+    }
+    fn buffer_limit(&self, id: i64) -> u64 {
+        match self.incoming.text.get(&id) {
+            Some(text) if text.size > 0 => text.size as u64,
+            _ => MAX_BUFFERED,
+        }
+    }
+    async fn open(&mut self, id: i64) -> RustdropResult<()> {
+        if let Some(file) = self.incoming.files.remove(&id) {
+            let writer = self.sink.file(&self.sender, &file).await?;
+            let streaming = Streaming {
+                writer,
+                written: 0,
+                file: Some(file),
+            };
+            self.streaming.insert(id, streaming);
+            return Ok(());
+        }
+        if let Some(text) = self.incoming.text.get(&id) {
+            if let Some(writer) = self.sink.text(&self.sender, text).await? {
+                self.incoming.text.remove(&id);
+                let streaming = Streaming {
+                    writer,
+                    written: 0,
+                    file: None,
+                };
+                self.streaming.insert(id, streaming);
+                return Ok(());
+            }
+        }
+        self.buffers.insert(id, BytesMut::new());
+        Ok(())
     }
 }
 impl From<IntroductionFrame> for Incoming {
@@ -87,3 +238,118 @@ impl From<IntroductionFrame> for Incoming {
         incoming
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protobuf::nearby::sharing::service::{
+        text_metadata, wifi_credentials_metadata::SecurityType, FileMetadata, TextMetadata,
+        WifiCredentialsMetadata,
+    };
+    use crate::{core::payload::sink::DirectorySink, test_util::temp_dir, Config};
+
+    fn text(payload_id: i64, text_type: text_metadata::Type) -> TextMetadata {
+        TextMetadata {
+            payload_id: Some(payload_id),
+            r#type: Some(text_type.into()),
+            size: Some(19),
+            ..Default::default()
+        }
+    }
+    #[test]
+    fn test_describe() {
+        let wifi = WifiCredentialsMetadata {
+            ssid: Some("Home".into()),
+            security_type: Some(SecurityType::WpaPsk.into()),
+            payload_id: Some(1),
+            ..Default::default()
+        };
+        let incoming = Incoming::from(IntroductionFrame {
+            wifi_credentials_metadata: vec![wifi],
+            ..Default::default()
+        });
+        assert_eq!(incoming.describe(), "a Wi-Fi network");
+        assert_eq!(incoming.wifi().next().unwrap().ssid, "Home");
+        assert_eq!(incoming.total_size(), 0);
+
+        let file = FileMetadata {
+            payload_id: Some(3),
+            size: Some(100),
+            ..Default::default()
+        };
+        let incoming = Incoming::from(IntroductionFrame {
+            file_metadata: vec![file],
+            text_metadata: vec![
+                text(1, text_metadata::Type::Url),
+                text(2, text_metadata::Type::Url),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(incoming.describe(), "2 links and a file");
+        assert_eq!(incoming.total_size(), 138);
+        let incoming = Incoming::from(IntroductionFrame {
+            text_metadata: vec![
+                text(1, text_metadata::Type::Unknown),
+                text(2, text_metadata::Type::PhoneNumber),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(incoming.describe(), "2 texts");
+        assert_eq!(Incoming::default().describe(), "nothing");
+    }
+    fn chunk(id: i64, data: &'static [u8]) -> Chunk {
+        Chunk {
+            id,
+            total_size: 19,
+            offset: 0,
+            data: data.into(),
+            last: true,
+        }
+    }
+    #[tokio::test]
+    async fn test_buffered() {
+        let incoming = Incoming::from(IntroductionFrame {
+            text_metadata: vec![
+                text(1, text_metadata::Type::Text),
+                text(2, text_metadata::Type::Text),
+            ],
+            ..Default::default()
+        });
+        let sink = Arc::new(DirectorySink::new("unused".into()));
+        let limits = Limits::default();
+        let mut receiving = Receiving::new(incoming, sink, "Sender".into(), limits);
+        let received = receiving.process_chunk(chunk(1, b"Caf\xc3")).await.unwrap();
+        let Some(Received::Text(text)) = received else {
+            panic!("No text received");
+        };
+        assert_eq!(text.text, "Caf\u{fffd}");
+        let res = receiving.process_chunk(chunk(2, &[b'a'; 20])).await;
+        assert!(res.is_err());
+    }
+    #[tokio::test]
+    async fn test_written_limit() {
+        let file = FileMetadata {
+            name: Some("stream".into()),
+            payload_id: Some(1),
+            size: Some(-1),
+            ..Default::default()
+        };
+        let incoming = Incoming::from(IntroductionFrame {
+            file_metadata: vec![file],
+            ..Default::default()
+        });
+        let mut config = Config::with_dest(temp_dir("rustdrop_written_limit").await);
+        config.max_file_size = Some(4);
+        let sink = Arc::new(DirectorySink::new(config.dest.clone()));
+        let mut receiving = Receiving::new(incoming, sink, "Sender".into(), Limits::new(&config));
+        let mut first = chunk(1, b"1234");
+        first.last = false;
+        assert!(receiving.process_chunk(first).await.unwrap().is_none());
+        let Err(e) = receiving.process_chunk(chunk(1, b"5")).await else {
+            panic!("The stream was not stopped at the limit");
+        };
+        assert_eq!(
+            e.to_string(),
+            "Transfer aborted: stream is 5 bytes, larger than the 4 byte limit"
+        );
+    }
+}
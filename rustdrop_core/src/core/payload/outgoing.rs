@@ -1,16 +1,33 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
-use async_stream::stream;
+use async_stream::try_stream;
 use bytes::Bytes;
 use futures::Stream;
-use tokio::{fs::File, io::AsyncReadExt};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt},
+    sync::Semaphore,
+};
 
-use super::{id::get_payload, traits::IncomingMeta};
+use super::{
+    file::{detect_mime_bytes, file_type},
+    traits::IncomingMeta,
+    Chunk,
+};
 use crate::{
-    core::protocol::get_online_frame,
+    core::{protocol::get_online_frame, RustdropError, Session},
     protobuf::nearby::sharing::service::{v1_frame::FrameType, Frame, IntroductionFrame, V1Frame},
-    IncomingFile, IncomingText, IncomingWifi,
+    FileOptions, IncomingFile, IncomingText, IncomingWifi, ReadHalf, RustdropResult, ScannedQrCode,
+    TextType,
 };
+// Payloads are read and sent in pieces of this size.
+const CHUNK_SIZE: u64 = 512 * 1024;
 // Metadata for Outgoing media
 #[derive(Debug, Clone, Default)]
 struct OutgoingMeta {
@@ -19,23 +36,165 @@ struct OutgoingMeta {
     pub wifi: HashMap<i64, IncomingWifi>,
 }
 
+#[derive(Clone)]
+enum Source {
+    Bytes(Bytes),
+    Path(PathBuf),
+    // Shared by the attempt over each route, whichever gets to send it takes it
+    Reader(Arc<Mutex<Option<ReadHalf>>>),
+}
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Bytes(data) => write!(f, "Bytes({})", data.len()),
+            Source::Path(path) => write!(f, "Path({:?})", path),
+            Source::Reader(_) => write!(f, "Reader"),
+        }
+    }
+}
+impl Source {
+    async fn open(self) -> RustdropResult<ReadHalf> {
+        Ok(match self {
+            Source::Bytes(data) => Box::new(io::Cursor::new(data)),
+            Source::Path(path) => Box::new(File::open(path).await?),
+            Source::Reader(reader) => {
+                reader
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .ok_or(RustdropError::InvalidMessage(
+                        "Reader was already sent".into(),
+                    ))?
+            }
+        })
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Outgoing {
     meta: OutgoingMeta,
-    payloads: HashMap<i64, Bytes>,
-    file_payloads: HashMap<i64, PathBuf>,
+    payloads: HashMap<i64, Source>,
+    qr_code: Option<ScannedQrCode>,
+    // Shared by sends to several devices, so only a few chunks are read at once
+    reads: Option<Arc<Semaphore>>,
 }
 impl Outgoing {
-    pub fn add_file(&mut self, path: PathBuf) {
-        let payload_id = get_payload();
-        let incoming = IncomingFile::from(path.clone());
+    // Numbered per transfer rather than per process, so a replayed transfer has the recorded ids
+    fn next_id(&self) -> i64 {
+        self.payloads.len() as i64
+    }
+    pub fn add_file(&mut self, path: PathBuf) -> RustdropResult<()> {
+        self.add_file_with(path, FileOptions::default())
+    }
+    pub fn add_file_with(&mut self, path: PathBuf, options: FileOptions) -> RustdropResult<()> {
+        let incoming = IncomingFile::from_path(&path, options)?;
+        let payload_id = self.next_id();
         self.meta.files.insert(payload_id, incoming);
-        self.file_payloads.insert(payload_id, path);
+        self.payloads.insert(payload_id, Source::Path(path));
+        Ok(())
     }
-    pub(crate) fn get_frames(self) -> (Frame, impl Stream<Item = (i64, Bytes)>) {
-        let intro = self.meta.into();
+    // Sends data the application has in memory as a file, detecting the MIME type when unset.
+    pub fn add_bytes(&mut self, name: String, mime_type: Option<String>, data: Bytes) {
+        let mime_type = mime_type.unwrap_or_else(|| detect_mime_bytes(&name, &data));
+        let incoming = IncomingFile {
+            name,
+            size: data.len() as i64,
+            file_type: file_type(&mime_type),
+            mime_type,
+        };
+        let payload_id = self.next_id();
+        self.meta.files.insert(payload_id, incoming);
+        self.payloads.insert(payload_id, Source::Bytes(data));
+    }
+    // Sends whatever the reader gives as a file, such as stdin.
+    // Without a size it is sent as a stream, which is read until it ends.
+    // The reader can only be sent once, so it is not resent over another route.
+    pub fn add_reader<R>(&mut self, name: String, mime_type: String, size: Option<u64>, reader: R)
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        let size = match size {
+            Some(size) => size.try_into().unwrap_or(i64::MAX),
+            None => -1,
+        };
+        let incoming = IncomingFile {
+            name,
+            size,
+            file_type: file_type(&mime_type),
+            mime_type,
+        };
+        let payload_id = self.next_id();
+        self.meta.files.insert(payload_id, incoming);
+        let reader: ReadHalf = Box::new(reader);
+        let source = Source::Reader(Arc::new(Mutex::new(Some(reader))));
+        self.payloads.insert(payload_id, source);
+    }
+    pub fn add_text(&mut self, text: String, text_type: TextType) {
+        let payload_id = self.next_id();
+        let incoming = IncomingText {
+            name: text.clone(),
+            text_type,
+            size: text.len() as i64,
+            text: String::new(),
+        };
+        self.meta.text.insert(payload_id, incoming);
+        self.payloads.insert(payload_id, Source::Bytes(text.into()));
+    }
+    // Send to the receiver which showed this code, skipping its confirmation.
+    pub fn set_qr_code(&mut self, qr_code: ScannedQrCode) {
+        self.qr_code = Some(qr_code);
+    }
+    pub(crate) fn qr_code(&self) -> Option<&ScannedQrCode> {
+        self.qr_code.as_ref()
+    }
+    pub(crate) fn limit_reads(&mut self, reads: Arc<Semaphore>) {
+        self.reads = Some(reads);
+    }
+    // Reads what readers give into memory, so it can be sent more than once.
+    pub(crate) async fn buffer_readers(&mut self, limit: Option<u64>) -> RustdropResult<()> {
+        for (id, source) in self.payloads.iter_mut() {
+            if let Source::Reader(_) = source {
+                let mut data = Vec::new();
+                let reader = source.clone().open().await?;
+                let max = limit.map_or(u64::MAX, |limit| limit.saturating_add(1));
+                reader.take(max).read_to_end(&mut data).await?;
+                let file = self.meta.files.get_mut(id);
+                if let Some(limit) = limit.filter(|limit| data.len() as u64 > *limit) {
+                    let name = file.as_ref().map_or("A reader", |file| &file.name);
+                    Err(RustdropError::InvalidConfig(format!(
+                        "{} is larger than the {} bytes buffered to send it to several devices",
+                        name, limit
+                    )))?;
+                }
+                if let Some(file) = file {
+                    file.size = data.len() as i64;
+                }
+                *source = Source::Bytes(data.into());
+            }
+        }
+        Ok(())
+    }
+    // Bytes of files and text to be sent, leaving out streams of unknown size.
+    pub fn total_size(&self) -> u64 {
+        let files = self.meta.files.values().map(|file| file.size);
+        let texts = self.meta.text.values().map(|text| text.size);
+        files.chain(texts).map(|size| size.max(0) as u64).sum()
+    }
+    pub(crate) fn get_frames(
+        self,
+        session: &Session,
+    ) -> (Frame, impl Stream<Item = RustdropResult<Chunk>>) {
+        // Unknown sizes are sent as streams
+        let sizes: HashMap<i64, i64> = self
+            .meta
+            .files
+            .iter()
+            .map(|(id, file)| (*id, file.size))
+            .chain(self.meta.text.iter().map(|(id, text)| (*id, text.size)))
+            .collect();
+        let intro = self.meta.introduction(session);
         let payloads = self.payloads;
-        let file_payloads = self.file_payloads;
+        let reads = self.reads;
         let v1 = V1Frame {
             r#type: Some(FrameType::Introduction.into()),
             introduction: Some(intro),
@@ -44,21 +203,53 @@ impl Outgoing {
         let frame = get_online_frame(v1);
         (
             frame,
-            stream! {
-                for (id, payload) in payloads.into_iter() {
-                    yield (id, payload)
-                }
-                for (id, path) in file_payloads.into_iter() {
-                    let mut buf = vec![];
-                    let mut file = File::open(path).await.unwrap();
-                    file.read_to_end(&mut buf).await.unwrap();
-                    yield (id, buf.into())
+            try_stream! {
+                for (id, source) in payloads.into_iter() {
+                    let total_size = sizes[&id];
+                    let mut reader = source.open().await?;
+                    let mut offset = 0;
+                    loop {
+                        let mut data = Vec::new();
+                        let limit = match total_size {
+                            -1 => CHUNK_SIZE,
+                            _ => CHUNK_SIZE.min((total_size - offset) as u64),
+                        };
+                        // Only held while reading, a slow device doesn't hold up the others
+                        let permit = match &reads {
+                            Some(reads) => Some(reads.acquire().await?),
+                            None => None,
+                        };
+                        (&mut reader).take(limit).read_to_end(&mut data).await?;
+                        drop(permit);
+                        if data.is_empty() {
+                            break;
+                        }
+                        let len = data.len() as i64;
+                        yield Chunk {
+                            id,
+                            total_size,
+                            offset,
+                            data: data.into(),
+                            last: false,
+                        };
+                        offset += len;
+                    }
+                    if total_size != -1 && offset != total_size {
+                        Err(io::Error::from(ErrorKind::UnexpectedEof))?;
+                    }
+                    yield Chunk {
+                        id,
+                        total_size,
+                        offset,
+                        data: Bytes::new(),
+                        last: true,
+                    };
                 }
             },
         )
     }
     pub fn len(&self) -> usize {
-        self.file_payloads.len() + self.payloads.len()
+        self.payloads.len()
     }
 
     #[must_use]
@@ -66,28 +257,56 @@ impl Outgoing {
         self.len() == 0
     }
 }
-impl From<OutgoingMeta> for IntroductionFrame {
-    fn from(val: OutgoingMeta) -> Self {
+impl OutgoingMeta {
+    fn introduction(self, session: &Session) -> IntroductionFrame {
         // TODO: App support
         IntroductionFrame {
             start_transfer: Some(true),
             app_metadata: [].into(),
-            file_metadata: val
+            file_metadata: self
                 .files
                 .into_iter()
-                .map(|(payload_id, data)| data.into_proto_type(payload_id))
+                .map(|(payload_id, data)| {
+                    data.into_proto_type_with_id(payload_id, session.random_id())
+                })
                 .collect(),
-            text_metadata: val
+            text_metadata: self
                 .text
                 .into_iter()
-                .map(|(payload_id, data)| data.into_proto_type(payload_id))
+                .map(|(payload_id, data)| {
+                    data.into_proto_type_with_id(payload_id, session.random_id())
+                })
                 .collect(),
             required_package: None,
-            wifi_credentials_metadata: val
+            wifi_credentials_metadata: self
                 .wifi
                 .into_iter()
-                .map(|(payload_id, data)| data.into_proto_type(payload_id))
+                .map(|(payload_id, data)| {
+                    data.into_proto_type_with_id(payload_id, session.random_id())
+                })
                 .collect(),
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn outgoing() -> Outgoing {
+        let mut outgoing = Outgoing::default();
+        let reader = Cursor::new(b"0123456789".to_vec());
+        outgoing.add_reader("stdin".into(), "text/plain".into(), None, reader);
+        outgoing
+    }
+    #[tokio::test]
+    async fn test_buffer_readers() {
+        let mut too_large = outgoing();
+        let e = too_large.buffer_readers(Some(9)).await.unwrap_err();
+        assert!(e.to_string().contains("stdin is larger"), "{}", e);
+        let mut buffered = outgoing();
+        buffered.buffer_readers(Some(10)).await.unwrap();
+        assert_eq!(buffered.total_size(), 10);
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    fmt::Debug,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use futures::future::BoxFuture;
+use tokio::fs::{create_dir_all, File, OpenOptions};
+
+use crate::{IncomingFile, IncomingText, RustdropResult, WriteHalf};
+
+// Where the data of an item is written to, as it arrives.
+pub struct SinkWriter {
+    pub writer: WriteHalf,
+    // Reported as where the item was saved, if it was saved to a file
+    pub path: Option<PathBuf>,
+}
+impl SinkWriter {
+    pub fn new(writer: WriteHalf, path: Option<PathBuf>) -> Self {
+        Self { writer, path }
+    }
+}
+// Decides where the items of an accepted share go, such as a folder per sender.
+pub trait IncomingSink: Debug + Send + Sync + 'static {
+    // Called before the first chunk of the file is written.
+    fn file<'a>(
+        &'a self,
+        sender: &'a str,
+        file: &'a IncomingFile,
+    ) -> BoxFuture<'a, RustdropResult<SinkWriter>>;
+    // Texts are passed on in events, unless a writer is returned for them.
+    fn text<'a>(
+        &'a self,
+        _sender: &'a str,
+        _text: &'a IncomingText,
+    ) -> BoxFuture<'a, RustdropResult<Option<SinkWriter>>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+// Writes files into a directory, which is `Config::dest` unless another sink is set.
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    pub dir: PathBuf,
+}
+impl DirectorySink {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+impl IncomingSink for DirectorySink {
+    fn file<'a>(
+        &'a self,
+        _sender: &'a str,
+        file: &'a IncomingFile,
+    ) -> BoxFuture<'a, RustdropResult<SinkWriter>> {
+        Box::pin(async {
+            create_dir_all(&self.dir).await?;
+            let (path, file) = create_unique(&self.dir, &file.name).await?;
+            Ok(SinkWriter::new(Box::new(file), Some(path)))
+        })
+    }
+}
+// Creates the file without overwriting, "a.txt" becomes "a (1).txt" when taken.
+pub async fn create_unique(dest: &Path, name: &str) -> RustdropResult<(PathBuf, File)> {
+    // Only the last component, a sender can't pick the directory
+    let name = Path::new(name)
+        .file_name()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| "file".into());
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut path = dest.join(&name);
+    for i in 1.. {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                path = dest.join(format!("{} ({}){}", stem, i, extension));
+            }
+            Err(e) => Err(e)?,
+        }
+    }
+    unreachable!()
+}
@@ -3,6 +3,7 @@ use crate::protobuf::nearby::sharing::service::{text_metadata, TextMetadata};
 
 #[derive(Debug, Clone)]
 pub struct IncomingText {
+    // A preview of the text, as titled by the sender
     pub name: String,
     pub text_type: text_metadata::Type,
     pub size: i64,
@@ -35,17 +36,15 @@ impl IncomingMeta for IncomingText {
                 "{} {}",
                 quantity,
                 match self.text_type {
-                    crate::TextType::Unknown => todo!(),
-                    crate::TextType::Text => "texts",
+                    crate::TextType::Unknown | crate::TextType::Text => "texts",
                     crate::TextType::Url => "links",
                     crate::TextType::Address => "addresses",
-                    crate::TextType::PhoneNumber => "phone numberes",
+                    crate::TextType::PhoneNumber => "phone numbers",
                 }
             )
         } else {
             match self.text_type {
-                crate::TextType::Unknown => todo!(),
-                crate::TextType::Text => "some text",
+                crate::TextType::Unknown | crate::TextType::Text => "some text",
                 crate::TextType::Url => "a link",
                 crate::TextType::Address => "an address",
                 crate::TextType::PhoneNumber => "a phone number",
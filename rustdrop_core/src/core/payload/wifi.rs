@@ -8,12 +8,17 @@ use super::traits::IncomingMeta;
 pub struct IncomingWifi {
     pub ssid: String,
     pub security_type: SecurityType,
+    // Sent once the share is accepted
+    pub password: String,
+    pub hidden: bool,
 }
 impl From<WifiCredentialsMetadata> for IncomingWifi {
     fn from(wifi: WifiCredentialsMetadata) -> Self {
         IncomingWifi {
             ssid: wifi.ssid().into(),
             security_type: wifi.security_type(),
+            password: String::new(),
+            hidden: false,
         }
     }
 }
@@ -23,6 +28,10 @@ impl IncomingMeta for IncomingWifi {
         todo!()
     }
     fn describe(&self, quantity: usize) -> String {
-        todo!()
+        if quantity > 1 {
+            format!("{} Wi-Fi networks", quantity)
+        } else {
+            "a Wi-Fi network".into()
+        }
     }
 }
@@ -6,7 +6,7 @@ use std::time::Duration;
 use tokio::{select, time::sleep};
 use tokio_util::sync::CancellationToken;
 
-use super::{io::writer::WriterSend, util::get_random};
+use super::{io::writer::WriterSend, Session};
 use crate::{
     core::handlers::offline::keep_alive,
     mediums::Discover,
@@ -44,10 +44,14 @@ pub(crate) fn get_paired_result() -> Frame {
     };
     get_online_frame(v1)
 }
-pub fn get_paired_frame() -> Frame {
+pub(crate) fn get_paired_frame(
+    session: &Session,
+    qr_code_handshake_data: Option<Vec<u8>>,
+) -> Frame {
     let p_key = PairedKeyEncryptionFrame {
-        secret_id_hash: Some(get_random(6)),
-        signed_data: Some(get_random(72)),
+        secret_id_hash: Some(session.random(6)),
+        signed_data: Some(session.random(72)),
+        qr_code_handshake_data,
         ..Default::default()
     };
     let v1 = V1FrameOnline {
@@ -57,7 +61,14 @@ pub fn get_paired_frame() -> Frame {
     };
     get_online_frame(v1)
 }
-#[derive(Debug, Clone)]
+pub(crate) fn qr_code_handshake_data(frame: Frame) -> Option<Vec<u8>> {
+    frame
+        .v1?
+        .paired_key_encryption?
+        .qr_code_handshake_data
+        .filter(|data| !data.is_empty())
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Device {
     pub endpoint_id: u32,
     pub device_name: String,
@@ -0,0 +1,183 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::prelude::*;
+use bytes::{BufMut, Bytes, BytesMut};
+use qrcode::{render::unicode::Dense1x2, Color};
+
+use super::{
+    ukey2::{Crypto, CryptoImpl},
+    RustdropError,
+};
+use crate::RustdropResult;
+
+const URL_PREFIX: &str = "https://quickshare.google/qrcode#key=";
+const KEY_VERSION: [u8; 2] = [0, 0];
+const KEY_LEN: usize = 35;
+const ADVERTISING_INFO: &[u8] = b"advertisingContext";
+const HANDSHAKE_SALT: &[u8] = b"qrCodeHandshake";
+// Codes are no longer advertised nor accepted this long after being made.
+pub(crate) const QR_CODE_TTL: Duration = Duration::from_secs(10 * 60);
+// The oldest code is forgotten when another is made.
+const MAX_CODES: usize = 4;
+
+fn handshake_data(key_data: &[u8], auth_string: &[u8]) -> Bytes {
+    CryptoImpl::extract_expand(auth_string, key_data, HANDSHAKE_SALT, 32)
+}
+// Shown by a receiver. Whoever scans it can skip the confirmation prompt.
+#[derive(Debug, Clone)]
+pub struct QrCode {
+    key: <CryptoImpl as Crypto>::SecretKey,
+    key_data: Bytes,
+}
+impl QrCode {
+    // Every code has a key of its own, forgotten along with the code.
+    pub(crate) fn new() -> Self {
+        let key = CryptoImpl::genkey();
+        let mut key_data = BytesMut::with_capacity(KEY_LEN);
+        key_data.put_slice(&KEY_VERSION);
+        key_data.put(CryptoImpl::to_compressed(&key));
+        Self {
+            key,
+            key_data: key_data.freeze(),
+        }
+    }
+    pub fn url(&self) -> String {
+        format!(
+            "{}{}",
+            URL_PREFIX,
+            BASE64_URL_SAFE_NO_PAD.encode(&self.key_data)
+        )
+    }
+    // Rows of modules, true where dark. Does not include a quiet zone.
+    pub fn matrix(&self) -> Vec<Vec<bool>> {
+        let code = qrcode::QrCode::new(self.url()).unwrap();
+        let width = code.width();
+        code.to_colors()
+            .chunks(width)
+            .map(|row| row.iter().map(|color| *color == Color::Dark).collect())
+            .collect()
+    }
+    // Renders the code with half-block characters, for terminals.
+    pub fn to_text(&self) -> String {
+        qrcode::QrCode::new(self.url())
+            .unwrap()
+            .render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build()
+    }
+    pub fn advertising_token(&self) -> Bytes {
+        CryptoImpl::extract_expand(ADVERTISING_INFO, &self.key_data, &[], 16)
+    }
+    pub(crate) fn verify(&self, auth_string: &[u8], data: &[u8]) -> bool {
+        handshake_data(&self.key_data, auth_string) == data
+    }
+    pub(crate) fn sign(&self, auth_string: &[u8]) -> Vec<u8> {
+        CryptoImpl::ecdsa_sign(&self.key, auth_string)
+    }
+}
+// The codes this device has shown, shared between every connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct QrCodes {
+    codes: Arc<Mutex<VecDeque<(Instant, QrCode)>>>,
+}
+impl QrCodes {
+    pub fn generate(&self) -> QrCode {
+        let code = QrCode::new();
+        let mut codes = self.codes.lock().unwrap();
+        codes.push_back((Instant::now(), code.clone()));
+        if codes.len() > MAX_CODES {
+            codes.pop_front();
+        }
+        code
+    }
+    // The codes which have not expired, oldest first.
+    fn active(&self) -> Vec<QrCode> {
+        let mut codes = self.codes.lock().unwrap();
+        codes.retain(|(made, _)| made.elapsed() < QR_CODE_TTL);
+        codes.iter().map(|(_, code)| code.clone()).collect()
+    }
+    pub fn latest(&self) -> Option<QrCode> {
+        self.active().pop()
+    }
+    pub fn verify(&self, auth_string: &[u8], data: &[u8]) -> bool {
+        self.active()
+            .iter()
+            .any(|code| code.verify(auth_string, data))
+    }
+}
+// A code scanned from a receiver, used when sending to it.
+#[derive(Debug, Clone)]
+pub struct ScannedQrCode {
+    key_data: Bytes,
+}
+impl ScannedQrCode {
+    pub fn from_url(url: &str) -> RustdropResult<Self> {
+        let encoded = url
+            .strip_prefix(URL_PREFIX)
+            .ok_or_else(|| RustdropError::InvalidQrCode(url.into()))?;
+        let key_data = BASE64_URL_SAFE_NO_PAD.decode(encoded)?;
+        if key_data.len() != KEY_LEN || !key_data.starts_with(&KEY_VERSION) {
+            Err(RustdropError::InvalidQrCode(url.into()))?;
+        }
+        if CryptoImpl::from_compressed(&key_data[KEY_VERSION.len()..]).is_none() {
+            Err(RustdropError::InvalidQrCode(url.into()))?;
+        }
+        Ok(Self {
+            key_data: key_data.into(),
+        })
+    }
+    pub(crate) fn handshake_data(&self, auth_string: &[u8]) -> Bytes {
+        handshake_data(&self.key_data, auth_string)
+    }
+    // Checks the receiver signed this session with the key from the code.
+    pub(crate) fn verify(&self, auth_string: &[u8], signature: &[u8]) -> bool {
+        CryptoImpl::from_compressed(&self.key_data[KEY_VERSION.len()..])
+            .is_some_and(|key| CryptoImpl::ecdsa_verify(&key, auth_string, signature))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::util::get_random;
+
+    #[test]
+    fn test_handshake() {
+        let codes = QrCodes::default();
+        let code = codes.generate();
+        let scanned = ScannedQrCode::from_url(&code.url()).unwrap();
+        let auth_string = get_random(32);
+        let data = scanned.handshake_data(&auth_string);
+        assert!(codes.verify(&auth_string, &data));
+        assert!(!codes.verify(&get_random(32), &data));
+        assert!(scanned.verify(&auth_string, &code.sign(&auth_string)));
+        assert!(!scanned.verify(&auth_string, &QrCode::new().sign(&auth_string)));
+        assert_eq!(code.advertising_token().len(), 16);
+        assert!(!code.matrix().is_empty());
+        assert!(ScannedQrCode::from_url("https://example.com").is_err());
+    }
+    #[test]
+    fn test_expiry() {
+        let codes = QrCodes::default();
+        let first = codes.generate();
+        for _ in 0..MAX_CODES {
+            codes.generate();
+        }
+        let auth_string = get_random(32);
+        let scanned = ScannedQrCode::from_url(&first.url()).unwrap();
+        assert!(!codes.verify(&auth_string, &scanned.handshake_data(&auth_string)));
+        let latest = codes.generate();
+        let scanned = ScannedQrCode::from_url(&latest.url()).unwrap();
+        let data = scanned.handshake_data(&auth_string);
+        assert!(codes.verify(&auth_string, &data));
+        if let Some(made) = Instant::now().checked_sub(QR_CODE_TTL) {
+            codes.codes.lock().unwrap().back_mut().unwrap().0 = made;
+            assert!(!codes.verify(&auth_string, &data));
+            assert_ne!(codes.latest().unwrap().url(), latest.url());
+        }
+    }
+}
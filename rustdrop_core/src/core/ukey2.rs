@@ -9,4 +9,4 @@ pub(crate) type CryptoImpl = OpenSSL;
 pub(crate) type Ukey2 = encryptor_decryptor::Ukey2<CryptoImpl>;
 pub(crate) use generic::Crypto;
 pub(crate) use key_exchange::get_public;
-pub(crate) use utils::get_generic_pubkey;
+pub(crate) use utils::{get_generic_pubkey, get_pin};
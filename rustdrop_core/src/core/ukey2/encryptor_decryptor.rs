@@ -5,9 +5,11 @@ use tracing::info;
 use super::consts::{D2D_SALT, PT2_SALT};
 use crate::{
     core::{
+        capture::{Direction, Layer},
         io::{reader::ReaderRecv, writer::WriterSend},
         ukey2::{generic::Crypto, key_exchange::key_echange, utils::get_header},
-        util::{get_iv, iv_from_vec},
+        util::iv_from_vec,
+        Session,
     },
     protobuf::{
         location::nearby::connections::OfflineFrame,
@@ -31,8 +33,8 @@ impl<C: Crypto + 'static> Ukey2<C> {
         server_init: Bytes,
         dest_key: C::PublicKey,
         is_client: bool,
-    ) -> (Self, Self) {
-        let (_auth_string, next_protocol_secret) =
+    ) -> (Self, Self, Bytes) {
+        let (auth_string, next_protocol_secret) =
             key_echange::<C>(dest_key, source_key, client_init, server_init);
         let d2d_client =
             C::extract_expand("client".as_bytes(), &next_protocol_secret, &D2D_SALT, 32);
@@ -45,9 +47,9 @@ impl<C: Crypto + 'static> Ukey2<C> {
         let client_ukey = Ukey2::new_half(client_key, client_hmac);
         let server_ukey = Ukey2::new_half(server_key, server_hmac);
         if is_client {
-            (client_ukey, server_ukey)
+            (client_ukey, server_ukey, auth_string)
         } else {
-            (server_ukey, client_ukey)
+            (server_ukey, client_ukey, auth_string)
         }
     }
     fn encrypt<T: Message>(&self, message: &T, iv: [u8; 16]) -> Vec<u8> {
@@ -56,17 +58,20 @@ impl<C: Crypto + 'static> Ukey2<C> {
     fn decrypt(&self, raw: Vec<u8>, iv: [u8; 16]) -> Vec<u8> {
         C::decrypt(&self.aes, iv, raw)
     }
-    fn encrypt_message<T: Message>(&mut self, message: &T) -> SecureMessage {
+    fn encrypt_message<T: Message>(&mut self, message: &T, iv: [u8; 16]) -> SecureMessage {
         self.seq += 1;
         let d2d = DeviceToDeviceMessage {
             sequence_number: Some(self.seq),
             message: Some(message.encode_to_vec()),
         };
-        self.encrypt_message_d2d(&d2d)
+        self.encrypt_message_d2d(&d2d, iv)
     }
-    fn encrypt_message_d2d(&mut self, message: &DeviceToDeviceMessage) -> SecureMessage {
+    fn encrypt_message_d2d(
+        &mut self,
+        message: &DeviceToDeviceMessage,
+        iv: [u8; 16],
+    ) -> SecureMessage {
         info!("{:?}", message);
-        let iv = get_iv();
         let header = get_header(&iv);
         let body = self.encrypt(message, iv);
         let header_and_body = HeaderAndBody { body, header };
@@ -80,10 +85,15 @@ impl<C: Crypto + 'static> Ukey2<C> {
         self,
         reader: ReaderRecv,
         context: &mut Context,
+        session: Session,
     ) -> UnboundedReceiver<OfflineFrame> {
         let (send, recv) = mpsc::unbounded_channel();
         context.spawn(async move {
-            while let Ok(msg) = reader.next_message().await {
+            while let Ok(raw) = reader.next().await {
+                session.record(Direction::Received, Layer::Encrypted, &raw);
+                let Ok(msg) = SecureMessage::decode(raw) else {
+                    break;
+                };
                 let decrypted = self.decrypt_message(&msg);
                 if send.send(decrypted).is_err() {
                     break;
@@ -92,15 +102,22 @@ impl<C: Crypto + 'static> Ukey2<C> {
         });
         recv
     }
+    // The IVs are drawn from the session, so a replay encrypts the same way.
     pub fn start_encrypting(
         mut self,
         writer: WriterSend,
         context: &mut Context,
+        session: Session,
     ) -> UnboundedSender<OfflineFrame> {
         let (send, mut recv) = mpsc::unbounded_channel();
         context.spawn(async move {
             while let Some(msg) = recv.recv().await {
-                let encrypted = self.encrypt_message(&msg);
+                let encrypted = self.encrypt_message(&msg, session.iv());
+                session.record(
+                    Direction::Sent,
+                    Layer::Encrypted,
+                    &encrypted.encode_to_vec(),
+                );
                 writer.send(&encrypted).await
             }
         });
@@ -134,7 +151,7 @@ mod tests {
 
     use super::*;
     use crate::{
-        core::{protocol::get_paired_frame, ukey2::OpenSSL},
+        core::{protocol::get_paired_frame, ukey2::OpenSSL, util::get_iv, Session},
         protobuf::nearby::sharing::service::Frame,
     };
     fn get_init_resp() -> (Bytes, Bytes) {
@@ -157,10 +174,10 @@ mod tests {
         // let _server_pubkey = to_pubkey(&server_keypair);
         let client_pubkey = to_pubkey::<OpenSSL>(&client_keypair);
         let (init, resp) = get_init_resp();
-        let (mut server_ukey, _client_ukey): (Ukey2<OpenSSL>, Ukey2<OpenSSL>) =
+        let (mut server_ukey, _client_ukey, _): (Ukey2<OpenSSL>, Ukey2<OpenSSL>, _) =
             Ukey2::new(init, server_keypair, resp, client_pubkey, false);
-        let msg = get_paired_frame();
-        let _encrypted = server_ukey.encrypt_message(&msg);
+        let msg = get_paired_frame(&Session::default(), None);
+        let _encrypted = server_ukey.encrypt_message(&msg, get_iv());
     }
     #[traced_test()]
     #[test]
@@ -171,18 +188,19 @@ mod tests {
         let server_pubkey = to_pubkey::<OpenSSL>(&server_keypair);
         let client_pubkey = to_pubkey::<OpenSSL>(&client_keypair);
         let (init, resp) = get_init_resp();
-        let (_, client_ukey): (Ukey2<OpenSSL>, Ukey2<OpenSSL>) = Ukey2::new(
+        let (_, client_ukey, client_auth): (Ukey2<OpenSSL>, Ukey2<OpenSSL>, _) = Ukey2::new(
             init.clone(),
             client_keypair,
             resp.clone(),
             server_pubkey,
             true,
         );
-        let (mut server_ukey, _): (Ukey2<OpenSSL>, Ukey2<OpenSSL>) =
+        let (mut server_ukey, _, server_auth): (Ukey2<OpenSSL>, Ukey2<OpenSSL>, _) =
             Ukey2::new(init, server_keypair, resp, client_pubkey, false);
+        assert_eq!(client_auth, server_auth);
         // info!("Client {:?} Server {:?}", client_ukey, server_ukey);
-        let msg = get_paired_frame();
-        let encrypted = server_ukey.encrypt_message(&msg);
+        let msg = get_paired_frame(&Session::default(), None);
+        let encrypted = server_ukey.encrypt_message(&msg, get_iv());
         let decrypted: Frame = client_ukey.decrypt_message(&encrypted);
         assert_eq!(decrypted, msg);
     }
@@ -10,6 +10,8 @@ pub trait Crypto: Debug + Sync + Send + Default {
     fn to_pubkey(x: &[u8], y: &[u8]) -> Self::PublicKey;
     fn from_pubkey(pubkey: &Self::SecretKey) -> (Bytes, Bytes);
     fn genkey() -> Self::SecretKey;
+    fn secret_to_bytes(secret: &Self::SecretKey) -> Bytes;
+    fn secret_from_bytes(raw: &[u8]) -> Option<Self::SecretKey>;
     fn diffie_hellman(secret: Self::SecretKey, public: &Self::PublicKey) -> Vec<u8>;
     fn extract_expand(info: &[u8], key: &[u8], salt: &[u8], len: usize) -> Bytes;
     fn get_aes_decrypt_from_bytes(source: &[u8]) -> Self::AesKey;
@@ -32,4 +34,8 @@ pub trait Crypto: Debug + Sync + Send + Default {
     fn sign(key: &Self::HmacKey, data: &[u8]) -> Vec<u8>;
     fn sha256(data: &[u8]) -> [u8; 32];
     fn sha512(data: &[u8]) -> [u8; 64];
+    fn to_compressed(secret: &Self::SecretKey) -> Bytes;
+    fn from_compressed(raw: &[u8]) -> Option<Self::PublicKey>;
+    fn ecdsa_sign(secret: &Self::SecretKey, data: &[u8]) -> Vec<u8>;
+    fn ecdsa_verify(public: &Self::PublicKey, data: &[u8], signature: &[u8]) -> bool;
 }
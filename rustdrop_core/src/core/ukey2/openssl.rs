@@ -2,14 +2,14 @@ use bytes::{Bytes, BytesMut};
 use openssl::{
     bn::{BigNum, BigNumContext},
     derive::Deriver,
-    ec::{EcGroup, EcKey},
+    ec::{EcGroup, EcKey, EcPoint, PointConversionForm},
     hash::MessageDigest,
     md::Md,
     nid::Nid,
     pkey::{Id, PKey, Private, Public},
     pkey_ctx::{HkdfMode, PkeyCtx},
     sha::{sha256, sha512},
-    sign::Signer,
+    sign::{Signer, Verifier},
     symm::{decrypt, encrypt, Cipher},
 };
 
@@ -47,6 +47,12 @@ impl Crypto for OpenSSL {
     fn genkey() -> Self::SecretKey {
         EcKey::generate(&Self::group()).unwrap()
     }
+    fn secret_to_bytes(secret: &Self::SecretKey) -> Bytes {
+        secret.private_key_to_der().unwrap().into()
+    }
+    fn secret_from_bytes(raw: &[u8]) -> Option<Self::SecretKey> {
+        EcKey::private_key_from_der(raw).ok()
+    }
 
     fn diffie_hellman(secret: Self::SecretKey, public: &Self::PublicKey) -> Vec<u8> {
         let converted: PKey<Private> = secret.try_into().unwrap();
@@ -97,6 +103,29 @@ impl Crypto for OpenSSL {
     fn sha512(data: &[u8]) -> [u8; 64] {
         sha512(data)
     }
+    fn to_compressed(secret: &Self::SecretKey) -> Bytes {
+        let mut ctx = BigNumContext::new().unwrap();
+        let raw = secret
+            .public_key()
+            .to_bytes(&Self::group(), PointConversionForm::COMPRESSED, &mut ctx)
+            .unwrap();
+        Bytes::from(raw)
+    }
+    fn from_compressed(raw: &[u8]) -> Option<Self::PublicKey> {
+        let mut ctx = BigNumContext::new().unwrap();
+        let point = EcPoint::from_bytes(&Self::group(), raw, &mut ctx).ok()?;
+        let ec = EcKey::from_public_key(&Self::group(), &point).ok()?;
+        ec.try_into().ok()
+    }
+    fn ecdsa_sign(secret: &Self::SecretKey, data: &[u8]) -> Vec<u8> {
+        let key: PKey<Private> = secret.clone().try_into().unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        signer.sign_oneshot_to_vec(data).unwrap()
+    }
+    fn ecdsa_verify(public: &Self::PublicKey, data: &[u8], signature: &[u8]) -> bool {
+        let mut verifier = Verifier::new(MessageDigest::sha256(), public).unwrap();
+        verifier.verify_oneshot(signature, data).unwrap_or(false)
+    }
 }
 
 #[cfg(test)]
@@ -42,3 +42,13 @@ pub fn get_generic_pubkey<C: Crypto>(secretkey: &C::SecretKey) -> GenericPublicK
         ..Default::default()
     }
 }
+// The four digit code both devices show, derived from the handshake the same way as Android does.
+pub fn get_pin(auth_string: &[u8]) -> String {
+    let mut hash: i32 = 0;
+    let mut multiplier: i32 = 1;
+    for byte in auth_string {
+        hash = (hash + *byte as i8 as i32 * multiplier) % 9973;
+        multiplier = (multiplier * 31) % 9973;
+    }
+    format!("{:04}", hash.abs())
+}
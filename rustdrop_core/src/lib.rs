@@ -3,18 +3,33 @@ mod core;
 mod mediums;
 pub(crate) mod protobuf;
 pub(crate) mod runner;
+#[cfg(test)]
+mod test_util;
 use core::RustdropError;
 
-pub use crate::api::PairingRequest;
-pub(crate) use crate::core::Incoming;
+pub use crate::api::{
+    DeclineReason, MultiOutcome, MultiTransfer, PairingRequest, Transfer, TransferOutcome,
+};
 pub use crate::core::{
-    protocol::Device, Config, IncomingFile, IncomingText, IncomingWifi, Outgoing,
+    capture::{self, Transcript},
+    inspect,
+    protocol::Device,
+    Config, DirectorySink, FileOptions, Incoming, IncomingFile, IncomingSink, IncomingText,
+    IncomingWifi, InterfaceMatch, MdnsProvider, NetworkConfig, Outgoing, QrCode, ReceivedFile,
+    ReceivedTransfer, RejectionReason, ScannedQrCode, SinkWriter,
+};
+pub use crate::protobuf::nearby::sharing::service::{
+    file_metadata::Type as FileType, text_metadata::Type as TextType,
+    wifi_credentials_metadata::SecurityType as WifiSecurityType,
 };
-pub use crate::protobuf::nearby::sharing::service::text_metadata::Type as TextType;
 pub use api::events::{DiscoveryEvent, ReceiveEvent, SenderEvent};
 pub use api::DiscoveryHandle;
 use color_eyre::eyre;
-pub(crate) use runner::context::Context;
-pub use runner::managed::Rustdrop;
+pub use runner::context::Context;
+pub use runner::{managed::Rustdrop, DiscoveringHandle};
 pub type RustdropResult<T> = eyre::Result<T>;
 pub use core::bits::DeviceType;
+pub use mediums::{
+    recieve, Discover, Discovery, Loopback, LoopbackNetwork, Medium, MediumType, ReadHalf, Route,
+    WriteHalf,
+};
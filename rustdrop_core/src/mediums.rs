@@ -1,11 +1,43 @@
-use self::{bt::BluetoothDiscovery, wlan::WlanDiscovery};
+use std::{fmt::Display, net::SocketAddr};
 
+#[cfg(feature = "bluetooth")]
 pub mod bt;
 mod generic;
+mod loopback;
 pub mod wlan;
-pub use generic::{Discovery, Medium};
+pub(crate) use generic::send_over;
+pub use generic::{recieve, Discover, Discovery, Medium, ReadHalf, WriteHalf};
+pub use loopback::{Loopback, LoopbackNetwork};
+// Ordered by preference, earlier mediums are tried first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MediumType {
+    Wlan,
+    Bluetooth,
+    // Added by the application, by name
+    Custom(&'static str),
+}
+// Where a device can be reached
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Discover {
-    Wlan(WlanDiscovery),
-    Bluetooth(BluetoothDiscovery),
+pub enum Route {
+    Wlan(SocketAddr),
+    Bluetooth(String),
+    Custom(&'static str, String),
+}
+impl Route {
+    pub fn medium(&self) -> MediumType {
+        match self {
+            Route::Wlan(_) => MediumType::Wlan,
+            Route::Bluetooth(_) => MediumType::Bluetooth,
+            Route::Custom(medium, _) => MediumType::Custom(medium),
+        }
+    }
+}
+impl Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Route::Wlan(addr) => write!(f, "{}", addr),
+            Route::Bluetooth(addr) => write!(f, "Bluetooth {}", addr),
+            Route::Custom(medium, addr) => write!(f, "{} {}", medium, addr),
+        }
+    }
 }
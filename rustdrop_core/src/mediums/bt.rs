@@ -1,6 +1,8 @@
-mod ble;
+mod adapter;
+mod bluez;
 mod consts;
 mod discovery;
 mod manager;
-pub(crate) use discovery::BluetoothDiscovery;
+#[cfg(test)]
+mod simulated;
 pub(crate) use manager::Bluetooth;
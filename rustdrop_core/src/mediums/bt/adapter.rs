@@ -0,0 +1,59 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+};
+
+use bluer::Address;
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    mediums::{ReadHalf, WriteHalf},
+    RustdropResult,
+};
+
+pub(crate) type Connection = (ReadHalf, WriteHalf);
+// Characteristic uuids and their values.
+pub(crate) type Characteristics = Vec<(Uuid, Vec<u8>)>;
+// What an adapter knows about a nearby device.
+#[derive(Debug, Clone)]
+pub(crate) struct NearbyDevice {
+    pub addr: Address,
+    pub name: Option<String>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+}
+#[derive(Debug, Clone)]
+pub(crate) enum ScanEvent {
+    // Sent again whenever the name or service data of the device changes
+    Found(NearbyDevice),
+    Lost(Address),
+}
+// Everything the Bluetooth medium needs from the radio.
+pub(crate) trait BluetoothAdapter: Debug + Send + Sync + 'static {
+    // Advertises the service data over BLE until the token is cancelled.
+    fn advertise(
+        &self,
+        service: Uuid,
+        data: Bytes,
+    ) -> BoxFuture<'_, RustdropResult<CancellationToken>>;
+    // Devices advertising any of the services, starting with the ones already known.
+    fn scan(
+        &self,
+        services: HashSet<Uuid>,
+    ) -> BoxFuture<'_, RustdropResult<BoxStream<'static, ScanEvent>>>;
+    // The characteristics of a GATT service on the device, with their values.
+    fn read_gatt(
+        &self,
+        addr: Address,
+        service: Uuid,
+    ) -> BoxFuture<'_, RustdropResult<Characteristics>>;
+    // Accepts RFCOMM connections to the service.
+    fn listen(
+        &self,
+        service: Uuid,
+        name: String,
+    ) -> BoxFuture<'_, RustdropResult<BoxStream<'static, Connection>>>;
+    fn connect(&self, addr: Address, service: Uuid) -> BoxFuture<'_, RustdropResult<Connection>>;
+}
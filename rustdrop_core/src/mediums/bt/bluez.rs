@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+
+use async_stream::stream;
+use bluer::{
+    adv::{Advertisement, Feature, SecondaryChannel},
+    rfcomm::{Profile, Role},
+    Adapter, AdapterEvent, Address, DiscoveryFilter, Session,
+};
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+use uuid::Uuid;
+
+use super::adapter::{BluetoothAdapter, Characteristics, Connection, NearbyDevice, ScanEvent};
+use crate::{
+    core::RustdropError,
+    mediums::{ReadHalf, WriteHalf},
+    Context, RustdropResult,
+};
+
+fn get_advertisment(service_uuid: Uuid, adv_data: Bytes) -> Advertisement {
+    Advertisement {
+        advertisement_type: bluer::adv::Type::Broadcast,
+        service_uuids: vec![service_uuid].into_iter().collect(),
+        service_data: [(service_uuid, adv_data.into())].into(),
+        secondary_channel: Some(SecondaryChannel::OneM),
+        system_includes: [Feature::TxPower].into(),
+        tx_power: Some(20),
+        ..Default::default()
+    }
+}
+fn into_connection(stream: bluer::rfcomm::Stream) -> Connection {
+    let (rx, tx) = stream.into_split();
+    (Box::new(rx) as ReadHalf, Box::new(tx) as WriteHalf)
+}
+async fn nearby(
+    adapter: &Adapter,
+    addr: Address,
+    services: &HashSet<Uuid>,
+) -> RustdropResult<Option<NearbyDevice>> {
+    let dev = adapter.device(addr)?;
+    let service_data = dev.service_data().await?.unwrap_or_default();
+    let uuids = dev.uuids().await?.unwrap_or_default();
+    if !uuids
+        .iter()
+        .chain(service_data.keys())
+        .any(|id| services.contains(id))
+    {
+        return Ok(None);
+    }
+    Ok(Some(NearbyDevice {
+        addr,
+        name: dev.name().await?,
+        service_data,
+    }))
+}
+// The adapter BlueZ reports as the default one.
+#[derive(Debug)]
+pub(crate) struct Bluez {
+    session: Session,
+    adapter: Adapter,
+    context: Context,
+}
+impl Bluez {
+    pub async fn new(context: Context) -> RustdropResult<Self> {
+        let session = bluer::Session::new().await?;
+        let adapter = session.default_adapter().await?;
+        adapter.set_powered(true).await?;
+        Ok(Self {
+            session,
+            adapter,
+            context,
+        })
+    }
+}
+impl BluetoothAdapter for Bluez {
+    fn advertise(
+        &self,
+        service: Uuid,
+        data: Bytes,
+    ) -> BoxFuture<'_, RustdropResult<CancellationToken>> {
+        Box::pin(async move {
+            info!(
+                "Advertising on Bluetooth adapter {} with address {}",
+                self.adapter.name(),
+                self.adapter.address().await?
+            );
+            let le_advertisement = get_advertisment(service, data);
+            let cancel = CancellationToken::new();
+            let c2 = cancel.child_token();
+            let handle = self.adapter.advertise(le_advertisement).await?;
+            self.context.spawn(async move {
+                c2.cancelled().await;
+                info!("Removing advertisement");
+                drop(handle);
+            });
+            Ok(cancel)
+        })
+    }
+    fn scan(
+        &self,
+        services: HashSet<Uuid>,
+    ) -> BoxFuture<'_, RustdropResult<BoxStream<'static, ScanEvent>>> {
+        Box::pin(async move {
+            let filter = DiscoveryFilter {
+                uuids: services.clone(),
+                transport: bluer::DiscoveryTransport::Auto,
+                discoverable: true,
+                ..Default::default()
+            };
+            self.adapter.set_discovery_filter(filter).await?;
+            let mut discover = self.adapter.discover_devices_with_changes().await?;
+            let known = self.adapter.device_addresses().await?;
+            let adapter = self.adapter.clone();
+            let events = stream! {
+                for addr in known {
+                    if let Ok(Some(dev)) = nearby(&adapter, addr, &services).await {
+                        yield ScanEvent::Found(dev);
+                    }
+                }
+                // Changed properties are reported as the device being added again
+                while let Some(event) = discover.next().await {
+                    match event {
+                        AdapterEvent::DeviceAdded(addr) => {
+                            if let Ok(Some(dev)) = nearby(&adapter, addr, &services).await {
+                                yield ScanEvent::Found(dev);
+                            }
+                        }
+                        AdapterEvent::DeviceRemoved(addr) => yield ScanEvent::Lost(addr),
+                        _ => (),
+                    }
+                }
+            };
+            Ok(events.boxed())
+        })
+    }
+    fn read_gatt(
+        &self,
+        addr: Address,
+        service: Uuid,
+    ) -> BoxFuture<'_, RustdropResult<Characteristics>> {
+        Box::pin(async move {
+            let dev = self.adapter.device(addr)?;
+            let mut values = Vec::new();
+            for gatt in dev.services().await? {
+                if gatt.uuid().await? != service {
+                    continue;
+                }
+                for char in gatt.characteristics().await? {
+                    values.push((char.uuid().await?, char.read().await?));
+                }
+            }
+            Ok(values)
+        })
+    }
+    fn listen(
+        &self,
+        service: Uuid,
+        name: String,
+    ) -> BoxFuture<'_, RustdropResult<BoxStream<'static, Connection>>> {
+        Box::pin(async move {
+            let profile = Profile {
+                uuid: service,
+                role: Some(Role::Server),
+                name: Some(name.clone()),
+                require_authentication: Some(false),
+                require_authorization: Some(false),
+                channel: Some(0),
+                psm: Some(0),
+                auto_connect: Some(true),
+                ..Default::default()
+            };
+            let handle = self.session.register_profile(profile).await?;
+            info!(
+                "Advertising on Bluetooth adapter {} with name {}",
+                self.adapter.name(),
+                name
+            );
+            let connections = handle.filter_map(|req| async move {
+                info!("Received Bluetooth request {:?}", req);
+                req.accept().ok().map(into_connection)
+            });
+            Ok(connections.boxed())
+        })
+    }
+    fn connect(&self, addr: Address, service: Uuid) -> BoxFuture<'_, RustdropResult<Connection>> {
+        Box::pin(async move {
+            // Let BlueZ look up the channel through SDP.
+            let profile = Profile {
+                uuid: service,
+                role: Some(Role::Client),
+                require_authentication: Some(false),
+                require_authorization: Some(false),
+                auto_connect: Some(false),
+                ..Default::default()
+            };
+            let mut handle = self.session.register_profile(profile).await?;
+            let dev = self.adapter.device(addr)?;
+            let stream = select! {
+                res = dev.connect_profile(&service) => {
+                    res?;
+                    handle.next().await.ok_or(RustdropError::Connection())?.accept()?
+                },
+                Some(req) = handle.next() => req.accept()?,
+            };
+            Ok(into_connection(stream))
+        })
+    }
+}
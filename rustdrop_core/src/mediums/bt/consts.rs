@@ -7,9 +7,10 @@ pub const SERVICE_UUID_NEW: Uuid = uuid!("00001101-0000-1000-8000-00805F9B34FB")
 pub const SERVICE_ID_BLE: &str = "NearbySharing";
 pub const SERVICE_UUID_RECIEVING: Uuid = uuid!("0000FEF300001000800000805F9B34FB"); // Device is
                                                                                     // receiving
-pub const SERVICE_UUID_SHARING: Uuid = uuid!("0000fe2c-0000-1000-8000-00805f9b34fb"); // device is
+pub const SERVICE_UUID_SHARING: Uuid = uuid!("0000fe2c-0000-1000-8000-00805f9b34fb"); // device is sharing
+
+// The characteristic of the first advertisement slot, the last byte counts up for the others
 pub const BLE_CHAR: Uuid = uuid!("00000000-0000-3000-8000-000000000000");
-// sharing
 pub const SERVICE_DATA: Bytes = Bytes::from_static(&[
     252, 18, 142, 1, 66, 0, 0, 0, 0, 0, 0, 0, 0, 0, 191, 45, 91, 160, 225, 216, 117, 36, 202, 0,
 ]);
@@ -19,5 +20,10 @@ pub const ALL_UUIDS: [Uuid; 4] = [
     SERVICE_UUID_NEW,
     SERVICE_UUID_SHARING,
 ];
+pub fn slot(characteristic: Uuid) -> Option<usize> {
+    let (high, low) = characteristic.as_u64_pair();
+    let (base_high, base_low) = BLE_CHAR.as_u64_pair();
+    (high == base_high && low & !0xff == base_low).then_some((low & 0xff) as usize)
+}
 // pub const SERVICE_UUID_FASTPAIR: Uuid = uuid!("df21fe2c-2515-4fdb-8886-f12c4d67927c");
 // pub const SERVICE_ID_FASTPAIR: &str = "Fast Pair";
@@ -1,263 +1,191 @@
-use std::{
-    any,
-    collections::{HashMap, HashSet},
-    hash::Hash,
-};
+use std::sync::Arc;
 
-use bluer::{
-    rfcomm::SocketAddr, Adapter, Address, DeviceEvent::PropertyChanged, DeviceProperty, UuidExt,
-};
-use bytes::Bytes;
-use futures::StreamExt;
-use prost::Message;
-use tokio::io::{AsyncRead, AsyncWrite};
+use bluer::Address;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
 use tracing::{debug, info};
 use uuid::Uuid;
 
-use super::consts::SERVICE_UUID;
+use super::{
+    adapter::{BluetoothAdapter, NearbyDevice, ScanEvent},
+    consts::{slot, SERVICE_ID_BLE, SERVICE_UUID, SERVICE_UUID_RECIEVING},
+};
 use crate::{
-    core::bits::{Bitfield, BleName, BluetoothName},
-    mediums::{
-        bt::consts::{BLE_CHAR, SERVICE_UUID_RECIEVING},
-        Discovery,
+    core::{
+        bits::{Bitfield, BleAdvertisement, BleHeader, BluetoothName},
+        protocol::Device,
     },
-    protobuf::location::nearby::mediums::SocketControlFrame,
+    mediums::{Discover, Discovery, MediumType, ReadHalf, Route, WriteHalf},
     runner::DiscoveringHandle,
-    Context, RustdropResult,
+    RustdropResult,
 };
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug)]
 pub struct BluetoothDiscovery {
+    adapter: Arc<dyn BluetoothAdapter>,
     addr: Address,
-    socket: Option<SocketAddr>,
     service: Uuid,
 }
 impl Discovery for BluetoothDiscovery {
-    async fn into_socket(
-        self,
-    ) -> RustdropResult<(
-        impl AsyncRead + Send + Sync + Unpin,
-        impl AsyncWrite + Send + Sync + Unpin,
-    )> {
-        let session = bluer::Session::new().await?;
-        let adapter = session.default_adapter().await?;
-        adapter.set_powered(true).await?;
-        let dev = adapter.device(self.addr)?;
-        dev.connect_profile(&self.service).await?;
-        let services = dev.services().await?;
-        for service in services {
-            info!("Service {:?}", service);
-            if service.uuid().await? == self.service {
-                for char in service.characteristics().await? {
-                    if char.uuid().await? == self.service {
-                        return Ok((char.notify_io().await?, char.write_io().await?));
-                    }
-                }
-            }
-        }
-
-        todo!();
+    fn route(&self) -> Route {
+        Route::Bluetooth(self.addr.to_string())
+    }
+    fn connect(&self) -> BoxFuture<'_, RustdropResult<(ReadHalf, WriteHalf)>> {
+        self.adapter.connect(self.addr, self.service)
     }
 }
-struct DiscoveringBluetooth {
-    dev: bluer::Device,
-    addr: Address,
-    name: String,
+// Turns what a scan reports about nearby devices into routes to them.
+pub(crate) struct DiscoveringBluetooth {
+    adapter: Arc<dyn BluetoothAdapter>,
     send: DiscoveringHandle,
-    to_connect: HashSet<Uuid>,
 }
 impl DiscoveringBluetooth {
-    async fn init(&mut self) -> RustdropResult<()> {
-        info!("Init {:?}", self.dev.all_properties().await?);
-        if self.dev.is_services_resolved().await? {
-            if let Some(name) = self.handle_receiving_ble().await? {
-                todo!()
-            }
-            if let Some(name) = self.handle_receiving_bt().await? {
-                todo!()
-            }
-        }
-        Ok(())
+    pub fn new(adapter: Arc<dyn BluetoothAdapter>, send: DiscoveringHandle) -> Self {
+        Self { adapter, send }
     }
-    async fn connect_profiles(&mut self, services: HashSet<Uuid>) -> RustdropResult<bool> {
-        let mut any_profiles: bool = false;
-        for profile in self.to_connect.iter() {
-            if services.contains(profile) {
-                info!("Found {}", profile);
-                any_profiles = true;
-                if *profile == SERVICE_UUID {
-                    info!("{:?}", self.dev.connect_profile(profile).await);
+    pub async fn run(self, mut events: BoxStream<'static, ScanEvent>) {
+        while let Some(event) = events.next().await {
+            match event {
+                ScanEvent::Found(dev) => {
+                    let addr = dev.addr;
+                    if let Err(e) = self.handle_dev(dev).await {
+                        debug!("Unable to handle {}: {:?}", addr, e);
+                    }
+                }
+                ScanEvent::Lost(addr) => {
+                    let lost = Route::Bluetooth(addr.to_string());
+                    self.send.lost_where(|route| route.route() == lost);
                 }
             }
         }
-        if any_profiles && !self.dev.is_connected().await? {
-            // info!("{:?}", self.dev.connect().await);
-        }
-        Ok(any_profiles)
-    }
-    async fn handle_receiving_bt(&mut self) -> RustdropResult<Option<BleName>> {
-        // if let Ok(service) = self.dev.service(SERVICE_UUID.as_u16().unwrap()).await {
-        //     info!("Found receiving service: {:?}", service.uuid().await);
-        //     for char in service.characteristics().await? {
-        //         info!("Char {:?}", char);
-        //         if char.uuid().await? == BLE_CHAR {
-        //             info!("Char :{:?}", char);
-        //         }
-        //     }
-        // } else {
-        // }
-        Ok(None)
     }
-
-    async fn handle_receiving_ble(&mut self) -> RustdropResult<Option<BleName>> {
-        if let Ok(service) = self
-            .dev
-            .service(SERVICE_UUID_RECIEVING.as_u16().unwrap())
-            .await
+    #[tracing::instrument(fields(addr=%dev.addr, name=?dev.name), skip_all)]
+    async fn handle_dev(&self, dev: NearbyDevice) -> RustdropResult<()> {
+        // Nearby Connections puts everything needed to reach a device in its name.
+        if let Some(name) = dev
+            .name
+            .and_then(|name| BluetoothName::decode_base64(name.as_bytes()).ok())
         {
-            info!("Found receiving service: {:?}", service.uuid().await);
-            for char in service.characteristics().await? {
-                info!("Char {:?}", char);
-                if char.uuid().await? == BLE_CHAR {
-                    info!("Char :{:?}", char);
+            self.resolve(name.device(), dev.addr);
+        }
+        for (id, service) in dev.service_data {
+            if let Ok(adv) = BleAdvertisement::decode_raw(&service) {
+                self.resolve_ble(&adv, dev.addr)?;
+            } else if let Ok(header) = BleHeader::decode_raw(&service) {
+                if header.bloom_filter.contains(SERVICE_ID_BLE) {
+                    self.handle_gatt(&header, dev.addr).await?;
                 }
+            } else {
+                debug!("Unknown service data for {}: {:X?}", id, service);
             }
         }
-        Ok(None)
-    }
-    async fn try_process_service_data(&mut self) -> RustdropResult<()> {
-        if let Some(services) = self.dev.service_data().await? {
-            self.process_service_data(services).await?;
-        }
         Ok(())
     }
-
-    async fn process_service_data(
-        &mut self,
-        service_data: HashMap<Uuid, Vec<u8>>,
-    ) -> RustdropResult<()> {
-        info!("Services: {:X?}", service_data);
-        for (id, service) in service_data {
-            info!("{}: {:?}", id, BleName::decode_base64(&service));
-            info!("{}: {:?}", id, BleName::decode_raw(&service));
-            info!("{}: {:?}", id, BluetoothName::decode_raw(&service[1..]));
-            info!(
-                "{}: {:?}",
-                id,
-                SocketControlFrame::decode(Bytes::copy_from_slice(&service))
-            );
-            info!(
-                "{}: {:?}",
-                id,
-                SocketControlFrame::decode_length_delimited(Bytes::from(service))
-            );
+    // Reads the advertisements the header in the service data pointed at.
+    async fn handle_gatt(&self, header: &BleHeader, addr: Address) -> RustdropResult<()> {
+        let mut slots: Vec<(usize, Vec<u8>)> = self
+            .adapter
+            .read_gatt(addr, SERVICE_UUID_RECIEVING)
+            .await?
+            .into_iter()
+            .filter_map(|(id, value)| Some((slot(id)?, value)))
+            .filter(|(slot, _)| *slot < header.slots())
+            .collect();
+        slots.sort_by_key(|(slot, _)| *slot);
+        let advertisements: Vec<Vec<u8>> = slots.into_iter().map(|(_, value)| value).collect();
+        if !header.matches(&advertisements) {
+            debug!("Advertisements of {} don't match their header", addr);
+            return Ok(());
+        }
+        if let Some(adv) = advertisements
+            .iter()
+            .find_map(|raw| BleAdvertisement::decode_raw(raw).ok())
+        {
+            self.resolve_ble(&adv, addr)?;
         }
         Ok(())
     }
-    async fn handle_recv_bt(&mut self) {}
-    #[tracing::instrument(fields(addr=%self.addr, name=self.name), skip_all)]
-
-    pub async fn handle_events(mut self) -> RustdropResult<()> {
-        if let Some(services) = self.dev.uuids().await? {
-            if !self.connect_profiles(services.clone()).await? {
-                return Ok(());
-            }
-        }
-        self.try_process_service_data().await?;
-        self.init().await?;
-        let mut events = self.dev.events().await?;
-        while let Some(event) = events.next().await {
-            match event {
-                PropertyChanged(DeviceProperty::ServiceData(data)) => {
-                    self.process_service_data(data).await?;
-                }
-                PropertyChanged(DeviceProperty::Uuids(uuids)) => {
-                    if !self.connect_profiles(uuids).await? {
-                        return Ok(());
-                    }
-                }
-                PropertyChanged(DeviceProperty::Connected(true)) => {
-                    info!("Connected!");
-                    self.init().await?
-                }
-                PropertyChanged(DeviceProperty::ServicesResolved(true)) => self.init().await?,
-                PropertyChanged(DeviceProperty::Rssi(_)) => {}
-                event => info!("{:?}", event),
-            };
-        }
+    fn resolve(&self, device: Device, addr: Address) {
+        info!("Found Bluetooth device {:?} at {}", device, addr);
+        let discovery = BluetoothDiscovery {
+            adapter: self.adapter.clone(),
+            addr,
+            service: SERVICE_UUID,
+        };
+        self.send.resolved(
+            device,
+            MediumType::Bluetooth,
+            vec![Discover::new(discovery)],
+        );
+    }
+    fn resolve_ble(&self, adv: &BleAdvertisement, addr: Address) -> RustdropResult<()> {
+        let (device, mac) = adv.device()?;
+        self.resolve(device, mac.map_or(addr, Address::new));
         Ok(())
     }
 }
-// pub async fn into_device(dev: bluer::Device, uuid: Uuid) -> RustdropResult<Device> {
-//     let mut name = dev.name().await?.unwrap_or(dev.alias().await?);
-//     let decoded = BluetoothName::decode_base64(name.as_bytes());
-//     info!("Name {}: {:?}", name, decoded);
-//     let device_type = DeviceType::Unknown;
-//     if let Some(services) = dev.service_data().await? {
-//         info!("Services: {:?}", services);
-//         if let Some(service) = services.get(&SERVICE_UUID_SHARING) {
-//             if let Ok(adv) = BleName::decode_base64(service) {
-//                 name = adv.name;
-//             }
-//         } else if let Some(service) = services.get(&SERVICE_UUID) {
-//             if let Ok(adv) = BluetoothName::decode_base64(service) {
-//                 name = adv.name;
-//             }
-//         } else if let Some(service) = services.get(&SERVICE_UUID_NEW) {
-//             if let Ok(adv) = BluetoothName::decode_base64(service) {
-//                 name = adv.name;
-//             }
-//         }
-//     }
-//     if let Ok(services) = dev.services().await {
-//         for service in services {
-//             if service.uuid().await? == SERVICE_UUID_RECIEVING {
-//                 info!("Discovered");
-//                 // TODO
-//             }
-//             for char in service.characteristics().await? {
-//                 info!("Char :{:?}", char);
-//             }
-//         }
-//     }
-//     if let Ok(service) = dev.service(SERVICE_UUID_RECIEVING.as_u16().unwrap()).await {
-//         for char in service.characteristics().await? {
-//             if char.uuid().await? == BLE_CHAR {
-//                 info!("Char :{:?}", char);
-//             }
-//         }
-//     } else {
-//     }
-//     let discovery = BluetoothDiscovery {
-//         addr: dev.address(),
-//         service: uuid,
-//     };
-//     Ok(Device {
-//         device_name: name,
-//         device_type,
-//         discovery: Discover::Bluetooth(discovery),
-//     })
-// }
-pub async fn handle_dev(
-    addr: Address,
-    adapter: &mut Adapter,
-    context: &Context,
-    send: &DiscoveringHandle,
-) -> RustdropResult<()> {
-    debug!("Handling {}", addr);
-    let dev = adapter.device(addr).unwrap();
-    let handle = DiscoveringBluetooth {
-        name: dev.name().await?.unwrap_or_default(),
-        dev,
-        addr,
-        send: send.clone(),
-        to_connect: [SERVICE_UUID_RECIEVING, SERVICE_UUID].into(),
-        // to_connect: [SERVICE_UUID].into(),
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env::temp_dir};
+
+    use uuid::uuid;
+
+    use super::*;
+    use crate::{
+        mediums::bt::{consts::BLE_CHAR, simulated::SimulatedRadio},
+        test_util::{discovered, next},
+        Config, Context, DiscoveryEvent,
     };
-    context.spawn(async move {
-        let e = handle.handle_events().await;
-        if e.is_err() {
-            info!("{:?}", e);
+
+    #[tokio::test]
+    async fn test_discovery() {
+        let context = Context::from(Config::with_dest(temp_dir()));
+        let by_name = Context::from(Config::with_dest(temp_dir()));
+        let by_ble = Context::from(Config::with_dest(temp_dir()));
+        let by_gatt = Context::from(Config::with_dest(temp_dir()));
+        let radio = SimulatedRadio::default();
+        let name_addr = Address::new([0, 0, 0, 0, 0, 1]);
+        let name = BluetoothName::new(by_name.endpoint_id(), by_name.endpoint_info()).to_base64();
+        let service_data = [(SERVICE_UUID, vec![])].into();
+        radio.add_device(name_addr, Some(name), service_data);
+        let adv = BleAdvertisement::fast(by_ble.endpoint_id(), by_ble.endpoint_info()).to_vec();
+        let service_data = [(SERVICE_UUID_RECIEVING, adv)].into();
+        radio.add_device(Address::new([0, 0, 0, 0, 0, 2]), None, service_data);
+        let gatt_addr = Address::new([0, 0, 0, 0, 0, 3]);
+        // The header covers both slots in order, whichever order they are read in
+        let first = BleAdvertisement::fast(by_gatt.endpoint_id(), by_gatt.endpoint_info()).to_vec();
+        let second = vec![0xff; 8];
+        let header = BleHeader::new(&[SERVICE_ID_BLE], &[&first, &second], 0).to_vec();
+        let characteristics = vec![
+            (uuid!("00000000-0000-3000-8000-000000000001"), second),
+            (uuid!("00000000-0000-1000-8000-00805f9b34fb"), vec![1, 2, 3]),
+            (BLE_CHAR, first),
+        ];
+        radio.add_gatt(gatt_addr, SERVICE_UUID_RECIEVING, characteristics);
+        radio.add_device(gatt_addr, None, [(SERVICE_UUID_RECIEVING, header)].into());
+        // Doesn't advertise anything we look for
+        radio.add_device(Address::new([0, 0, 0, 0, 0, 4]), None, HashMap::new());
+
+        let adapter = radio.adapter(Address::new([0, 0, 0, 0, 0, 9]));
+        let events = adapter
+            .scan([SERVICE_UUID, SERVICE_UUID_RECIEVING].into())
+            .await
+            .unwrap();
+        let (tx, rx) = flume::unbounded();
+        let handle = DiscoveringHandle::new(context.clone(), tx);
+        tokio::spawn(DiscoveringBluetooth::new(Arc::new(adapter), handle).run(events));
+        let mut found = HashMap::new();
+        for _ in 0..3 {
+            let handle = discovered(&rx).await;
+            found.insert(handle.device().endpoint_id, handle.routes());
         }
-    });
-    Ok(())
+        let expected = |addr: [u8; 6]| vec![Route::Bluetooth(Address::new(addr).to_string())];
+        assert_eq!(found[&by_name.endpoint_id()], expected([0, 0, 0, 0, 0, 1]));
+        assert_eq!(found[&by_ble.endpoint_id()], expected([0, 0, 0, 0, 0, 2]));
+        assert_eq!(found[&by_gatt.endpoint_id()], expected([0, 0, 0, 0, 0, 3]));
+
+        radio.remove_device(name_addr);
+        match next(&rx).await {
+            DiscoveryEvent::Removed(id) => assert_eq!(id, by_name.endpoint_id()),
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
 }
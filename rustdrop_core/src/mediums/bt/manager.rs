@@ -1,255 +1,164 @@
-use std::collections::HashSet;
+use std::sync::Arc;
 
-use bluer::{
-    monitor::{MonitorEvent, MonitorManager},
-    rfcomm::Profile,
-    Adapter, AdapterEvent, Device, DeviceEvent, DiscoveryFilter, Session, Uuid,
-};
-use bytes::Bytes;
 use flume::Sender;
-use futures::StreamExt;
-use tokio::{
-    select,
-    sync::mpsc::{self, UnboundedReceiver},
-};
+use futures::{future::BoxFuture, StreamExt};
 use tokio_util::sync::CancellationToken;
-use tracing::{info, trace};
+use tracing::{info, warn};
 
 use super::{
-    consts::{
-        SERVICE_DATA, SERVICE_ID_BLE, SERVICE_UUID, SERVICE_UUID_RECIEVING, SERVICE_UUID_SHARING,
-    },
-    BluetoothDiscovery,
+    adapter::BluetoothAdapter,
+    bluez::Bluez,
+    consts::{ALL_UUIDS, SERVICE_DATA, SERVICE_UUID, SERVICE_UUID_RECIEVING, SERVICE_UUID_SHARING},
+    discovery::DiscoveringBluetooth,
 };
 use crate::{
-    core::bits::{Bitfield, BluetoothName},
-    mediums::{
-        bt::{
-            ble::{get_advertisment, get_monitor, process_device},
-            consts::SERVICE_UUID_NEW,
-            discovery::handle_dev,
-        },
-        Medium,
-    },
-    runner::DiscoveringHandle,
+    core::bits::{Bitfield, BleAdvertisement, BluetoothName},
+    mediums::{recieve, Medium},
+    runner::{context::Advertised, DiscoveringHandle},
     Context, ReceiveEvent, RustdropResult,
 };
 
 pub(crate) struct Bluetooth {
-    session: Session,
-    adapter: Adapter,
+    adapter: Arc<dyn BluetoothAdapter>,
     context: Context,
-    monitor_manager: MonitorManager,
+}
+impl Medium for Bluetooth {
+    fn start_recieving(&mut self, send: Sender<ReceiveEvent>) -> BoxFuture<'_, RustdropResult<()>> {
+        Box::pin(async move {
+            self.advertise_receiving().await?;
+            self.adv_bt(send).await?;
+            Ok(())
+        })
+    }
+    fn discover(&mut self, send: DiscoveringHandle) -> BoxFuture<'_, RustdropResult<()>> {
+        Box::pin(async move {
+            self.trigger_reciever().await?;
+            self.discover_bt_recv(send).await?;
+            Ok(())
+        })
+    }
 }
 impl Bluetooth {
     pub async fn new(context: Context) -> RustdropResult<Self> {
-        let session = bluer::Session::new().await?;
-        let adapter = session.default_adapter().await?;
-        adapter.set_powered(true).await?;
-        let mm = adapter.monitor().await?;
-        Ok(Self {
-            session,
-            adapter,
+        let adapter = Bluez::new(context.clone()).await?;
+        Ok(Self::with_adapter(context, adapter))
+    }
+    pub(crate) fn with_adapter(context: Context, adapter: impl BluetoothAdapter) -> Self {
+        Self {
+            adapter: Arc::new(adapter),
             context,
-            monitor_manager: mm,
-        })
+        }
     }
-    async fn adv_profile(
-        &self,
-        profile: Profile,
-        name: String,
-        send: Sender<ReceiveEvent>,
-    ) -> RustdropResult<()> {
-        // self.adapter.set_discoverable(true).await?;
-        let mut handle = self.session.register_profile(profile).await?;
-        info!(
-            "Advertising on Bluetooth adapter {} with name {}",
-            self.adapter.name(),
-            name
-        );
+    pub(crate) async fn adv_bt(&mut self, send: Sender<ReceiveEvent>) -> RustdropResult<()> {
+        let advertised = self.context.advertised();
+        let name = BluetoothName::new(advertised.endpoint_id, advertised.info).to_base64();
+        let mut connections = self.adapter.listen(SERVICE_UUID, name).await?;
         let context = self.context.clone();
         self.context.spawn(async move {
-            while let Some(req) = handle.next().await {
-                info!("Received BLE request{:?}", req);
+            while let Some((rx, tx)) = connections.next().await {
                 let child = context.clone();
-                let (rx, tx) = req.accept().unwrap().into_split();
                 let send = send.clone();
                 context.spawn(async move {
-                    Self::recieve(rx, tx, child, send).await;
+                    recieve(rx, tx, child, send).await;
                 });
             }
             info!("No more requests");
         });
         Ok(())
     }
-    pub(crate) async fn adv_bt(&mut self, send: Sender<ReceiveEvent>) -> RustdropResult<()> {
-        // self.discover_bt().await?;
-        let name = BluetoothName::new(&self.context.config, self.context.endpoint_info.clone())
-            .to_base64();
-        let profile = Profile {
-            uuid: SERVICE_UUID,
-            role: Some(bluer::rfcomm::Role::Server),
-            name: Some(name.clone()),
-            require_authentication: Some(false),
-            require_authorization: Some(false),
-            channel: Some(0),
-            psm: Some(0),
-            auto_connect: Some(true),
-            ..Default::default()
-        };
-        self.adv_profile(profile, name, send).await?;
-        Ok(())
-    }
-    pub(crate) async fn discover_bt_send(&mut self, send: DiscoveringHandle) -> RustdropResult<()> {
-        let ids = [SERVICE_UUID_SHARING];
-        let filter = DiscoveryFilter {
-            uuids: HashSet::from(ids),
-            transport: bluer::DiscoveryTransport::Auto,
-            ..Default::default()
-        };
-        self.discover(filter, send, ids.into()).await?;
-        Ok(())
-    }
     pub(crate) async fn discover_bt_recv(&mut self, send: DiscoveringHandle) -> RustdropResult<()> {
         // When sharing, find devices which are receiving;
-        let ids = [
-            SERVICE_UUID_RECIEVING,
-            SERVICE_UUID_NEW,
-            SERVICE_UUID,
-            SERVICE_UUID_SHARING,
-        ];
-        let filter = DiscoveryFilter {
-            uuids: HashSet::from(ids),
-            transport: bluer::DiscoveryTransport::Auto,
-            discoverable: true,
-            ..Default::default()
-        };
-        self.discover(filter, send, ids.into()).await?;
+        let events = self.adapter.scan(ALL_UUIDS.into()).await?;
+        let discovering = DiscoveringBluetooth::new(self.adapter.clone(), send);
+        self.context.spawn(discovering.run(events));
         Ok(())
     }
-    async fn discover(
-        &mut self,
-        filter: DiscoveryFilter,
-        send: DiscoveringHandle,
-        allowed_ids: HashSet<Uuid>,
-    ) -> RustdropResult<()> {
-        self.adapter.set_discovery_filter(filter).await?;
-        let mut discover = self.adapter.discover_devices_with_changes().await?;
-        let mut adapter: Adapter = self.adapter.clone();
-        for addr in self.adapter.device_addresses().await? {
-            handle_dev(addr, &mut self.adapter, &self.context, &send).await?;
-        }
-        let child = self.context.clone();
-        self.context.spawn(async move {
-            while let Some(discovery) = discover.next().await {
-                trace!("{:?}", discovery);
-                match discovery {
-                    AdapterEvent::DeviceAdded(addr) => {
-                        handle_dev(addr, &mut adapter, &child, &send).await.unwrap();
-                    }
-                    AdapterEvent::DeviceRemoved(_) => {
-                        // send.send_async(DiscoveryEvent::Removed()).await.unwrap();
-                    }
-                    _ => (),
-                }
-            }
-        });
-        Ok(())
-    }
-    async fn advertise(
-        &mut self,
-        service_id: String,
-        service_uuid: Uuid,
-        adv_data: Bytes,
-    ) -> RustdropResult<CancellationToken> {
-        info!(
-            "Advertising on Bluetooth adapter {} with address {}",
-            self.adapter.name(),
-            self.adapter.address().await?
-        );
-        let le_advertisement = get_advertisment(service_id, service_uuid, adv_data);
-        let cancel = CancellationToken::new();
-        let c2 = cancel.child_token();
-        info!("{:?}", &le_advertisement);
-        let handle = self.adapter.advertise(le_advertisement).await?;
-        self.context.spawn(async move {
-            c2.cancelled().await;
-            info!("Removing advertisement");
-            drop(handle);
-        });
-        Ok(cancel)
-    }
-    async fn scan_le(
-        &mut self,
-        services: Vec<Uuid>,
-    ) -> RustdropResult<(UnboundedReceiver<Device>, UnboundedReceiver<DeviceEvent>)> {
-        let mut monitor_handle = self.monitor_manager.register(get_monitor(services)).await?;
-        info!("Scanning BLE");
-        let (devices_tx, devices_rx) = mpsc::unbounded_channel();
-        let (events_tx, events_rx) = mpsc::unbounded_channel();
+    // Lets senders scanning over BLE find us, advertising anew when what we advertise changes.
+    pub async fn advertise_receiving(&mut self) -> RustdropResult<()> {
+        let mut changes = self.context.watch_advertised();
+        let advertised = self.context.advertised();
+        let mut advertising = advertise_info(&*self.adapter, advertised).await?;
         let adapter = self.adapter.clone();
+        let context = self.context.clone();
         self.context.spawn(async move {
-            while let Some(mevt) = monitor_handle.next().await {
-                if let MonitorEvent::DeviceFound(devid) = mevt {
-                    info!("Discovered device {:?}", devid);
-                    let dev = adapter.device(devid.device).unwrap();
-                    process_device(dev, &devices_tx, &events_tx).await;
-                }
-            }
-            info!("Closing BLE scan");
-        });
-        Ok((devices_rx, events_rx))
-    }
-    pub async fn scan_for_incoming(&mut self) -> RustdropResult<()> {
-        self.advertise(SERVICE_ID_BLE.into(), SERVICE_UUID_RECIEVING, SERVICE_DATA)
-            .await?;
-        let (mut devices, mut events) = self.scan_le(vec![SERVICE_UUID_SHARING]).await?;
-        self.context.spawn(async move {
-            loop {
-                select! {
-                    Some(dev) = devices.recv() => {
-                        info!("{:?}", dev)
-                    }
-                    Some(event) = events.recv() => {
-                        info!("{:?}", event)
-                    }
-                    else => break
+            while let Some(advertised) = context.advertised_change(&mut changes).await {
+                advertising.cancel();
+                match advertise_info(&*adapter, advertised).await {
+                    Ok(token) => advertising = token,
+                    Err(e) => warn!("Unable to advertise over BLE anew: {}", e),
                 }
             }
+            advertising.cancel();
         });
         Ok(())
     }
     pub async fn trigger_reciever(&mut self) -> RustdropResult<()> {
-        self.advertise(SERVICE_ID_BLE.into(), SERVICE_UUID_SHARING, SERVICE_DATA)
+        self.adapter
+            .advertise(SERVICE_UUID_SHARING, SERVICE_DATA)
             .await?;
-        let (mut devices, mut events) = self.scan_le(vec![SERVICE_UUID_RECIEVING]).await?;
-        self.context.spawn(async move {
-            loop {
-                select! {
-                    Some(dev) = devices.recv() => {
-                        info!("{:?}", dev)
-                    }
-                    Some(event) = events.recv() => {
-                        info!("{:?}", event)
-                    }
-                    else => break
-                }
-            }
-        });
         Ok(())
     }
 }
-impl Medium for Bluetooth {
-    type Discovery = BluetoothDiscovery;
-    async fn start_recieving(&mut self, send: Sender<ReceiveEvent>) -> RustdropResult<()> {
-        self.scan_for_incoming().await?;
-        self.adv_bt(send).await?;
-        Ok(())
-    }
-    async fn discover(&mut self, send: DiscoveringHandle) -> RustdropResult<()> {
-        self.trigger_reciever().await?;
-        // self.adv_bt_recv().await?;
-        self.discover_bt_recv(send).await?;
-        Ok(())
+async fn advertise_info(
+    adapter: &dyn BluetoothAdapter,
+    advertised: Advertised,
+) -> RustdropResult<CancellationToken> {
+    let adv = BleAdvertisement::fast(advertised.endpoint_id, advertised.info);
+    adapter
+        .advertise(SERVICE_UUID_RECIEVING, adv.to_vec().into())
+        .await
+}
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bluer::Address;
+    use tokio::{fs, runtime::Handle, time::timeout};
+
+    use super::*;
+    use crate::{
+        mediums::bt::simulated::SimulatedRadio,
+        test_util::{discovered, read_when_written, temp_dir},
+        Config, Outgoing, TransferOutcome,
+    };
+
+    #[tokio::test]
+    async fn test_transfer() {
+        let dir = temp_dir("rustdrop_bt_transfer").await;
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Hello over Bluetooth").await.unwrap();
+        let radio = SimulatedRadio::default();
+
+        let receiver = Context::from(Config::with_dest(dir.join("received")));
+        let mut medium =
+            Bluetooth::with_adapter(receiver.clone(), radio.adapter(Address::new([1; 6])));
+        let (tx, requests) = flume::unbounded();
+        medium.start_recieving(tx).await.unwrap();
+        tokio::spawn(async move {
+            while let Ok(event) = requests.recv_async().await {
+                if let ReceiveEvent::PairingRequest(request) = event {
+                    request.respond(true);
+                }
+            }
+        });
+
+        let sender = Context::from(Config::with_dest(dir.clone()));
+        let mut medium =
+            Bluetooth::with_adapter(sender.clone(), radio.adapter(Address::new([2; 6])));
+        let (tx, events) = flume::unbounded();
+        medium
+            .discover(DiscoveringHandle::new(sender.clone(), tx))
+            .await
+            .unwrap();
+        let found = discovered(&events).await;
+        assert_eq!(found.device().endpoint_id, receiver.endpoint_id());
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let transfer = found.send_file(outgoing, &Handle::current()).unwrap();
+        let outcome = timeout(Duration::from_secs(10), transfer.outcome()).await;
+        assert_eq!(outcome.unwrap(), TransferOutcome::Completed);
+        let received = dir.join("received").join("hello.txt");
+        assert_eq!(read_when_written(received).await, "Hello over Bluetooth");
     }
 }
@@ -0,0 +1,203 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use bluer::Address;
+use bytes::Bytes;
+use flume::Sender;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use tokio::io::{duplex, split};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use super::adapter::{BluetoothAdapter, Characteristics, Connection, NearbyDevice, ScanEvent};
+use crate::{
+    core::RustdropError,
+    mediums::{ReadHalf, WriteHalf},
+    RustdropResult,
+};
+
+#[derive(Debug, Default)]
+struct SimulatedDevice {
+    name: Option<String>,
+    service_data: HashMap<Uuid, Vec<u8>>,
+    gatt: HashMap<Uuid, Characteristics>,
+    listeners: HashMap<Uuid, Sender<Connection>>,
+}
+impl SimulatedDevice {
+    fn visible(&self, services: &HashSet<Uuid>) -> bool {
+        self.service_data
+            .keys()
+            .chain(self.listeners.keys())
+            .any(|id| services.contains(id))
+    }
+}
+#[derive(Debug)]
+struct Scanner {
+    owner: Address,
+    services: HashSet<Uuid>,
+    seen: HashSet<Address>,
+    send: Sender<ScanEvent>,
+}
+impl Scanner {
+    fn update(&mut self, addr: Address, dev: Option<&SimulatedDevice>) {
+        if addr == self.owner {
+            return;
+        }
+        let event = match dev {
+            Some(dev) if dev.visible(&self.services) => {
+                self.seen.insert(addr);
+                ScanEvent::Found(NearbyDevice {
+                    addr,
+                    name: dev.name.clone(),
+                    service_data: dev.service_data.clone(),
+                })
+            }
+            _ if self.seen.remove(&addr) => ScanEvent::Lost(addr),
+            _ => return,
+        };
+        let _ = self.send.send(event);
+    }
+}
+#[derive(Debug, Default)]
+struct Air {
+    devices: HashMap<Address, SimulatedDevice>,
+    scanners: Vec<Scanner>,
+}
+impl Air {
+    fn change(&mut self, addr: Address, change: impl FnOnce(&mut SimulatedDevice)) {
+        change(self.devices.entry(addr).or_default());
+        let dev = self.devices.get(&addr);
+        self.scanners
+            .retain(|scanner| !scanner.send.is_disconnected());
+        for scanner in self.scanners.iter_mut() {
+            scanner.update(addr, dev);
+        }
+    }
+}
+// In-memory stand-in for the radio, shared by every simulated adapter.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SimulatedRadio(Arc<Mutex<Air>>);
+impl SimulatedRadio {
+    pub fn adapter(&self, addr: Address) -> SimulatedAdapter {
+        SimulatedAdapter {
+            radio: self.clone(),
+            addr,
+        }
+    }
+    // A device which only advertises, like one not running Nearby Share.
+    pub fn add_device(
+        &self,
+        addr: Address,
+        name: Option<String>,
+        service_data: HashMap<Uuid, Vec<u8>>,
+    ) {
+        self.0.lock().unwrap().change(addr, |dev| {
+            dev.name = name;
+            dev.service_data = service_data;
+        });
+    }
+    pub fn add_gatt(&self, addr: Address, service: Uuid, characteristics: Characteristics) {
+        self.0.lock().unwrap().change(addr, |dev| {
+            dev.gatt.insert(service, characteristics);
+        });
+    }
+    pub fn remove_device(&self, addr: Address) {
+        let mut air = self.0.lock().unwrap();
+        air.devices.remove(&addr);
+        for scanner in air.scanners.iter_mut() {
+            scanner.update(addr, None);
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub(crate) struct SimulatedAdapter {
+    radio: SimulatedRadio,
+    addr: Address,
+}
+impl BluetoothAdapter for SimulatedAdapter {
+    fn advertise(
+        &self,
+        service: Uuid,
+        data: Bytes,
+    ) -> BoxFuture<'_, RustdropResult<CancellationToken>> {
+        Box::pin(async move {
+            self.radio.0.lock().unwrap().change(self.addr, |dev| {
+                dev.service_data.insert(service, data.to_vec());
+            });
+            let cancel = CancellationToken::new();
+            let cancelled = cancel.child_token();
+            let radio = self.radio.clone();
+            let addr = self.addr;
+            tokio::spawn(async move {
+                cancelled.cancelled().await;
+                radio.0.lock().unwrap().change(addr, |dev| {
+                    dev.service_data.remove(&service);
+                });
+            });
+            Ok(cancel)
+        })
+    }
+    fn scan(
+        &self,
+        services: HashSet<Uuid>,
+    ) -> BoxFuture<'_, RustdropResult<BoxStream<'static, ScanEvent>>> {
+        Box::pin(async move {
+            let (send, recv) = flume::unbounded();
+            let mut scanner = Scanner {
+                owner: self.addr,
+                services,
+                seen: HashSet::new(),
+                send,
+            };
+            let mut air = self.radio.0.lock().unwrap();
+            for (addr, dev) in air.devices.iter() {
+                scanner.update(*addr, Some(dev));
+            }
+            air.scanners.push(scanner);
+            Ok(recv.into_stream().boxed())
+        })
+    }
+    fn read_gatt(
+        &self,
+        addr: Address,
+        service: Uuid,
+    ) -> BoxFuture<'_, RustdropResult<Characteristics>> {
+        Box::pin(async move {
+            let air = self.radio.0.lock().unwrap();
+            let dev = air.devices.get(&addr).ok_or(RustdropError::Connection())?;
+            Ok(dev.gatt.get(&service).cloned().unwrap_or_default())
+        })
+    }
+    fn listen(
+        &self,
+        service: Uuid,
+        _name: String,
+    ) -> BoxFuture<'_, RustdropResult<BoxStream<'static, Connection>>> {
+        Box::pin(async move {
+            let (send, recv) = flume::unbounded();
+            self.radio.0.lock().unwrap().change(self.addr, |dev| {
+                dev.listeners.insert(service, send);
+            });
+            Ok(recv.into_stream().boxed())
+        })
+    }
+    fn connect(&self, addr: Address, service: Uuid) -> BoxFuture<'_, RustdropResult<Connection>> {
+        Box::pin(async move {
+            let air = self.radio.0.lock().unwrap();
+            let listener = air
+                .devices
+                .get(&addr)
+                .and_then(|dev| dev.listeners.get(&service))
+                .ok_or(RustdropError::Connection())?;
+            let (client, server) = duplex(64 * 1024);
+            let (server_rx, server_tx) = split(server);
+            listener
+                .send((Box::new(server_rx), Box::new(server_tx)))
+                .map_err(|_| RustdropError::Connection())?;
+            let (rx, tx) = split(client);
+            Ok((Box::new(rx) as ReadHalf, Box::new(tx) as WriteHalf))
+        })
+    }
+}
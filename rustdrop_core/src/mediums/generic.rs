@@ -1,58 +1,158 @@
 mod receiver;
+mod replay;
 mod sender;
 mod socket;
 
-use std::{fmt::Debug, hash::Hash};
+use std::{
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
 
 use flume::Sender;
+use futures::future::BoxFuture;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::error;
 
 use self::{receiver::GenericReciever, sender::GenericSender};
 use crate::{
-    core::io::{reader::ReaderRecv, writer::WriterSend},
+    core::{
+        io::{reader::ReaderRecv, writer::WriterSend},
+        Session,
+    },
+    mediums::{MediumType, Route},
     runner::DiscoveringHandle,
-    Context, Outgoing, ReceiveEvent, RustdropResult, SenderEvent,
+    Context, DeviceType, Outgoing, ReceiveEvent, RustdropResult, SenderEvent,
 };
 
-pub trait Discovery: Debug + Clone + PartialEq + Hash + Eq + 'static {
-    async fn into_socket(
-        self,
-    ) -> RustdropResult<(
-        impl AsyncRead + Send + Sync + Unpin,
-        impl AsyncWrite + Send + Sync + Unpin,
-    )>;
-    async fn send_to(
-        self,
+pub type ReadHalf = Box<dyn AsyncRead + Send + Sync + Unpin>;
+pub type WriteHalf = Box<dyn AsyncWrite + Send + Sync + Unpin>;
+// A way to reach a discovered device.
+pub trait Discovery: Debug + Send + Sync + 'static {
+    // Identifies the discovery, two discoveries with the same route are the same.
+    fn route(&self) -> Route;
+    fn connect(&self) -> BoxFuture<'_, RustdropResult<(ReadHalf, WriteHalf)>>;
+}
+#[derive(Debug, Clone)]
+pub struct Discover(Arc<dyn Discovery>);
+impl Discover {
+    pub fn new(discovery: impl Discovery) -> Self {
+        Self(Arc::new(discovery))
+    }
+    pub fn medium(&self) -> MediumType {
+        self.route().medium()
+    }
+    pub fn route(&self) -> Route {
+        self.0.route()
+    }
+    pub(crate) async fn send_to(
+        &self,
         context: Context,
         outgoing: Outgoing,
         send: Sender<SenderEvent>,
     ) -> RustdropResult<()> {
-        let (rx, tx) = self.into_socket().await?;
+        let (rx, tx) = self.0.connect().await?;
+        send.send_async(SenderEvent::Connected(self.route()))
+            .await
+            .unwrap();
+        send_over(rx, tx, context, outgoing, send).await
+    }
+    pub(crate) async fn probe(&self, context: Context) -> RustdropResult<DeviceType> {
+        let (rx, tx) = self.0.connect().await?;
         let reader = ReaderRecv::new(rx, &context);
         let writer = WriterSend::new(tx, &context);
-        GenericSender::send_to(context, reader, writer, outgoing, send).await?;
-        Ok(())
-    }
-}
-pub trait Medium {
-    type Discovery: Discovery;
-    async fn discover(&mut self, send: DiscoveringHandle) -> RustdropResult<()>;
-    async fn start_recieving(&mut self, send: Sender<ReceiveEvent>) -> RustdropResult<()>;
-    async fn recieve<
-        R: AsyncRead + Unpin + Send + 'static,
-        W: AsyncWrite + Unpin + Send + 'static,
-    >(
-        rx: R,
-        tx: W,
+        GenericSender::probe(context, reader, writer).await
+    }
+}
+impl PartialEq for Discover {
+    fn eq(&self, other: &Self) -> bool {
+        self.route() == other.route()
+    }
+}
+impl Eq for Discover {}
+impl Hash for Discover {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.route().hash(state)
+    }
+}
+// A transport which devices can be discovered and reached over.
+pub trait Medium: Send {
+    fn discover(&mut self, send: DiscoveringHandle) -> BoxFuture<'_, RustdropResult<()>>;
+    fn start_recieving(&mut self, send: Sender<ReceiveEvent>) -> BoxFuture<'_, RustdropResult<()>>;
+}
+// Run the receiving side of the protocol over an accepted connection.
+pub async fn recieve<
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+>(
+    rx: R,
+    tx: W,
+    context: Context,
+    send: Sender<ReceiveEvent>,
+) {
+    let reader = ReaderRecv::new(rx, &context);
+    let writer = WriterSend::new(tx, &context);
+    let session = Session::new(&context);
+    let res = GenericReciever::recieve(reader, writer, context, send, session).await;
+    if let Err(e) = res {
+        error!("{:?}", e);
+    }
+}
+// Run the sending side of the protocol over a connection.
+pub(crate) async fn send_over<
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+>(
+    rx: R,
+    tx: W,
+    context: Context,
+    outgoing: Outgoing,
+    send: Sender<SenderEvent>,
+) -> RustdropResult<()> {
+    let reader = ReaderRecv::new(rx, &context);
+    let writer = WriterSend::new(tx, &context);
+    let session = Session::new(&context);
+    GenericSender::send_to(context, reader, writer, outgoing, send, session).await
+}
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use tokio::io::{duplex, split};
+
+    use super::*;
+    use crate::Config;
+
+    // An in-memory transport, standing in for one added by an application.
+    #[derive(Debug)]
+    struct Pipe {
         context: Context,
-        send: Sender<ReceiveEvent>,
-    ) {
-        let reader = ReaderRecv::new(rx, &context);
-        let writer = WriterSend::new(tx, &context);
-        let res = GenericReciever::recieve(reader, writer, context, send).await;
-        if let Err(e) = res {
-            error!("{:?}", e);
+    }
+    impl Discovery for Pipe {
+        fn route(&self) -> Route {
+            Route::Custom("pipe", "local".into())
+        }
+        fn connect(&self) -> BoxFuture<'_, RustdropResult<(ReadHalf, WriteHalf)>> {
+            Box::pin(async move {
+                let (client, server) = duplex(64 * 1024);
+                let (server_rx, server_tx) = split(server);
+                let (events, _rx) = flume::unbounded();
+                let context = self.context.clone();
+                tokio::spawn(recieve(server_rx, server_tx, context, events));
+                let (rx, tx) = split(client);
+                Ok((Box::new(rx) as ReadHalf, Box::new(tx) as WriteHalf))
+            })
         }
     }
+    #[tokio::test]
+    async fn test_custom_discovery() {
+        let context = Context::from(Config::with_dest(temp_dir()));
+        let discover = Discover::new(Pipe {
+            context: context.clone(),
+        });
+        assert_eq!(discover.medium(), MediumType::Custom("pipe"));
+        assert_eq!(discover.clone(), discover);
+        let device_type = discover.probe(context).await.unwrap();
+        assert_eq!(device_type, DeviceType::Laptop);
+    }
 }
@@ -0,0 +1,227 @@
+use std::{future::Future, time::Duration};
+
+use bytes::Bytes;
+use flume::Sender;
+use tokio::{
+    io::{duplex, split, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    join,
+    time::timeout,
+};
+
+use super::{receiver::GenericReciever, sender::GenericSender};
+use crate::{
+    core::{
+        capture::Direction,
+        io::{reader::ReaderRecv, writer::WriterSend},
+        RustdropError, Session,
+    },
+    Context, Outgoing, ReceiveEvent, RustdropResult, SenderEvent, Transcript,
+};
+
+const BUFFER: usize = 1024 * 1024;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Replays draw the recorded randomness, keys and IVs, so the side being replayed derives the keys it
+// did before and should put exactly the frames it recorded on the wire.
+impl Transcript {
+    // Plays the recorded sender against a receiver, returning the frames the receiver sent.
+    pub async fn replay_receiving(
+        &self,
+        context: Context,
+        events: Sender<ReceiveEvent>,
+    ) -> RustdropResult<Vec<Bytes>> {
+        let (ours, peer) = duplex(BUFFER);
+        let (rx, tx) = split(ours);
+        let reader = ReaderRecv::new(rx, &context);
+        let writer = WriterSend::new(tx, &context);
+        let session = Session::replay(self);
+        let receiving = GenericReciever::recieve(reader, writer, context, events, session.clone());
+        self.play(receiving, peer, session).await
+    }
+    // Plays the recorded receiver against a sender, returning the frames the sender sent.
+    pub async fn replay_sending(
+        &self,
+        context: Context,
+        outgoing: Outgoing,
+        events: Sender<SenderEvent>,
+    ) -> RustdropResult<Vec<Bytes>> {
+        let (ours, peer) = duplex(BUFFER);
+        let (rx, tx) = split(ours);
+        let reader = ReaderRecv::new(rx, &context);
+        let writer = WriterSend::new(tx, &context);
+        let session = Session::replay(self);
+        let sending =
+            GenericSender::send_to(context, reader, writer, outgoing, events, session.clone());
+        self.play(sending, peer, session).await
+    }
+    async fn play(
+        &self,
+        ours: impl Future<Output = RustdropResult<()>>,
+        peer: DuplexStream,
+        session: Session,
+    ) -> RustdropResult<Vec<Bytes>> {
+        let (res, sent) = join!(ours, self.script(peer));
+        if let Some(diverged) = session.diverged() {
+            Err(RustdropError::ReplayDiverged(diverged))?;
+        }
+        res?;
+        sent
+    }
+    // Stands in for the other side, writing what it sent and reading what it got back.
+    // The connection is closed once the transcript runs out.
+    async fn script(&self, mut peer: DuplexStream) -> RustdropResult<Vec<Bytes>> {
+        let mut sent = Vec::new();
+        for (direction, data) in self.wire() {
+            match direction {
+                Direction::Received => {
+                    peer.write_i32(data.len().try_into()?).await?;
+                    peer.write_all(&data).await?;
+                }
+                Direction::Sent => {
+                    let Ok(size) = timeout(REPLY_TIMEOUT, peer.read_i32()).await else {
+                        return Err(RustdropError::InvalidMessage(format!(
+                            "Replay stopped after {} frames",
+                            sent.len()
+                        ))
+                        .into());
+                    };
+                    let mut frame = vec![0; size?.try_into()?];
+                    peer.read_exact(&mut frame).await?;
+                    sent.push(frame.into());
+                }
+            }
+        }
+        Ok(sent)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use flume::Receiver;
+    use tokio::{
+        fs,
+        io::{duplex, split},
+    };
+
+    use super::*;
+    use crate::{
+        core::{capture::Entry, identity::Identity},
+        test_util::temp_dir,
+        Config, Rustdrop,
+    };
+
+    // Recorded by `record_captures`, the sender shares hello.txt with the receiver.
+    const RECEIVING: &str = include_str!("captures/receiving.capture");
+    const SENDING: &str = include_str!("captures/sending.capture");
+
+    // The sender puts its endpoint id and info on the wire, so they are kept the same.
+    async fn instance(dir: &Path, endpoint_id: &[u8; 4], capture: Option<&Path>) -> Rustdrop {
+        let mut identity = Identity::generate();
+        identity.endpoint_id = u32::from_be_bytes(*endpoint_id);
+        identity.salt = Bytes::from_static(b"0123456789abcdef");
+        let state = dir.join(format!("{}.identity", identity.endpoint_id));
+        identity.save(&state).unwrap();
+        let mut config = Config::with_dest(dir.join("received"));
+        config.name = "Replayed".into();
+        config.mediums = Vec::new();
+        config.state = Some(state);
+        config.capture = capture.map(Path::to_path_buf);
+        Rustdrop::new(config).await.unwrap()
+    }
+    async fn outgoing(dir: &Path) -> Outgoing {
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Hello again").await.unwrap();
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        outgoing
+    }
+    // Accepts every request until the session ends.
+    async fn accept(events: Receiver<ReceiveEvent>) {
+        while let Ok(event) = events.recv_async().await {
+            if let ReceiveEvent::PairingRequest(request) = event {
+                request.respond(true);
+            }
+        }
+    }
+    async fn drain<T>(events: Receiver<T>) {
+        while events.recv_async().await.is_ok() {}
+    }
+    async fn transcript(dir: &Path) -> String {
+        let mut entries = fs::read_dir(dir).await.unwrap();
+        let entry = entries.next_entry().await.unwrap().unwrap();
+        assert!(entries.next_entry().await.unwrap().is_none());
+        fs::read_to_string(entry.path()).await.unwrap()
+    }
+    // Run with `--ignored` to record the captures again once the protocol changes.
+    #[tokio::test]
+    #[ignore]
+    async fn record_captures() {
+        let dir = temp_dir("rustdrop_record").await;
+        let to = instance(&dir, b"RcvR", Some(&dir.join("to"))).await;
+        let from = instance(&dir, b"SndR", Some(&dir.join("from"))).await;
+        let (a, b) = duplex(64 * 1024);
+        let (a_rx, a_tx) = split(a);
+        let (b_rx, b_tx) = split(b);
+        let receiving = accept(to.receive_on(a_rx, a_tx));
+        let transfer = from.send_over(b_rx, b_tx, outgoing(&dir).await);
+        let sending = drain(transfer.events().clone());
+        timeout(Duration::from_secs(10), async { join!(receiving, sending) })
+            .await
+            .unwrap();
+        let captures = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/mediums/generic/captures");
+        fs::create_dir_all(&captures).await.unwrap();
+        let receiving = transcript(&dir.join("to")).await;
+        fs::write(captures.join("receiving.capture"), receiving)
+            .await
+            .unwrap();
+        let sending = transcript(&dir.join("from")).await;
+        fs::write(captures.join("sending.capture"), sending)
+            .await
+            .unwrap();
+    }
+    #[tokio::test]
+    async fn test_replay() {
+        let dir = temp_dir("rustdrop_replay").await;
+        let receiving: Transcript = RECEIVING.parse().unwrap();
+        let to = instance(&dir, b"RcvR", None).await;
+        let (tx, events) = flume::unbounded();
+        tokio::spawn(accept(events));
+        let replayed = receiving
+            .replay_receiving(to.context().clone(), tx)
+            .await
+            .unwrap();
+        assert_eq!(replayed, receiving.sent());
+
+        let sending: Transcript = SENDING.parse().unwrap();
+        let from = instance(&dir, b"SndR", None).await;
+        let (tx, events) = flume::unbounded();
+        let replayed = sending
+            .replay_sending(from.context().clone(), outgoing(&dir).await, tx)
+            .await
+            .unwrap();
+        drain(events).await;
+        assert_eq!(replayed, sending.sent());
+    }
+    #[tokio::test]
+    async fn test_diverged() {
+        let dir = temp_dir("rustdrop_replay_diverged").await;
+        let mut receiving: Transcript = RECEIVING.parse().unwrap();
+        let last_iv = receiving
+            .records
+            .iter()
+            .rposition(|record| matches!(record.entry, Entry::Iv(_)))
+            .unwrap();
+        receiving.records.remove(last_iv);
+        let to = instance(&dir, b"RcvR", None).await;
+        let (tx, events) = flume::unbounded();
+        tokio::spawn(accept(events));
+        let res = receiving.replay_receiving(to.context().clone(), tx).await;
+        let e = res.unwrap_err().to_string();
+        assert!(
+            e.contains("Drew an IV where the transcript has nothing left"),
+            "{}",
+            e
+        );
+    }
+}
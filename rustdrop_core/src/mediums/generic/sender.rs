@@ -10,22 +10,27 @@ use crate::RustdropResult;
 use crate::{
     core::{
         handlers::{
-            offline::{get_con_request, get_conn_response},
+            offline::{get_con_request, get_conn_response, guess_device_type},
             transfer::process_transfer_response,
             ukey::get_ukey_init_finish,
         },
         io::{reader::ReaderRecv, writer::WriterSend},
-        protocol::{get_paired_frame, get_paired_result},
-        ukey2::{get_public, Crypto, CryptoImpl, Ukey2},
+        protocol::{get_paired_frame, get_paired_result, qr_code_handshake_data},
+        ukey2::{get_pin, get_public, Crypto, CryptoImpl, Ukey2},
+        RustdropError, Session,
     },
-    protobuf::securegcm::{ukey2_message::Type, Ukey2Message, Ukey2ServerInit},
-    Context, Outgoing, SenderEvent,
+    protobuf::{
+        location::nearby::connections::OfflineFrame,
+        securegcm::{ukey2_message::Type, Ukey2Message, Ukey2ServerInit},
+    },
+    Context, DeviceType, Outgoing, SenderEvent,
 };
 
 pub struct GenericSender {
     stream_handler: StreamHandler,
     context: Context,
     outgoing: Outgoing,
+    auth_string: Bytes,
     send: Sender<SenderEvent>,
 }
 impl GenericSender {
@@ -35,21 +40,50 @@ impl GenericSender {
         writer: WriterSend,
         outgoing: Outgoing,
         send: Sender<SenderEvent>,
+        session: Session,
     ) -> RustdropResult<()> {
         let sender = GenericSender {
-            stream_handler: StreamHandler::new(reader, writer, context.clone()),
+            stream_handler: StreamHandler::new(reader, writer, context.clone(), session),
             context,
             outgoing,
+            auth_string: Bytes::new(),
             send,
         };
         sender.run().await?;
         Ok(())
     }
+    // Only run the handshake, to learn about a device which was not discovered
+    pub(crate) async fn probe(
+        context: Context,
+        reader: ReaderRecv,
+        writer: WriterSend,
+    ) -> RustdropResult<DeviceType> {
+        let (tx, _rx) = flume::unbounded();
+        let session = Session::new(&context);
+        let mut sender = GenericSender {
+            stream_handler: StreamHandler::new(reader, writer, context.clone(), session),
+            context,
+            outgoing: Outgoing::default(),
+            auth_string: Bytes::new(),
+            send: tx,
+        };
+        let (init_raw, finish, key) = sender.handle_init().await?;
+        let response = sender
+            .handle_ukey2_exchange(init_raw, finish, key)
+            .await?
+            .v1
+            .and_then(|v1| v1.connection_response)
+            .ok_or_else(|| {
+                RustdropError::InvalidMessage("Expected a connection response".into())
+            })?;
+        sender.stream_handler.send_disconnect();
+        Ok(guess_device_type(&response))
+    }
     async fn handle_init(
         &mut self,
     ) -> RustdropResult<(Bytes, Ukey2Message, <CryptoImpl as Crypto>::SecretKey)> {
-        let init = get_con_request(self.context.endpoint_info.clone());
-        let (ukey_init, finish, key) = get_ukey_init_finish();
+        let init = get_con_request(self.context.endpoint_id(), self.context.endpoint_info());
+        let (ukey_init, finish, key) = get_ukey_init_finish(self.stream_handler.session());
         self.stream_handler.send(&init).await;
         let init_raw = self
             .stream_handler
@@ -63,25 +97,43 @@ impl GenericSender {
         init_raw: Bytes,
         finish: Ukey2Message,
         key: EcKey<Private>,
-    ) -> RustdropResult<()> {
+    ) -> RustdropResult<OfflineFrame> {
         let (server_resp, resp_raw): (Ukey2ServerInit, Bytes) =
             self.stream_handler.next_ukey_message().await?;
         debug!("Recived message {:#?}", server_resp);
         let server_key = get_public::<CryptoImpl>(server_resp.public_key());
-        let (ukey2_send, ukey2_recv) = Ukey2::new(init_raw, key, resp_raw, server_key, true);
+        let (ukey2_send, ukey2_recv, auth_string) =
+            Ukey2::new(init_raw, key, resp_raw, server_key, true);
+        self.send
+            .send_async(SenderEvent::Pin(get_pin(&auth_string)))
+            .await
+            .unwrap();
+        self.auth_string = auth_string;
         self.stream_handler.send(&finish).await;
-        let _connection_response = self.stream_handler.next_offline().await?;
-        let c_frame = get_conn_response();
+        let connection_response = self.stream_handler.next_offline().await?;
+        let c_frame = get_conn_response(self.stream_handler.session());
         self.stream_handler.send(&c_frame).await;
-        debug!("Recived message {:#?}", _connection_response);
+        debug!("Recived message {:#?}", connection_response);
         self.stream_handler
             .setup_ukey2(ukey2_send, ukey2_recv)
             .await;
-        Ok(())
+        Ok(connection_response)
     }
     async fn handle_pairing(&mut self) -> RustdropResult<()> {
-        let _server_resp = self.stream_handler.next_payload().await?;
-        let p_frame = get_paired_frame();
+        let server_resp = self.stream_handler.next_payload().await?;
+        let handshake_data = match self.outgoing.qr_code() {
+            Some(code) => {
+                let signature = qr_code_handshake_data(server_resp).unwrap_or_default();
+                if !code.verify(&self.auth_string, &signature) {
+                    Err(RustdropError::InvalidQrCode(
+                        "Receiver did not sign with the scanned key".into(),
+                    ))?;
+                }
+                Some(code.handshake_data(&self.auth_string).to_vec())
+            }
+            None => None,
+        };
+        let p_frame = get_paired_frame(self.stream_handler.session(), handshake_data);
         self.stream_handler.send_payload(&p_frame);
         let _server_resp = self.stream_handler.next_payload().await?;
         let p_res = get_paired_result();
@@ -92,7 +144,7 @@ impl GenericSender {
         let (init_raw, finish, key) = self.handle_init().await?;
         self.handle_ukey2_exchange(init_raw, finish, key).await?;
         self.handle_pairing().await?;
-        let (intro, payload) = self.outgoing.get_frames();
+        let (intro, payload) = self.outgoing.get_frames(self.stream_handler.session());
         pin_mut!(payload);
         self.send
             .send_async(SenderEvent::AwaitingResponse())
@@ -100,19 +152,57 @@ impl GenericSender {
             .unwrap();
         self.stream_handler.send_payload(&intro);
         let frame = self.stream_handler.next_payload().await?;
-        let resp = process_transfer_response(frame);
-        if resp {
-            self.send.send_async(SenderEvent::Accepted()).await.unwrap();
-            while let Some((id, data)) = payload.next().await {
-                self.stream_handler.send_payload_raw(data, id)
+        match process_transfer_response(frame) {
+            Ok(()) => {
+                self.send.send_async(SenderEvent::Accepted()).await.unwrap();
+                let mut sent = 0;
+                while let Some(chunk) = payload.next().await {
+                    let chunk = chunk?;
+                    let len = chunk.data.len() as u64;
+                    self.stream_handler.send_chunk(chunk);
+                    if len > 0 {
+                        sent += len;
+                        let event = SenderEvent::Progress(sent);
+                        self.send.send_async(event).await.unwrap();
+                    }
+                }
+                self.send.send_async(SenderEvent::Finished()).await.unwrap();
+            }
+            Err(reason) => {
+                self.send
+                    .send_async(SenderEvent::Rejected(reason))
+                    .await
+                    .unwrap();
             }
-            self.send.send_async(SenderEvent::Finished()).await.unwrap();
-        } else {
-            self.send.send_async(SenderEvent::Rejected()).await.unwrap();
         }
         info!("Finished, disconnecting");
         self.stream_handler.send_disconnect();
-        self.context.shutdown().await;
         Ok(())
     }
 }
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use tokio::io::{duplex, split};
+
+    use super::*;
+    use crate::{mediums::recieve, Config};
+
+    #[tokio::test]
+    async fn test_probe() {
+        let context = Context::from(Config::with_dest(temp_dir()));
+        let (client, server) = duplex(64 * 1024);
+        let (server_rx, server_tx) = split(server);
+        let (events, _rx) = flume::unbounded();
+        let server_context = context.clone();
+        tokio::spawn(async move {
+            recieve(server_rx, server_tx, server_context, events).await;
+        });
+        let (rx, tx) = split(client);
+        let reader = ReaderRecv::new(rx, &context);
+        let writer = WriterSend::new(tx, &context);
+        let device_type = GenericSender::probe(context, reader, writer).await.unwrap();
+        assert_eq!(device_type, DeviceType::Laptop);
+    }
+}
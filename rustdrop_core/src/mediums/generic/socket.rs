@@ -1,14 +1,16 @@
 use bytes::Bytes;
 use prost::Message;
-use tokio_util::sync::CancellationToken;
+use tokio_util::sync::{CancellationToken, DropGuard};
 use tracing::{debug, info};
 
 use crate::{
     core::{
+        capture::{Direction, Layer},
         io::{reader::ReaderRecv, writer::WriterSend},
         protocol::{payload_message::get_disconnect, repeat_keep_alive},
         ukey2::Ukey2,
-        Payload, PayloadReciever, PayloadRecieverHandle, PayloadSender, RustdropError,
+        Chunk, Payload, PayloadReciever, PayloadRecieverHandle, PayloadSender, RustdropError,
+        Session,
     },
     protobuf::{
         location::nearby::connections::OfflineFrame,
@@ -25,45 +27,73 @@ pub(super) struct StreamHandler {
     payload_recv: Option<PayloadRecieverHandle>,
     payload_send: Option<PayloadSender>,
     keep_alive: CancellationToken,
+    // Stops the keep alive if the connection is dropped halfway, such as when cancelled
+    _keep_alive_guard: DropGuard,
+    session: Session,
 }
 impl StreamHandler {
-    pub fn new(reader: ReaderRecv, writer: WriterSend, context: Context) -> Self {
+    pub fn new(reader: ReaderRecv, writer: WriterSend, context: Context, session: Session) -> Self {
+        let keep_alive = CancellationToken::new();
         StreamHandler {
             reader,
             write_half: writer,
             payload_recv: None,
             payload_send: None,
-            keep_alive: CancellationToken::new(),
+            _keep_alive_guard: keep_alive.clone().drop_guard(),
+            keep_alive,
             context,
+            session,
         }
     }
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
     pub async fn setup_ukey2(&mut self, ukey2_send: Ukey2, ukey2_recv: Ukey2) {
         self.start_keep_alive().await;
-        let encrypted = ukey2_send.start_encrypting(self.write_half.clone(), &mut self.context);
-        let decrypted = ukey2_recv.start_decrypting(self.reader.clone(), &mut self.context);
+        let session = self.session.clone();
+        let encrypted =
+            ukey2_send.start_encrypting(self.write_half.clone(), &mut self.context, session);
+        let session = self.session.clone();
+        let decrypted =
+            ukey2_recv.start_decrypting(self.reader.clone(), &mut self.context, session);
+        let encrypted = self.session.record_sending(encrypted, &self.context);
+        let decrypted = self.session.record_receiving(decrypted, &self.context);
         let payload_recv = PayloadReciever::push_frames(decrypted, &mut self.context);
         self.payload_recv = Some(payload_recv);
         self.payload_send = Some(PayloadSender::new(encrypted));
     }
     pub async fn send<T: Message>(&self, message: &T) {
+        self.session
+            .record(Direction::Sent, Layer::Plain, &message.encode_to_vec());
         self.write_half.send(message).await;
     }
     pub fn send_payload(&mut self, message: &Frame) {
         info!("Sending payload: {:?}", message);
-        self.payload_send.as_mut().unwrap().send_message(message);
+        self.session
+            .record(Direction::Sent, Layer::Online, &message.encode_to_vec());
+        let id = self.session.random_id();
+        self.payload_send
+            .as_mut()
+            .unwrap()
+            .send_message(message, id);
     }
-    pub fn send_payload_raw(&mut self, raw: Bytes, id: i64) {
-        self.payload_send.as_mut().unwrap().send_raw(raw, id);
+    pub fn send_chunk(&mut self, chunk: Chunk) {
+        self.payload_send.as_mut().unwrap().send_chunk(chunk);
     }
     pub async fn send_ukey2<T: Message>(&mut self, message: &T, message_type: Type) -> Bytes {
-        self.write_half.send_ukey2(message, message_type).await
+        let raw = self.write_half.send_ukey2(message, message_type).await;
+        self.session.record(Direction::Sent, Layer::Plain, &raw);
+        raw
     }
     pub async fn next_offline(&mut self) -> RustdropResult<OfflineFrame> {
-        self.reader.next_message().await
+        let raw = self.reader.next().await?;
+        self.session.record(Direction::Received, Layer::Plain, &raw);
+        Ok(OfflineFrame::decode(raw)?)
     }
     // TODO impl as a trait extension
     pub async fn next_ukey_message<T: Message + Default>(&mut self) -> RustdropResult<(T, Bytes)> {
         let raw = self.reader.next().await?;
+        self.session.record(Direction::Received, Layer::Plain, &raw);
         let ukey = Ukey2Message::decode(raw.clone()).unwrap();
         let ukey_type = ukey.message_type();
         if ukey_type == Type::Alert || ukey_type == Type::UnknownDoNotUse {
@@ -93,11 +123,22 @@ impl StreamHandler {
         drop(self.payload_send);
         let _ = self.payload_recv.unwrap().wait_for_disconnect().await;
     }
-    pub async fn next_payload_raw(&mut self) -> RustdropResult<Payload> {
-        self.payload_recv.as_mut().unwrap().get_next_raw().await
+    pub async fn next_chunk(&mut self) -> RustdropResult<Chunk> {
+        self.payload_recv.as_mut().unwrap().next_chunk().await
+    }
+    pub fn assemble(&mut self, chunk: Chunk) -> Option<Payload> {
+        self.payload_recv.as_mut().unwrap().assemble(chunk)
     }
     pub async fn next_payload(&mut self) -> RustdropResult<Frame> {
-        self.payload_recv.as_mut().unwrap().get_next_payload().await
+        let frame = self
+            .payload_recv
+            .as_mut()
+            .unwrap()
+            .get_next_payload()
+            .await?;
+        self.session
+            .record(Direction::Received, Layer::Online, &frame.encode_to_vec());
+        Ok(frame)
     }
     pub fn send_disconnect(mut self) {
         self.pre_shutdown();
@@ -0,0 +1,558 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    sync::{Arc, Mutex},
+};
+
+use flume::Sender;
+use futures::future::BoxFuture;
+use tokio::io::{duplex, split};
+use tracing::info;
+
+use crate::{
+    core::RustdropError,
+    mediums::{recieve, Discover, Discovery, Medium, MediumType, ReadHalf, Route, WriteHalf},
+    runner::DiscoveringHandle,
+    Context, Device, ReceiveEvent, RustdropResult,
+};
+
+const LOOPBACK: &str = "loopback";
+type Connection = (ReadHalf, WriteHalf);
+
+struct Receiving {
+    device: Device,
+    connections: Sender<Connection>,
+}
+struct Watcher {
+    endpoint_id: u32,
+    handle: DiscoveringHandle,
+}
+#[derive(Default)]
+struct Registry {
+    receiving: HashMap<u32, Receiving>,
+    watchers: Vec<Watcher>,
+}
+impl Registry {
+    fn resolve(&self, watcher: &Watcher, network: &LoopbackNetwork, device: &Device) {
+        if watcher.endpoint_id == device.endpoint_id {
+            return;
+        }
+        let discovery = LoopbackDiscovery {
+            network: network.clone(),
+            endpoint_id: device.endpoint_id,
+        };
+        watcher.handle.resolved(
+            device.clone(),
+            MediumType::Custom(LOOPBACK),
+            vec![Discover::new(discovery)],
+        );
+    }
+}
+// Instances in the same process which reach each other over in-memory pipes.
+#[derive(Clone, Default)]
+pub struct LoopbackNetwork(Arc<Mutex<Registry>>);
+impl LoopbackNetwork {
+    pub fn medium(&self, context: Context) -> Loopback {
+        Loopback {
+            network: self.clone(),
+            context,
+        }
+    }
+    // Takes the device off the network, as if it went out of range.
+    pub fn disconnect(&self, endpoint_id: u32) {
+        let mut registry = self.0.lock().unwrap();
+        registry.receiving.remove(&endpoint_id);
+        registry
+            .watchers
+            .retain(|watcher| watcher.endpoint_id != endpoint_id);
+        for watcher in registry.watchers.iter() {
+            watcher
+                .handle
+                .lost_medium(endpoint_id, MediumType::Custom(LOOPBACK));
+        }
+    }
+}
+impl Debug for LoopbackNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registry = self.0.lock().unwrap();
+        f.debug_struct("LoopbackNetwork")
+            .field("receiving", &registry.receiving.keys())
+            .finish_non_exhaustive()
+    }
+}
+#[derive(Debug)]
+struct LoopbackDiscovery {
+    network: LoopbackNetwork,
+    endpoint_id: u32,
+}
+impl Discovery for LoopbackDiscovery {
+    fn route(&self) -> Route {
+        Route::Custom(LOOPBACK, self.endpoint_id.to_string())
+    }
+    fn connect(&self) -> BoxFuture<'_, RustdropResult<(ReadHalf, WriteHalf)>> {
+        Box::pin(async move {
+            let registry = self.network.0.lock().unwrap();
+            let receiving = registry
+                .receiving
+                .get(&self.endpoint_id)
+                .ok_or(RustdropError::Connection())?;
+            let (client, server) = duplex(64 * 1024);
+            let (server_rx, server_tx) = split(server);
+            receiving
+                .connections
+                .send((Box::new(server_rx), Box::new(server_tx)))
+                .map_err(|_| RustdropError::Connection())?;
+            let (rx, tx) = split(client);
+            Ok((Box::new(rx) as ReadHalf, Box::new(tx) as WriteHalf))
+        })
+    }
+}
+// The medium of one instance on a loopback network.
+pub struct Loopback {
+    network: LoopbackNetwork,
+    context: Context,
+}
+impl Loopback {
+    fn device(&self) -> Device {
+        Device {
+            endpoint_id: self.context.endpoint_id(),
+            device_name: self.context.endpoint_info().name,
+            device_type: self.context.endpoint_info().devtype(),
+        }
+    }
+}
+impl Medium for Loopback {
+    fn discover(&mut self, send: DiscoveringHandle) -> BoxFuture<'_, RustdropResult<()>> {
+        Box::pin(async move {
+            let mut registry = self.network.0.lock().unwrap();
+            let watcher = Watcher {
+                endpoint_id: self.context.endpoint_id(),
+                handle: send,
+            };
+            for receiving in registry.receiving.values() {
+                registry.resolve(&watcher, &self.network, &receiving.device);
+            }
+            registry.watchers.push(watcher);
+            Ok(())
+        })
+    }
+    fn start_recieving(&mut self, send: Sender<ReceiveEvent>) -> BoxFuture<'_, RustdropResult<()>> {
+        Box::pin(async move {
+            let (connections, incoming) = flume::unbounded::<Connection>();
+            let device = self.device();
+            let mut registry = self.network.0.lock().unwrap();
+            for watcher in registry.watchers.iter() {
+                registry.resolve(watcher, &self.network, &device);
+            }
+            info!(
+                "Receiving on the loopback network as {}",
+                device.endpoint_id
+            );
+            registry.receiving.insert(
+                device.endpoint_id,
+                Receiving {
+                    device,
+                    connections,
+                },
+            );
+            let context = self.context.clone();
+            self.context.spawn(async move {
+                while let Ok((rx, tx)) = incoming.recv_async().await {
+                    let child = context.clone();
+                    let send = send.clone();
+                    context.spawn(async move {
+                        recieve(rx, tx, child, send).await;
+                    });
+                }
+            });
+            Ok(())
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use flume::Receiver;
+    use tokio::{fs, runtime::Handle, time::timeout};
+
+    use super::*;
+    use crate::{
+        test_util::{discovered, next, read_when_written, temp_dir},
+        Config, DeclineReason, DirectorySink, DiscoveryEvent, DiscoveryHandle, IncomingFile,
+        IncomingSink, IncomingText, Outgoing, Rustdrop, ScannedQrCode, SenderEvent, SinkWriter,
+        TextType, Transfer, TransferOutcome,
+    };
+
+    async fn instance(network: &LoopbackNetwork, dest: PathBuf) -> Rustdrop {
+        let mut config = Config::with_dest(dest);
+        config.mediums = Vec::new();
+        let mut rustdrop = Rustdrop::new(config).await.unwrap();
+        rustdrop.add_medium(network.medium(rustdrop.context().clone()));
+        rustdrop
+    }
+    // Answers every request, passing on the texts received.
+    async fn receiver(rustdrop: &mut Rustdrop, accept: bool) -> Receiver<IncomingText> {
+        let events = rustdrop.start_recieving().await.unwrap();
+        let (tx, texts) = flume::unbounded();
+        tokio::spawn(async move {
+            while let Ok(event) = events.recv_async().await {
+                match event {
+                    ReceiveEvent::PairingRequest(request) => request.respond(accept),
+                    ReceiveEvent::Text(text) => tx.send(text).unwrap(),
+                    _ => (),
+                }
+            }
+        });
+        texts
+    }
+    async fn discover(rustdrop: &mut Rustdrop) -> DiscoveryHandle {
+        discovered(&rustdrop.discover().await.unwrap()).await
+    }
+    // Every event of the transfer but the pin and progress, until the sender is done with it.
+    async fn send(found: &DiscoveryHandle, outgoing: Outgoing) -> (Vec<String>, Transfer) {
+        let transfer = found.send_file(outgoing, &Handle::current()).unwrap();
+        let all = async {
+            let mut all = Vec::new();
+            while let Ok(event) = transfer.events().recv_async().await {
+                if !matches!(event, SenderEvent::Pin(_) | SenderEvent::Progress(_)) {
+                    all.push(format!("{:?}", event));
+                }
+            }
+            all
+        };
+        let all = timeout(Duration::from_secs(10), all).await.unwrap();
+        (all, transfer)
+    }
+    #[tokio::test]
+    async fn test_accept() {
+        let dir = temp_dir("rustdrop_loopback_accept").await;
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Hello over loopback").await.unwrap();
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        receiver(&mut to, true).await;
+        let mut from = instance(&network, dir.clone()).await;
+        let found = discover(&mut from).await;
+        assert_eq!(found.device().endpoint_id, to.context().endpoint_id());
+        assert_eq!(found.device().device_name, to.context().config.name);
+        assert_eq!(found.routes().len(), 1);
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let (events, transfer) = send(&found, outgoing).await;
+        assert_eq!(events[1..], ["AwaitingResponse", "Accepted", "Finished"]);
+        assert_eq!(transfer.outcome().await, TransferOutcome::Completed);
+        assert_eq!(transfer.route(), found.routes().first().cloned());
+        assert_eq!(transfer.pin().unwrap().len(), 4);
+        let received = read_when_written(dir.join("received").join("hello.txt"));
+        assert_eq!(received.await, "Hello over loopback");
+    }
+    #[tokio::test]
+    async fn test_reject() {
+        let dir = temp_dir("rustdrop_loopback_reject").await;
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Unwanted").await.unwrap();
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        receiver(&mut to, false).await;
+        let mut from = instance(&network, dir.clone()).await;
+        let found = discover(&mut from).await;
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let (events, transfer) = send(&found, outgoing).await;
+        assert_eq!(events[1..], ["AwaitingResponse", "Rejected(Declined)"]);
+        let outcome = transfer.outcome().await;
+        assert_eq!(outcome, TransferOutcome::Rejected(DeclineReason::Declined));
+        assert!(!dir.join("received").join("hello.txt").exists());
+    }
+    #[tokio::test]
+    async fn test_text() {
+        let dir = temp_dir("rustdrop_loopback_text").await;
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        let texts = receiver(&mut to, true).await;
+        let mut from = instance(&network, dir.clone()).await;
+        let found = discover(&mut from).await;
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_text("https://example.com".into(), TextType::Url);
+        let (events, _) = send(&found, outgoing).await;
+        assert_eq!(events.last().unwrap(), "Finished");
+        let text = next(&texts).await;
+        assert_eq!(text.text, "https://example.com");
+        assert_eq!(text.text_type, TextType::Url);
+    }
+    #[tokio::test]
+    async fn test_multiple_files() {
+        let dir = temp_dir("rustdrop_loopback_multiple").await;
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        receiver(&mut to, true).await;
+        let mut from = instance(&network, dir.clone()).await;
+        let found = discover(&mut from).await;
+
+        let mut outgoing = Outgoing::default();
+        for i in 0..3 {
+            let path = dir.join(format!("file{}.txt", i));
+            fs::write(&path, format!("File number {}", i))
+                .await
+                .unwrap();
+            outgoing.add_file(path).unwrap();
+        }
+        let (events, _) = send(&found, outgoing).await;
+        assert_eq!(events.last().unwrap(), "Finished");
+        for i in 0..3 {
+            let path = dir.join("received").join(format!("file{}.txt", i));
+            assert_eq!(read_when_written(path).await, format!("File number {}", i));
+        }
+    }
+    #[tokio::test]
+    async fn test_received_files() {
+        let dir = temp_dir("rustdrop_loopback_received").await;
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Second hello").await.unwrap();
+        fs::create_dir_all(dir.join("received")).await.unwrap();
+        fs::write(dir.join("received").join("hello.txt"), "First hello")
+            .await
+            .unwrap();
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        let events = to.start_recieving().await.unwrap();
+        let mut from = instance(&network, dir.clone()).await;
+        let found = discover(&mut from).await;
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let transfer = send(&found, outgoing);
+        let mut received = Vec::new();
+        let completed = timeout(Duration::from_secs(10), async {
+            loop {
+                match events.recv_async().await.unwrap() {
+                    ReceiveEvent::PairingRequest(request) => request.respond(true),
+                    ReceiveEvent::File(file) => received.push(file),
+                    ReceiveEvent::TransferCompleted(completed) => return completed,
+                    other => panic!("Unexpected event {:?}", other),
+                }
+            }
+        });
+        let (completed, (_, transfer)) = tokio::join!(completed, transfer);
+        let completed = completed.unwrap();
+        assert_eq!(transfer.outcome().await, TransferOutcome::Completed);
+        assert_eq!(received.len(), 1);
+        let file = &received[0];
+        let path = dir.join("received").join("hello (1).txt");
+        assert_eq!(file.path.as_ref(), Some(&path));
+        assert_eq!(file.name, "hello.txt");
+        assert_eq!(file.size, 12);
+        assert_eq!(file.sender, from.context().config.name);
+        assert_eq!(completed.sender, from.context().config.name);
+        assert_eq!(completed.files.len(), 1);
+        assert!(completed.texts.is_empty());
+        assert_eq!(read_when_written(path).await, "Second hello");
+    }
+    // Files go in a folder named after the device which sent them.
+    #[derive(Debug)]
+    struct PerSender(PathBuf);
+    impl IncomingSink for PerSender {
+        fn file<'a>(
+            &'a self,
+            sender: &'a str,
+            file: &'a IncomingFile,
+        ) -> BoxFuture<'a, RustdropResult<SinkWriter>> {
+            Box::pin(async move {
+                let sink = DirectorySink::new(self.0.join(sender));
+                sink.file(sender, file).await
+            })
+        }
+    }
+    #[tokio::test]
+    async fn test_sink() {
+        let dir = temp_dir("rustdrop_loopback_sink").await;
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Hello to a folder").await.unwrap();
+        let network = LoopbackNetwork::default();
+        let mut config = Config::with_dest(dir.join("unused"));
+        config.mediums = Vec::new();
+        config.sink = Some(Arc::new(PerSender(dir.join("received"))));
+        let mut to = Rustdrop::new(config).await.unwrap();
+        to.add_medium(network.medium(to.context().clone()));
+        receiver(&mut to, true).await;
+        let mut from = instance(&network, dir.clone()).await;
+        let found = discover(&mut from).await;
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let (_, transfer) = send(&found, outgoing).await;
+        assert_eq!(transfer.outcome().await, TransferOutcome::Completed);
+        let sender = &from.context().config.name;
+        let path = dir.join("received").join(sender).join("hello.txt");
+        assert_eq!(read_when_written(path).await, "Hello to a folder");
+        assert!(!dir.join("unused").exists());
+    }
+    #[tokio::test]
+    async fn test_bytes_and_reader() {
+        let dir = temp_dir("rustdrop_loopback_reader").await;
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        receiver(&mut to, true).await;
+        let mut from = instance(&network, dir.clone()).await;
+        let found = discover(&mut from).await;
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_bytes("report.txt".into(), None, "In memory".into());
+        // Long enough to be sent in several chunks
+        let piped: Vec<u8> = (0..1_200_000).map(|i| (i % 251) as u8).collect();
+        let reader = std::io::Cursor::new(piped.clone());
+        outgoing.add_reader("stdin".into(), "text/plain".into(), None, reader);
+        let (events, _) = send(&found, outgoing).await;
+        assert_eq!(events.last().unwrap(), "Finished");
+        let received = dir.join("received");
+        assert_eq!(
+            read_when_written(received.join("report.txt")).await,
+            "In memory"
+        );
+        let path = received.join("stdin");
+        let piped_back = timeout(Duration::from_secs(5), async {
+            loop {
+                match fs::read(&path).await {
+                    Ok(content) if content.len() == piped.len() => return content,
+                    _ => tokio::time::sleep(Duration::from_millis(50)).await,
+                }
+            }
+        });
+        assert!(piped_back.await.unwrap() == piped);
+    }
+    #[tokio::test]
+    async fn test_send_to_all() {
+        let dir = temp_dir("rustdrop_loopback_all").await;
+        let path = dir.join("slides.txt");
+        fs::write(&path, "Slides for everyone").await.unwrap();
+        let network = LoopbackNetwork::default();
+        let mut receivers = Vec::new();
+        for name in ["first", "second"] {
+            let mut to = instance(&network, dir.join(name)).await;
+            receiver(&mut to, true).await;
+            receivers.push(to);
+        }
+        let mut from = instance(&network, dir.clone()).await;
+        let events = from.discover().await.unwrap();
+        let mut found = Vec::new();
+        while found.len() < 2 {
+            found.push(discovered(&events).await);
+        }
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let reader = std::io::Cursor::new(b"Notes for everyone".to_vec());
+        outgoing.add_reader("notes.txt".into(), "text/plain".into(), None, reader);
+        let targets: Vec<&DiscoveryHandle> = found.iter().collect();
+        let transfer = DiscoveryHandle::send_to_all(&targets, outgoing, &Handle::current());
+        let transfer = transfer.unwrap();
+        let outcome = timeout(Duration::from_secs(10), transfer.outcome()).await;
+        let outcome = outcome.unwrap();
+        assert!(outcome.is_completed(), "{:?}", outcome);
+        assert_eq!(outcome.completed().count(), 2);
+        assert_eq!(transfer.sent(), 2 * (19 + 18));
+        for name in ["first", "second"] {
+            let slides = read_when_written(dir.join(name).join("slides.txt"));
+            assert_eq!(slides.await, "Slides for everyone");
+            let notes = read_when_written(dir.join(name).join("notes.txt"));
+            assert_eq!(notes.await, "Notes for everyone");
+        }
+    }
+    #[tokio::test]
+    async fn test_unreachable() {
+        let dir = temp_dir("rustdrop_loopback_unreachable").await;
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Nobody is there").await.unwrap();
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        receiver(&mut to, true).await;
+        let mut from = instance(&network, dir.clone()).await;
+        let events = from.discover().await.unwrap();
+        let found = discovered(&events).await;
+
+        let endpoint_id = to.context().endpoint_id();
+        network.disconnect(endpoint_id);
+        match next(&events).await {
+            DiscoveryEvent::Removed(id) => assert_eq!(id, endpoint_id),
+            other => panic!("Unexpected event {:?}", other),
+        }
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let (events, transfer) = send(&found, outgoing).await;
+        assert!(events.is_empty());
+        let outcome = transfer.outcome().await;
+        assert_eq!(outcome, TransferOutcome::Failed("Connection Error".into()));
+    }
+    #[tokio::test]
+    async fn test_cancel() {
+        let dir = temp_dir("rustdrop_loopback_cancel").await;
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Never answered").await.unwrap();
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        let requests = to.start_recieving().await.unwrap();
+        let mut from = instance(&network, dir.clone()).await;
+        let found = discover(&mut from).await;
+
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let transfer = found.send_file(outgoing, &Handle::current()).unwrap();
+        let request = match next(&requests).await {
+            ReceiveEvent::PairingRequest(request) => request,
+            other => panic!("Unexpected event {:?}", other),
+        };
+        assert_eq!(request.endpoint_id(), Some(from.context().endpoint_id()));
+        assert_eq!(request.device_name(), from.context().config.name);
+        assert_eq!(request.incoming().files().next().unwrap().name, "hello.txt");
+        assert_eq!(request.incoming().total_size(), 14);
+        assert!(request.body().ends_with("wants to share a file with you"));
+        transfer.cancel();
+        let outcome = timeout(Duration::from_secs(5), transfer.outcome()).await;
+        assert_eq!(outcome.unwrap(), TransferOutcome::Cancelled);
+    }
+    // Sends a text to the first device found, with the code the sender scanned.
+    async fn send_scanned(from: &mut Rustdrop, url: &str) -> TransferOutcome {
+        let found = discover(from).await;
+        let mut outgoing = Outgoing::default();
+        outgoing.add_text("Scanned".into(), TextType::Text);
+        outgoing.set_qr_code(ScannedQrCode::from_url(url).unwrap());
+        let (_, transfer) = send(&found, outgoing).await;
+        transfer.outcome().await
+    }
+    #[tokio::test]
+    async fn test_qr_code() {
+        let dir = temp_dir("rustdrop_loopback_qr").await;
+        let network = LoopbackNetwork::default();
+        let mut to = instance(&network, dir.join("received")).await;
+        let events = to.start_recieving().await.unwrap();
+        let code = to.qr_code();
+        let token = to.context().endpoint_info().qr_token;
+        assert_eq!(token, Some(code.advertising_token()));
+
+        let mut from = instance(&network, dir.clone()).await;
+        let outcome = send_scanned(&mut from, &code.url()).await;
+        assert_eq!(outcome, TransferOutcome::Completed);
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            received.push(format!("{:?}", next(&events).await));
+        }
+        assert!(received[0].starts_with("AutoAccepted"), "{:?}", received);
+        assert!(received[1].starts_with("Text"), "{:?}", received);
+
+        // A code shown by another device is not signed by this one
+        let other = instance(&LoopbackNetwork::default(), dir.join("other")).await;
+        let outcome = send_scanned(&mut from, &other.qr_code().url()).await;
+        assert!(matches!(outcome, TransferOutcome::Failed(e) if e.starts_with("Invalid QR code")));
+        // Nor signed at all by a device not showing any
+        let mut silent = instance(&network, dir.join("silent")).await;
+        receiver(&mut silent, true).await;
+        network.disconnect(to.context().endpoint_id());
+        let outcome = send_scanned(&mut from, &code.url()).await;
+        assert!(matches!(outcome, TransferOutcome::Failed(e) if e.starts_with("Invalid QR code")));
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -1,5 +1,9 @@
 mod discovery;
+mod interfaces;
+#[cfg(feature = "mdns")]
 mod mdns;
 mod wlan;
 pub(crate) use discovery::WlanDiscovery;
+#[cfg(feature = "mdns")]
+pub(crate) use mdns::lookup;
 pub(crate) use wlan::Wlan;
@@ -1,11 +1,14 @@
 use std::{io::ErrorKind, net::SocketAddr};
 
-use tokio::io::{AsyncRead, AsyncWrite};
+use futures::future::BoxFuture;
 use tokio::net::TcpStream;
 use tracing::info;
 
 use crate::RustdropResult;
-use crate::{core::RustdropError, mediums::generic::Discovery};
+use crate::{
+    core::RustdropError,
+    mediums::{Discovery, ReadHalf, Route, WriteHalf},
+};
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct WlanDiscovery {
     addr: SocketAddr,
@@ -37,8 +40,13 @@ async fn get_stream(ip: &SocketAddr) -> RustdropResult<TcpStream> {
     Ok(stream.unwrap())
 }
 impl Discovery for WlanDiscovery {
-    async fn into_socket(self) -> RustdropResult<(impl AsyncRead, impl AsyncWrite)> {
-        let stream = get_stream(&self.addr).await?;
-        Ok(stream.into_split())
+    fn route(&self) -> Route {
+        Route::Wlan(self.addr)
+    }
+    fn connect(&self) -> BoxFuture<'_, RustdropResult<(ReadHalf, WriteHalf)>> {
+        Box::pin(async move {
+            let (rx, tx) = get_stream(&self.addr).await?.into_split();
+            Ok((Box::new(rx) as ReadHalf, Box::new(tx) as WriteHalf))
+        })
     }
 }
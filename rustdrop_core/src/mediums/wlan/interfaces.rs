@@ -0,0 +1,97 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use if_addrs::{get_if_addrs, Interface};
+
+use crate::NetworkConfig;
+
+pub(crate) fn interfaces() -> Vec<Interface> {
+    get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|iface| !iface.is_loopback())
+        .collect()
+}
+pub(crate) fn selected_interfaces(network: &NetworkConfig) -> Vec<Interface> {
+    interfaces()
+        .into_iter()
+        .filter(|iface| network.allows(&iface.name, iface.ip()))
+        .collect()
+}
+#[cfg_attr(not(feature = "mdns"), allow(dead_code))]
+pub(crate) fn advertised_ips(interfaces: &[Interface]) -> Vec<IpAddr> {
+    interfaces.iter().map(Interface::ip).collect()
+}
+// Where to listen on each interface, link-local addresses need the interface as scope.
+pub(crate) fn listen_addrs(interfaces: &[Interface]) -> Vec<SocketAddr> {
+    interfaces
+        .iter()
+        .map(|iface| match iface.ip() {
+            IpAddr::V6(ip) if is_link_local(&ip) => {
+                SocketAddrV6::new(ip, 0, 0, iface.index.unwrap_or_default()).into()
+            }
+            ip => SocketAddr::new(ip, 0),
+        })
+        .collect()
+}
+fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+// mDNS does not tell us which link a link-local address was seen on, so try every link we are on.
+#[cfg_attr(not(feature = "mdns"), allow(dead_code))]
+pub(crate) fn socket_addrs(ip: IpAddr, port: u16, interfaces: &[Interface]) -> Vec<SocketAddr> {
+    match ip {
+        IpAddr::V6(ip) if is_link_local(&ip) => {
+            let mut scopes: Vec<u32> = interfaces
+                .iter()
+                .filter(|iface| iface.ip().is_ipv6() && iface.is_link_local())
+                .filter_map(|iface| iface.index)
+                .collect();
+            scopes.sort();
+            scopes.dedup();
+            scopes
+                .into_iter()
+                .map(|scope| SocketAddrV6::new(ip, port, 0, scope).into())
+                .collect()
+        }
+        _ => vec![SocketAddr::new(ip, port)],
+    }
+}
+#[cfg(test)]
+mod tests {
+    use if_addrs::{IfAddr, Ifv6Addr};
+
+    use super::*;
+
+    fn v6(name: &str, ip: &str, index: u32) -> Interface {
+        Interface {
+            name: name.into(),
+            addr: IfAddr::V6(Ifv6Addr {
+                ip: ip.parse().unwrap(),
+                netmask: Ipv6Addr::UNSPECIFIED,
+                broadcast: None,
+            }),
+            index: Some(index),
+        }
+    }
+    #[test]
+    fn test_scopes() {
+        let interfaces = [
+            v6("eth0", "fe80::1", 2),
+            v6("wlan0", "fe80::2", 3),
+            v6("wlan0", "2001:db8::2", 3),
+        ];
+        let addrs = socket_addrs("fe80::5".parse().unwrap(), 80, &interfaces);
+        assert_eq!(
+            addrs,
+            vec![
+                SocketAddrV6::new("fe80::5".parse().unwrap(), 80, 0, 2).into(),
+                SocketAddrV6::new("fe80::5".parse().unwrap(), 80, 0, 3).into(),
+            ]
+        );
+        let global: IpAddr = "2001:db8::5".parse().unwrap();
+        assert_eq!(
+            socket_addrs(global, 80, &interfaces),
+            vec![SocketAddr::new(global, 80)]
+        );
+    }
+}
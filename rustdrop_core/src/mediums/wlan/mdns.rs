@@ -1,5 +1,10 @@
+mod avahi;
+mod backend;
 mod browser;
 mod constants;
 mod main;
 mod manager;
+mod sd;
+mod unicast;
 pub(crate) use manager::Mdns;
+pub(crate) use unicast::lookup;
@@ -0,0 +1,495 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::Mutex,
+};
+
+use futures::{future::BoxFuture, StreamExt};
+use tracing::{debug, error, info};
+use zbus::{proxy, zvariant::OwnedObjectPath, CacheProperties, Connection};
+
+use super::{
+    backend::MdnsBackend,
+    browser::{parse_device, parse_removed, txt_value},
+    constants::{AVAHI_TYPE, DOMAIN, TXT_KEY},
+    main::Advertisement,
+};
+use crate::{
+    mediums::wlan::interfaces::selected_interfaces, runner::DiscoveringHandle, Context,
+    NetworkConfig, RustdropResult,
+};
+
+const IF_UNSPEC: i32 = -1;
+const PROTO_UNSPEC: i32 = -1;
+
+#[proxy(
+    interface = "org.freedesktop.Avahi.Server",
+    default_service = "org.freedesktop.Avahi",
+    default_path = "/",
+    gen_blocking = false
+)]
+trait Server {
+    fn entry_group_new(&self) -> zbus::Result<OwnedObjectPath>;
+    fn service_browser_prepare(
+        &self,
+        interface: i32,
+        protocol: i32,
+        service_type: &str,
+        domain: &str,
+        flags: u32,
+    ) -> zbus::Result<OwnedObjectPath>;
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    fn resolve_service(
+        &self,
+        interface: i32,
+        protocol: i32,
+        name: &str,
+        service_type: &str,
+        domain: &str,
+        aprotocol: i32,
+        flags: u32,
+    ) -> zbus::Result<(
+        i32,
+        i32,
+        String,
+        String,
+        String,
+        String,
+        i32,
+        String,
+        u16,
+        Vec<Vec<u8>>,
+        u32,
+    )>;
+}
+#[proxy(
+    interface = "org.freedesktop.Avahi.EntryGroup",
+    default_service = "org.freedesktop.Avahi",
+    gen_blocking = false
+)]
+trait EntryGroup {
+    #[allow(clippy::too_many_arguments)]
+    fn add_service(
+        &self,
+        interface: i32,
+        protocol: i32,
+        flags: u32,
+        name: &str,
+        service_type: &str,
+        domain: &str,
+        host: &str,
+        port: u16,
+        txt: Vec<Vec<u8>>,
+    ) -> zbus::Result<()>;
+    fn commit(&self) -> zbus::Result<()>;
+    fn free(&self) -> zbus::Result<()>;
+}
+#[proxy(
+    interface = "org.freedesktop.Avahi.ServiceBrowser",
+    default_service = "org.freedesktop.Avahi",
+    gen_blocking = false
+)]
+trait ServiceBrowser {
+    fn start(&self) -> zbus::Result<()>;
+    #[zbus(signal)]
+    fn item_new(
+        &self,
+        interface: i32,
+        protocol: i32,
+        name: &str,
+        service_type: &str,
+        domain: &str,
+        flags: u32,
+    ) -> zbus::Result<()>;
+    #[zbus(signal)]
+    fn item_remove(
+        &self,
+        interface: i32,
+        protocol: i32,
+        name: &str,
+        service_type: &str,
+        domain: &str,
+        flags: u32,
+    ) -> zbus::Result<()>;
+}
+fn selected(interfaces: &HashSet<i32>, interface: i32) -> bool {
+    interfaces.is_empty() || interfaces.contains(&interface)
+}
+// Everything resolved for one service name, Avahi reports each interface and protocol apart.
+#[derive(Debug, Default)]
+struct Resolved {
+    endpoint_info: Option<String>,
+    port: u16,
+    addrs: HashMap<(i32, i32), IpAddr>,
+}
+impl Resolved {
+    fn report(&self, name: &str, handle: &DiscoveringHandle) -> RustdropResult<()> {
+        let addrs: Vec<IpAddr> = self.addrs.values().copied().collect();
+        parse_device(
+            name,
+            self.endpoint_info.as_deref(),
+            &addrs,
+            self.port,
+            handle,
+        )
+    }
+}
+// The system avahi-daemon, so we don't compete with it for port 5353.
+pub(crate) struct Avahi {
+    server: ServerProxy<'static>,
+    // Interface indexes to announce and browse on, any when empty.
+    interfaces: HashSet<i32>,
+    // Avahi withdraws the services once the group is dropped.
+    group: Mutex<Option<EntryGroupProxy<'static>>>,
+}
+impl Avahi {
+    pub async fn system(network: &NetworkConfig) -> RustdropResult<Self> {
+        let connection = Connection::system().await?;
+        let interfaces = selected_interfaces(network)
+            .iter()
+            .filter_map(|iface| iface.index)
+            .map(|index| index as i32)
+            .collect();
+        Self::new(connection, interfaces).await
+    }
+    async fn new(connection: Connection, interfaces: HashSet<i32>) -> RustdropResult<Self> {
+        let server = ServerProxy::builder(&connection)
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        Ok(Self {
+            server,
+            interfaces,
+            group: Mutex::new(None),
+        })
+    }
+    async fn resolve(
+        server: &ServerProxy<'static>,
+        interface: i32,
+        protocol: i32,
+        name: &str,
+        resolved: &mut Resolved,
+    ) -> RustdropResult<()> {
+        let (_, _, _, _, _, host, _, address, port, txt, _) = server
+            .resolve_service(
+                interface,
+                protocol,
+                name,
+                AVAHI_TYPE,
+                DOMAIN,
+                PROTO_UNSPEC,
+                0,
+            )
+            .await?;
+        debug!("Resolved {} on {} to {} port {}", name, host, address, port);
+        resolved.endpoint_info = txt_value(&txt);
+        resolved.port = port;
+        resolved
+            .addrs
+            .insert((interface, protocol), address.parse()?);
+        Ok(())
+    }
+    async fn run_browser(
+        server: ServerProxy<'static>,
+        browser: ServiceBrowserProxy<'static>,
+        interfaces: HashSet<i32>,
+        handle: DiscoveringHandle,
+    ) -> RustdropResult<()> {
+        let mut added = browser.receive_item_new().await?;
+        let mut removed = browser.receive_item_remove().await?;
+        browser.start().await?;
+        let mut services: HashMap<String, Resolved> = HashMap::new();
+        loop {
+            tokio::select! {
+                Some(signal) = added.next() => {
+                    let args = signal.args()?;
+                    if !selected(&interfaces, args.interface) {
+                        continue;
+                    }
+                    let name = args.name.to_string();
+                    let resolved = services.entry(name.clone()).or_default();
+                    match Self::resolve(&server, args.interface, args.protocol, &name, resolved).await {
+                        Ok(()) => {
+                            if let Err(e) = resolved.report(&name, &handle) {
+                                error!("Error while parsing endpoint {}: {}", name, e);
+                            }
+                        }
+                        Err(e) => error!("Unable to resolve {}: {}", name, e),
+                    }
+                }
+                Some(signal) = removed.next() => {
+                    let args = signal.args()?;
+                    let name = args.name.to_string();
+                    let Some(resolved) = services.get_mut(&name) else {
+                        continue;
+                    };
+                    resolved.addrs.remove(&(args.interface, args.protocol));
+                    let res = if resolved.addrs.is_empty() {
+                        services.remove(&name);
+                        parse_removed(&name, &handle)
+                    } else {
+                        resolved.report(&name, &handle)
+                    };
+                    if let Err(e) = res {
+                        error!("Error while parsing endpoint {}: {}", name, e);
+                    }
+                }
+                else => break,
+            }
+        }
+        Ok(())
+    }
+}
+impl MdnsBackend for Avahi {
+    fn browse(
+        &self,
+        context: &Context,
+        handle: DiscoveringHandle,
+    ) -> BoxFuture<'_, RustdropResult<()>> {
+        let context = context.clone();
+        Box::pin(async move {
+            let path = self
+                .server
+                .service_browser_prepare(IF_UNSPEC, PROTO_UNSPEC, AVAHI_TYPE, DOMAIN, 0)
+                .await?;
+            let browser = ServiceBrowserProxy::builder(self.server.inner().connection())
+                .path(path)?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?;
+            let server = self.server.clone();
+            let interfaces = self.interfaces.clone();
+            context.spawn(async move {
+                if let Err(e) = Self::run_browser(server, browser, interfaces, handle).await {
+                    error!("Avahi browser stopped: {}", e);
+                }
+            });
+            Ok(())
+        })
+    }
+    fn register(&self, advertisement: Advertisement) -> BoxFuture<'_, RustdropResult<()>> {
+        Box::pin(async move {
+            // Registered again when what we advertise changes, replacing the last one
+            let previous = self.group.lock().unwrap().take();
+            if let Some(previous) = previous {
+                previous.free().await?;
+            }
+            let path = self.server.entry_group_new().await?;
+            let group = EntryGroupProxy::builder(self.server.inner().connection())
+                .path(path)?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?;
+            let txt = vec![format!("{}={}", TXT_KEY, advertisement.endpoint_info).into_bytes()];
+            let interfaces: Vec<i32> = if self.interfaces.is_empty() {
+                vec![IF_UNSPEC]
+            } else {
+                self.interfaces.iter().copied().collect()
+            };
+            for interface in interfaces {
+                // Avahi announces the addresses of its own host name
+                group
+                    .add_service(
+                        interface,
+                        PROTO_UNSPEC,
+                        0,
+                        &advertisement.name,
+                        AVAHI_TYPE,
+                        DOMAIN,
+                        "",
+                        advertisement.port,
+                        txt.clone(),
+                    )
+                    .await?;
+            }
+            group.commit().await?;
+            info!("Registered {} with Avahi", advertisement.name);
+            *self.group.lock().unwrap() = Some(group);
+            Ok(())
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, sync::Arc};
+
+    use tokio::net::UnixStream;
+    use zbus::{
+        connection::Builder, fdo, interface, object_server::SignalContext, zvariant::ObjectPath,
+        Guid, ObjectServer,
+    };
+
+    use super::*;
+    use crate::{
+        core::bits::{Bitfield, MdnsName},
+        mediums::{wlan::mdns::main::get_advertisement, Route},
+        test_util::discovered,
+        Config,
+    };
+
+    const GROUP: &str = "/Client1/EntryGroup1";
+    const BROWSER: &str = "/Client1/ServiceBrowser1";
+    type Calls = Arc<Mutex<Vec<String>>>;
+
+    // Stands in for avahi-daemon, knowing about a single service.
+    struct MockServer {
+        name: String,
+        txt: Vec<Vec<u8>>,
+        calls: Calls,
+    }
+    #[interface(name = "org.freedesktop.Avahi.Server")]
+    impl MockServer {
+        async fn entry_group_new(
+            &self,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> fdo::Result<OwnedObjectPath> {
+            let group = MockGroup {
+                calls: self.calls.clone(),
+            };
+            server.at(GROUP, group).await?;
+            Ok(ObjectPath::from_static_str_unchecked(GROUP).into())
+        }
+        async fn service_browser_prepare(
+            &self,
+            _interface: i32,
+            _protocol: i32,
+            _service_type: String,
+            _domain: String,
+            _flags: u32,
+            #[zbus(object_server)] server: &ObjectServer,
+        ) -> fdo::Result<OwnedObjectPath> {
+            let browser = MockBrowser {
+                name: self.name.clone(),
+            };
+            server.at(BROWSER, browser).await?;
+            Ok(ObjectPath::from_static_str_unchecked(BROWSER).into())
+        }
+        #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+        fn resolve_service(
+            &self,
+            interface: i32,
+            protocol: i32,
+            name: String,
+            service_type: String,
+            domain: String,
+            _aprotocol: i32,
+            _flags: u32,
+        ) -> (
+            i32,
+            i32,
+            String,
+            String,
+            String,
+            String,
+            i32,
+            String,
+            u16,
+            Vec<Vec<u8>>,
+            u32,
+        ) {
+            (
+                interface,
+                protocol,
+                name,
+                service_type,
+                domain,
+                "phone.local".into(),
+                0,
+                "192.168.1.20".into(),
+                4000,
+                self.txt.clone(),
+                0,
+            )
+        }
+    }
+    struct MockGroup {
+        calls: Calls,
+    }
+    #[interface(name = "org.freedesktop.Avahi.EntryGroup")]
+    impl MockGroup {
+        #[allow(clippy::too_many_arguments)]
+        fn add_service(
+            &self,
+            _interface: i32,
+            _protocol: i32,
+            _flags: u32,
+            name: String,
+            _service_type: String,
+            _domain: String,
+            _host: String,
+            port: u16,
+            _txt: Vec<Vec<u8>>,
+        ) {
+            let call = format!("AddService {} {}", name, port);
+            self.calls.lock().unwrap().push(call);
+        }
+        fn commit(&self) {
+            self.calls.lock().unwrap().push("Commit".into());
+        }
+        fn free(&self) {
+            self.calls.lock().unwrap().push("Free".into());
+        }
+    }
+    struct MockBrowser {
+        name: String,
+    }
+    #[interface(name = "org.freedesktop.Avahi.ServiceBrowser")]
+    impl MockBrowser {
+        async fn start(&self, #[zbus(signal_context)] ctxt: SignalContext<'_>) -> fdo::Result<()> {
+            Self::item_new(&ctxt, 2, 0, &self.name, AVAHI_TYPE, DOMAIN, 0).await?;
+            Ok(())
+        }
+        #[zbus(signal)]
+        async fn item_new(
+            ctxt: &SignalContext<'_>,
+            interface: i32,
+            protocol: i32,
+            name: &str,
+            service_type: &str,
+            domain: &str,
+            flags: u32,
+        ) -> zbus::Result<()>;
+    }
+    async fn connect(mock: MockServer) -> (Connection, Connection) {
+        let (server, client) = UnixStream::pair().unwrap();
+        tokio::try_join!(
+            Builder::unix_stream(server)
+                .server(Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at("/", mock)
+                .unwrap()
+                .build(),
+            Builder::unix_stream(client).p2p().build(),
+        )
+        .unwrap()
+    }
+    #[tokio::test]
+    async fn test_avahi() {
+        let context = Context::from(Config::with_dest(temp_dir()));
+        let name = MdnsName::from_endpoint_id(context.endpoint_id()).to_base64();
+        let endpoint_info = context.endpoint_info().to_base64();
+        let calls = Calls::default();
+        let mock = MockServer {
+            name: name.clone(),
+            txt: vec![format!("{}={}", TXT_KEY, endpoint_info).into_bytes()],
+            calls: calls.clone(),
+        };
+        let (_server, client) = connect(mock).await;
+        let avahi = Avahi::new(client, HashSet::new()).await.unwrap();
+        let (tx, rx) = flume::unbounded();
+        let handle = DiscoveringHandle::new(context.clone(), tx);
+        avahi.browse(&context, handle).await.unwrap();
+        let found = discovered(&rx).await;
+        assert_eq!(found.device().endpoint_id, context.endpoint_id());
+        let addr = "192.168.1.20:4000".parse().unwrap();
+        assert_eq!(found.routes(), vec![Route::Wlan(addr)]);
+        let advertisement = get_advertisement(&context.config, context.advertised(), vec![], 4000);
+        avahi.register(advertisement.clone()).await.unwrap();
+        let added = format!("AddService {} 4000", name);
+        assert_eq!(*calls.lock().unwrap(), [added.as_str(), "Commit"]);
+        avahi.register(advertisement).await.unwrap();
+        let expected = [added.as_str(), "Commit", "Free", added.as_str(), "Commit"];
+        assert_eq!(*calls.lock().unwrap(), expected);
+    }
+}
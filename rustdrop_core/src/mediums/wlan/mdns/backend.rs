@@ -0,0 +1,14 @@
+use futures::future::BoxFuture;
+
+use super::main::Advertisement;
+use crate::{runner::DiscoveringHandle, Context, RustdropResult};
+
+pub(crate) trait MdnsBackend: Send + Sync {
+    // Report devices to the handle from a task spawned on the context.
+    fn browse(
+        &self,
+        context: &Context,
+        handle: DiscoveringHandle,
+    ) -> BoxFuture<'_, RustdropResult<()>>;
+    fn register(&self, advertisement: Advertisement) -> BoxFuture<'_, RustdropResult<()>>;
+}
@@ -1,25 +1,53 @@
-use std::net::SocketAddr;
+use std::net::IpAddr;
 
-use mdns_sd::ServiceInfo;
 use tracing::info;
 
+use super::constants::TXT_KEY;
 use crate::{
     core::{
         bits::{Bitfield, EndpointInfo, MdnsName},
         protocol::Device,
+        RustdropError,
+    },
+    mediums::{
+        wlan::{
+            interfaces::{interfaces, socket_addrs},
+            WlanDiscovery,
+        },
+        Discover, MediumType,
     },
-    mediums::{wlan::WlanDiscovery, Discover},
     runner::DiscoveringHandle,
     RustdropResult,
 };
-pub async fn parse_device(
-    info: &ServiceInfo,
-    handle: &mut DiscoveringHandle,
+// The endpoint info out of the entries of a TXT record.
+pub fn txt_value(txt: &[Vec<u8>]) -> Option<String> {
+    txt.iter().find_map(|entry| {
+        let entry = std::str::from_utf8(entry).ok()?;
+        let (key, value) = entry.split_once('=')?;
+        (key == TXT_KEY).then(|| value.to_string())
+    })
+}
+fn parse_name(fullname: &str) -> RustdropResult<MdnsName> {
+    let split_name = fullname.split_once('.').map_or(fullname, |(name, _)| name);
+    MdnsName::decode_base64(split_name.as_bytes())
+}
+pub fn parse_removed(fullname: &str, handle: &DiscoveringHandle) -> RustdropResult<()> {
+    let name = parse_name(fullname)?;
+    info!("Lost Wlan Device {}", name.endpoint_id());
+    handle.lost_medium(name.endpoint_id(), MediumType::Wlan);
+    Ok(())
+}
+pub fn parse_device(
+    fullname: &str,
+    raw_info: Option<&str>,
+    addrs: &[IpAddr],
+    port: u16,
+    handle: &DiscoveringHandle,
 ) -> RustdropResult<()> {
-    let raw_info = info.get_property_val("n").unwrap().unwrap();
-    let split_name = info.get_fullname().split_once('.').unwrap().0;
-    let name = MdnsName::decode_base64(split_name.as_bytes())?;
-    let endpoint_info = EndpointInfo::decode_base64(raw_info)?;
+    let raw_info =
+        raw_info.ok_or_else(|| RustdropError::InvalidMessage("Missing endpoint info".into()))?;
+    let name = parse_name(fullname)?;
+    let endpoint_info = EndpointInfo::decode_base64(raw_info.as_bytes())?;
     info!(
         "Found Wlan Device with name {:?} and info {:?}",
         name, endpoint_info
@@ -29,10 +57,12 @@ pub async fn parse_device(
         device_type: endpoint_info.devtype(),
         device_name: endpoint_info.name,
     };
-    for addr in info.get_addresses() {
-        let full_addr = SocketAddr::new(*addr, info.get_port());
-        let discovery = Discover::Wlan(WlanDiscovery::from(full_addr));
-        handle.discovered(device.clone(), discovery).await;
-    }
+    let interfaces = interfaces();
+    let routes = addrs
+        .iter()
+        .flat_map(|addr| socket_addrs(*addr, port, &interfaces))
+        .map(|addr| Discover::new(WlanDiscovery::from(addr)))
+        .collect();
+    handle.resolved(device, MediumType::Wlan, routes);
     Ok(())
 }
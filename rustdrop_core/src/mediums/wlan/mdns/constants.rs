@@ -1 +1,5 @@
 pub const TYPE: &str = "_FC9F5ED42C8A._tcp.local.";
+// The same service type as Avahi spells it, without the domain.
+pub const AVAHI_TYPE: &str = "_FC9F5ED42C8A._tcp";
+pub const DOMAIN: &str = "local";
+pub const TXT_KEY: &str = "n";
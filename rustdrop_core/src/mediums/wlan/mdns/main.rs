@@ -1,34 +1,43 @@
-use std::{collections::HashMap, net::IpAddr};
+use std::net::IpAddr;
 
 use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
-use mdns_sd::ServiceInfo;
 use tracing::info;
 
 use crate::{
     core::{
-        bits::{Bitfield, EndpointInfo, MdnsName},
+        bits::{Bitfield, MdnsName},
         Config,
     },
-    mediums::wlan::mdns::constants::TYPE,
+    runner::context::Advertised,
 };
 fn encode(data: &[u8]) -> String {
     BASE64_URL_SAFE_NO_PAD.encode(data)
 }
-
-pub fn get_service_info(
+// What we announce, independent of the mDNS backend.
+#[derive(Debug, Clone)]
+pub struct Advertisement {
+    pub name: String,
+    pub host: String,
+    pub ips: Vec<IpAddr>,
+    pub port: u16,
+    // Goes in the "n" TXT record
+    pub endpoint_info: String,
+}
+pub fn get_advertisement(
     config: &Config,
-    endpoint_info: EndpointInfo,
+    advertised: Advertised,
     ips: Vec<IpAddr>,
     port: u16,
-) -> ServiceInfo {
-    let name_raw = MdnsName::from_config(config).into_bytes();
+) -> Advertisement {
+    let name_raw = MdnsName::from_endpoint_id(advertised.endpoint_id).into_bytes();
     info!("Name {:#X?}", name_raw);
-    let name = encode(&name_raw);
-    let mut instance = config.name.clone();
-    instance.push_str(".local");
-    let txt = endpoint_info.to_base64();
-    let mut txt_record = HashMap::new();
-    txt_record.insert("n".to_string(), txt);
-    let service = ServiceInfo::new(TYPE, &name, &instance, &*ips, port, txt_record).unwrap();
-    service.enable_addr_auto()
+    let mut host = config.name.clone();
+    host.push_str(".local");
+    Advertisement {
+        name: encode(&name_raw),
+        host,
+        ips,
+        port,
+        endpoint_info: advertised.info.to_base64(),
+    }
 }
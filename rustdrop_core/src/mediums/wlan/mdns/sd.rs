@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use futures::{future::BoxFuture, StreamExt};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+use tracing::{debug, error, info};
+
+use super::{
+    backend::MdnsBackend,
+    browser::{parse_device, parse_removed},
+    constants::{TXT_KEY, TYPE},
+    main::Advertisement,
+};
+use crate::{
+    mediums::wlan::interfaces::selected_interfaces, runner::DiscoveringHandle, Context,
+    NetworkConfig, RustdropResult,
+};
+// mdns-sd, which runs its own responder on port 5353.
+pub(crate) struct MdnsSd {
+    daemon: ServiceDaemon,
+}
+impl MdnsSd {
+    pub fn new(network: &NetworkConfig) -> RustdropResult<Self> {
+        let daemon = ServiceDaemon::new()?;
+        let selected: Vec<IfKind> = selected_interfaces(network)
+            .iter()
+            .map(|iface| IfKind::Addr(iface.ip()))
+            .collect();
+        daemon.disable_interface(IfKind::All)?;
+        daemon.enable_interface(selected)?;
+        Ok(Self { daemon })
+    }
+    fn on_service_discovered(event: ServiceEvent, sender: &DiscoveringHandle) {
+        match event {
+            ServiceEvent::ServiceResolved(info) => {
+                debug!("Service discovered: {:?}", info);
+                let addrs: Vec<_> = info.get_addresses().iter().copied().collect();
+                if let Err(e) = parse_device(
+                    info.get_fullname(),
+                    info.get_property_val_str(TXT_KEY),
+                    &addrs,
+                    info.get_port(),
+                    sender,
+                ) {
+                    error!("Error while parsing endpoint {:?}: {}", info, e)
+                };
+            }
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                debug!("Service removed: {}", fullname);
+                if let Err(e) = parse_removed(&fullname, sender) {
+                    error!("Error while parsing endpoint {}: {}", fullname, e)
+                };
+            }
+            ServiceEvent::SearchStarted(_) => {}
+            other_event => {
+                info!("Received other event: {:?}", &other_event);
+            }
+        }
+    }
+}
+impl MdnsBackend for MdnsSd {
+    fn browse(
+        &self,
+        context: &Context,
+        handle: DiscoveringHandle,
+    ) -> BoxFuture<'_, RustdropResult<()>> {
+        let reciever = self.daemon.browse(TYPE).map(|r| r.into_stream());
+        let context = context.clone();
+        Box::pin(async move {
+            let mut reciever = reciever?;
+            context.spawn(async move {
+                while let Some(event) = reciever.next().await {
+                    Self::on_service_discovered(event, &handle);
+                }
+            });
+            Ok(())
+        })
+    }
+    fn register(&self, advertisement: Advertisement) -> BoxFuture<'_, RustdropResult<()>> {
+        Box::pin(async move {
+            let txt: HashMap<String, String> =
+                [(TXT_KEY.to_string(), advertisement.endpoint_info)].into();
+            let info = ServiceInfo::new(
+                TYPE,
+                &advertisement.name,
+                &advertisement.host,
+                &*advertisement.ips,
+                advertisement.port,
+                txt,
+            )?;
+            info!("Started MDNS thread {:?}", info);
+            self.daemon.register(info)?;
+            Ok(())
+        })
+    }
+}
+impl Drop for MdnsSd {
+    fn drop(&mut self) {
+        info!("Shutting down");
+        if let Err(e) = self.daemon.shutdown() {
+            error!("Unable to stop the mDNS daemon: {}", e);
+        }
+    }
+}
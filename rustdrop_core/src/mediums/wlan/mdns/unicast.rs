@@ -0,0 +1,149 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{net::UdpSocket, time::timeout};
+
+use super::{
+    browser::txt_value,
+    constants::{AVAHI_TYPE, DOMAIN},
+};
+use crate::{
+    core::{
+        bits::{Bitfield, EndpointInfo},
+        RustdropError,
+    },
+    RustdropResult,
+};
+
+const MDNS_PORT: u16 = 5353;
+const PTR: u16 = 12;
+const TXT: u16 = 16;
+const CLASS_IN: u16 = 1;
+const WAIT: Duration = Duration::from_secs(1);
+
+// Asks the device itself for its endpoint info, which works where multicast is blocked.
+// Responders answer queries not sent from port 5353 directly to the sender.
+pub(crate) async fn lookup(ip: IpAddr) -> RustdropResult<EndpointInfo> {
+    lookup_at(SocketAddr::new(ip, MDNS_PORT)).await
+}
+async fn lookup_at(responder: SocketAddr) -> RustdropResult<EndpointInfo> {
+    let local: IpAddr = match responder {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, 0)).await?;
+    socket.send_to(&query(), responder).await?;
+    let mut buf = vec![0; 9000];
+    let len = timeout(WAIT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| RustdropError::NoResponse())??;
+    let raw = response_txt(&buf[..len])?
+        .ok_or_else(|| RustdropError::InvalidMessage("Missing endpoint info".into()))?;
+    EndpointInfo::decode_base64(raw.as_bytes())
+}
+// A single PTR question for the Quick Share service type.
+fn query() -> Vec<u8> {
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in AVAHI_TYPE.split('.').chain([DOMAIN]) {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&PTR.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    packet
+}
+// The endpoint info of the first TXT record which has one, in any section.
+fn response_txt(packet: &[u8]) -> RustdropResult<Option<String>> {
+    let invalid = || RustdropError::InvalidMessage("Invalid DNS response".into());
+    let u16_at = |at: usize| {
+        packet
+            .get(at..at + 2)
+            .map(|raw| u16::from_be_bytes([raw[0], raw[1]]))
+            .ok_or_else(invalid)
+    };
+    let questions = u16_at(4)?;
+    let records = u16_at(6)? as usize + u16_at(8)? as usize + u16_at(10)? as usize;
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(packet, at).ok_or_else(invalid)? + 4;
+    }
+    for _ in 0..records {
+        at = skip_name(packet, at).ok_or_else(invalid)?;
+        let kind = u16_at(at)?;
+        let len = u16_at(at + 8)? as usize;
+        let data = packet.get(at + 10..at + 10 + len).ok_or_else(invalid)?;
+        at += 10 + len;
+        if kind == TXT {
+            if let Some(value) = txt_value(&txt_entries(data)) {
+                return Ok(Some(value));
+            }
+        }
+    }
+    Ok(None)
+}
+// Names are only skipped, a compression pointer ends them.
+fn skip_name(packet: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(at)? as usize;
+        match len {
+            0 => return Some(at + 1),
+            len if len & 0xc0 == 0xc0 => return Some(at + 2),
+            len => at += 1 + len,
+        }
+    }
+}
+fn txt_entries(mut data: &[u8]) -> Vec<Vec<u8>> {
+    let mut entries = Vec::new();
+    while let Some((len, rest)) = data.split_first() {
+        let len = (*len as usize).min(rest.len());
+        entries.push(rest[..len].to_vec());
+        data = &rest[len..];
+    }
+    entries
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, DeviceType};
+
+    fn record(kind: u16, data: &[u8]) -> Vec<u8> {
+        // Points back at the question's name
+        let mut record = vec![0xc0, 12];
+        record.extend_from_slice(&kind.to_be_bytes());
+        record.extend_from_slice(&CLASS_IN.to_be_bytes());
+        record.extend_from_slice(&120u32.to_be_bytes());
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+    #[tokio::test]
+    async fn test_lookup() {
+        let mut config = Config::with_dest(std::env::temp_dir());
+        config.name = "Manual laptop".into();
+        config.devtype = DeviceType::Laptop;
+        let info = EndpointInfo::new(&config, &mut rand::thread_rng()).to_base64();
+        let responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = responder.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 512];
+            let (len, from) = responder.recv_from(&mut buf).await.unwrap();
+            assert_eq!(buf[..len], query());
+            let mut response = query();
+            response[2] = 0x84;
+            response[7] = 1;
+            response[11] = 1;
+            response.extend(record(PTR, &[4, b'n', b'a', b'm', b'e', 0xc0, 12]));
+            let mut txt = vec![(info.len() + 2) as u8];
+            txt.extend_from_slice(format!("n={}", info).as_bytes());
+            response.extend(record(TXT, &txt));
+            responder.send_to(&response, from).await.unwrap();
+        });
+        let found = lookup_at(addr).await.unwrap();
+        assert_eq!(found.name, "Manual laptop");
+        assert_eq!(found.devtype(), DeviceType::Laptop);
+        assert!(response_txt(&[0, 0, 0, 0, 0, 1]).is_err());
+    }
+}
@@ -1,39 +1,46 @@
 use std::{
-    io::ErrorKind,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    io::{self, ErrorKind},
+    iter::repeat_n,
+    net::SocketAddr,
+    ops::RangeInclusive,
 };
 
 use flume::Sender;
+use futures::future::BoxFuture;
+use socket2::{Domain, Protocol, Socket, Type};
 use tokio::net::TcpListener;
-use tracing::{info, span, Level};
+use tracing::{debug, info, span, warn, Level};
 
-use super::{mdns::Mdns, WlanDiscovery};
-use crate::{mediums::Medium, runner::DiscoveringHandle, Context, ReceiveEvent, RustdropResult};
+use super::interfaces::{listen_addrs, selected_interfaces};
+#[cfg(feature = "mdns")]
+use super::{interfaces::advertised_ips, mdns::Mdns};
+use crate::{
+    mediums::{recieve, Medium},
+    runner::DiscoveringHandle,
+    Context, ReceiveEvent, RustdropResult,
+};
+
+// Ephemeral ports tried before giving up, when one is taken on another address.
+const EPHEMERAL_ATTEMPTS: usize = 5;
 
 pub struct Wlan {
-    mdns: Mdns,
+    // Without it, devices can still be reached by address.
+    #[cfg(feature = "mdns")]
+    mdns: Option<Mdns>,
     context: Context,
 }
 impl Wlan {
-    pub fn new(context: Context) -> Self {
-        Self {
-            mdns: Mdns::new(context.clone()),
+    pub async fn new(context: Context) -> RustdropResult<Self> {
+        Ok(Self {
+            #[cfg(feature = "mdns")]
+            mdns: Mdns::new(context.clone())
+                .await
+                .inspect_err(|e| warn!("Unable to start mDNS: {}", e))
+                .ok(),
             context,
-        }
+        })
     }
-    async fn run_listener(&self, addr: IpAddr, events: Sender<ReceiveEvent>) -> RustdropResult<()> {
-        let full_addr = SocketAddr::new(addr, 0);
-        let listener = match TcpListener::bind(full_addr).await {
-            Ok(l) => l,
-            Err(e) => {
-                if e.kind() == ErrorKind::InvalidInput {
-                    return Ok(());
-                }
-                return Err(e)?;
-            }
-        };
-        let addr = listener.local_addr()?;
-        info!("Bind: {}", addr);
+    fn run_listener(&self, listener: TcpListener, events: Sender<ReceiveEvent>) {
         let child = self.context.clone();
         self.context.spawn(async move {
             while let Ok((stream, addr)) = listener.accept().await {
@@ -42,29 +49,104 @@ impl Wlan {
                 let events = events.clone();
                 let (rx, tx) = stream.into_split();
                 child.spawn(async {
-                    Self::recieve(rx, tx, child_context, events).await;
+                    recieve(rx, tx, child_context, events).await;
                     drop(span)
                 });
             }
         });
-        self.mdns.advertise_mdns(vec![addr.ip()], addr.port()).await;
-        Ok(())
     }
+    // One listener per selected address, all on the same port.
     pub async fn start_wlan(&self, events: Sender<ReceiveEvent>) -> RustdropResult<()> {
-        let events = events.clone();
-        self.run_listener(Ipv4Addr::new(0, 0, 0, 0).into(), events)
-            .await
+        let network = &self.context.config.network;
+        let selected = selected_interfaces(network);
+        let listeners = bind_all(&listen_addrs(&selected), network.ports.clone())?;
+        let port = listeners[0].local_addr()?.port();
+        for listener in listeners {
+            info!("Bind: {}", listener.local_addr()?);
+            self.run_listener(listener, events.clone());
+        }
+        #[cfg(feature = "mdns")]
+        if let Some(mdns) = &self.mdns {
+            mdns.advertise_mdns(advertised_ips(&selected), port).await?;
+        }
+        #[cfg(not(feature = "mdns"))]
+        info!("Not advertising port {}, built without mDNS", port);
+        Ok(())
+    }
+}
+fn bind_all(
+    addrs: &[SocketAddr],
+    ports: Option<RangeInclusive<u16>>,
+) -> io::Result<Vec<TcpListener>> {
+    let mut last = io::Error::new(ErrorKind::InvalidInput, "Empty port range");
+    let ports: Box<dyn Iterator<Item = u16>> = match ports {
+        Some(ports) => Box::new(ports),
+        None => Box::new(repeat_n(0, EPHEMERAL_ATTEMPTS)),
+    };
+    for port in ports {
+        match bind_port(addrs, port) {
+            Ok(listeners) => return Ok(listeners),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                debug!("Unable to bind port {}: {}", port, e);
+                last = e;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last)
+}
+// Port 0 picks an ephemeral port for the first address and reuses it for the rest.
+// Listeners already bound are dropped when the port is taken on a later address.
+fn bind_port(addrs: &[SocketAddr], mut port: u16) -> io::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        let mut addr = *addr;
+        addr.set_port(port);
+        match bind(addr) {
+            Ok(listener) => {
+                port = listener.local_addr()?.port();
+                listeners.push(listener);
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => return Err(e),
+            Err(e) => warn!("Unable to listen on {}: {}", addr, e),
+        }
+    }
+    if listeners.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::AddrNotAvailable,
+            "No network interface to listen on",
+        ));
     }
+    Ok(listeners)
+}
+fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
 }
 impl Medium for Wlan {
-    type Discovery = WlanDiscovery;
-    async fn discover(&mut self, send: DiscoveringHandle) -> RustdropResult<()> {
-        self.mdns.get_dests(send).await;
-        Ok(())
+    fn discover(&mut self, send: DiscoveringHandle) -> BoxFuture<'_, RustdropResult<()>> {
+        Box::pin(async move {
+            #[cfg(feature = "mdns")]
+            if let Some(mdns) = &mut self.mdns {
+                mdns.get_dests(send).await?;
+            }
+            #[cfg(not(feature = "mdns"))]
+            let _ = send;
+            Ok(())
+        })
     }
 
-    async fn start_recieving(&mut self, send: Sender<ReceiveEvent>) -> RustdropResult<()> {
-        self.start_wlan(send).await?;
-        Ok(())
+    fn start_recieving(&mut self, send: Sender<ReceiveEvent>) -> BoxFuture<'_, RustdropResult<()>> {
+        Box::pin(async move {
+            self.start_wlan(send).await?;
+            Ok(())
+        })
     }
 }
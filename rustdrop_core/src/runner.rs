@@ -2,3 +2,4 @@ pub mod context;
 mod discovery;
 pub mod managed;
 pub use discovery::DiscoveringHandle;
+pub(crate) use discovery::KnownDevices;
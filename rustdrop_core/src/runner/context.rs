@@ -1,26 +1,138 @@
-use std::future::Future;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
 
-use rand::thread_rng;
-use tokio::runtime::Handle;
-use tokio_util::task::TaskTracker;
+use tokio::{runtime::Handle, select, sync::watch, time::sleep};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::warn;
 
-use crate::{core::bits::EndpointInfo, Config};
+use crate::{
+    core::{bits::EndpointInfo, identity::Identity, QrCodes, QR_CODE_TTL},
+    Config, QrCode,
+};
+// What the device is advertised as, which changes as the identity rotates.
+#[derive(Debug, Clone)]
+pub(crate) struct Advertised {
+    pub endpoint_id: u32,
+    pub info: EndpointInfo,
+}
 #[derive(Debug, Clone)]
 pub struct Context {
     pub config: Config,
     tasks: TaskTracker,
-    pub endpoint_info: EndpointInfo,
+    // Cancelled on shutdown, for tasks which would otherwise run forever
+    stopping: CancellationToken,
+    identity: Arc<Mutex<Identity>>,
+    advertised: Arc<watch::Sender<Advertised>>,
+    pub(crate) qr_codes: QrCodes,
 }
 impl Context {
+    // Loads the identity from `Config::state`, falling back to a new one if it can't be kept.
     pub fn new(config: Config) -> Self {
-        let mut rng = thread_rng();
-        let endpoint_info = EndpointInfo::new(&config, &mut rng);
+        let identity = config.state.as_ref().and_then(|path| {
+            Identity::load_or_create(path, config.rotate_identity)
+                .map_err(|e| warn!("Unable to keep the identity in {}: {}", path.display(), e))
+                .ok()
+        });
+        let identity = identity.unwrap_or_else(Identity::generate);
+        let advertised = Advertised {
+            endpoint_id: identity.endpoint_id,
+            info: EndpointInfo::with_salt(&config, identity.salt.clone()),
+        };
         Self {
             tasks: TaskTracker::default(),
             config,
-            endpoint_info,
+            stopping: CancellationToken::new(),
+            identity: Arc::new(Mutex::new(identity)),
+            advertised: Arc::new(watch::channel(advertised).0),
+            qr_codes: QrCodes::default(),
+        }
+    }
+    // What is advertised right now.
+    pub fn endpoint_id(&self) -> u32 {
+        self.advertised.borrow().endpoint_id
+    }
+    pub fn endpoint_info(&self) -> EndpointInfo {
+        self.advertised.borrow().info.clone()
+    }
+    pub(crate) fn advertised(&self) -> Advertised {
+        self.advertised.borrow().clone()
+    }
+    pub(crate) fn watch_advertised(&self) -> watch::Receiver<Advertised> {
+        self.advertised.subscribe()
+    }
+    // Waits for what is advertised to change, None once shutting down.
+    pub(crate) async fn advertised_change(
+        &self,
+        changes: &mut watch::Receiver<Advertised>,
+    ) -> Option<Advertised> {
+        select! {
+            _ = self.stopping.cancelled() => None,
+            changed = changes.changed() => {
+                changed.ok()?;
+                Some(changes.borrow_and_update().clone())
+            }
         }
     }
+    // A code for senders to scan, whose token is advertised until it expires.
+    pub fn qr_code(&self) -> QrCode {
+        let code = self.qr_codes.generate();
+        self.advertise_qr_token();
+        let context = self.clone();
+        self.spawn(async move {
+            select! {
+                _ = context.stopping.cancelled() => (),
+                _ = sleep(QR_CODE_TTL) => context.advertise_qr_token(),
+            }
+        });
+        code
+    }
+    fn advertise_qr_token(&self) {
+        let token = self.qr_codes.latest().map(|code| code.advertising_token());
+        self.advertised.send_if_modified(|advertised| {
+            let changed = advertised.info.qr_token != token;
+            advertised.info.qr_token = token;
+            changed
+        });
+    }
+    // Rotates the identity once it is `Config::rotate_identity` old, for as long as we run.
+    pub(crate) fn rotate_periodically(&self) {
+        let Some(rotation) = self.config.rotate_identity.filter(|r| !r.is_zero()) else {
+            return;
+        };
+        let context = self.clone();
+        self.spawn(async move {
+            loop {
+                let since = context.identity.lock().unwrap().since;
+                let age = since.elapsed().unwrap_or_default();
+                select! {
+                    _ = context.stopping.cancelled() => break,
+                    _ = sleep(rotation.saturating_sub(age)) => context.rotate_identity(),
+                }
+            }
+        });
+    }
+    // Advertises a new endpoint id and endpoint info, keeping them when there is a state file.
+    pub(crate) fn rotate_identity(&self) {
+        let mut identity = self.identity.lock().unwrap();
+        identity.rotate();
+        if let Some(path) = &self.config.state {
+            if let Err(e) = identity.save(path) {
+                warn!("Unable to keep the identity in {}: {}", path.display(), e);
+            }
+        }
+        let mut info = EndpointInfo::with_salt(&self.config, identity.salt.clone());
+        self.advertised.send_modify(|advertised| {
+            info.qr_token = advertised.info.qr_token.take();
+            advertised.endpoint_id = identity.endpoint_id;
+            advertised.info = info;
+        });
+    }
+    // Cancelled on shutdown, tasks which run until something else ends watch it as well.
+    pub(crate) fn stopping(&self) -> &CancellationToken {
+        &self.stopping
+    }
     pub fn spawn<F: Future<Output = ()> + Send + 'static>(&self, task: F) {
         self.tasks.spawn(task);
     }
@@ -28,6 +140,7 @@ impl Context {
         self.tasks.spawn_on(task, handle);
     }
     pub async fn shutdown(self) {
+        self.stopping.cancel();
         self.tasks.close();
         self.tasks.wait().await;
     }
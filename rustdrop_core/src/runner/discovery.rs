@@ -1,34 +1,265 @@
-use crate::mediums::Discover;
-use std::collections::{
-    hash_map::Entry::{Occupied, Vacant},
-    HashMap,
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use flume::Sender;
+use tokio::{
+    select,
+    time::{interval, Instant},
+};
+use tracing::debug;
+
+use crate::{
+    mediums::{Discover, MediumType, Route},
+    Context, Device, DiscoveryEvent, DiscoveryHandle,
+};
 
-use crate::{Context, Device, DiscoveryEvent, DiscoveryHandle};
+#[derive(Debug)]
+struct KnownDevice {
+    device: Device,
+    routes: HashMap<Discover, Instant>,
+    last_good: Option<Discover>,
+}
+impl KnownDevice {
+    fn mediums(&self) -> Vec<MediumType> {
+        let mut mediums: Vec<MediumType> = self.routes.keys().map(Discover::medium).collect();
+        mediums.sort();
+        mediums.dedup();
+        mediums
+    }
+    fn route_set(&self) -> HashSet<Discover> {
+        self.routes.keys().cloned().collect()
+    }
+    fn ranked(&self, prefer_ipv6: bool) -> Vec<Discover> {
+        let mut routes: Vec<(&Discover, &Instant)> = self.routes.iter().collect();
+        routes.sort_by_key(|(route, seen)| {
+            let other_family = match route.route() {
+                Route::Wlan(addr) => addr.is_ipv6() != prefer_ipv6,
+                Route::Bluetooth(_) | Route::Custom(..) => false,
+            };
+            (
+                self.last_good.as_ref() != Some(*route),
+                route.medium(),
+                other_family,
+                Reverse(**seen),
+            )
+        });
+        routes.into_iter().map(|(route, _)| route.clone()).collect()
+    }
+}
+// Every route known to each endpoint, shared between discovery and the handles given out.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct KnownDevices(Arc<Mutex<HashMap<u32, KnownDevice>>>);
+impl KnownDevices {
+    pub(crate) fn with_device(device: Device, route: Discover) -> Self {
+        let known = KnownDevice {
+            routes: [(route, Instant::now())].into(),
+            device,
+            last_good: None,
+        };
+        Self(Arc::new(Mutex::new(
+            [(known.device.endpoint_id, known)].into(),
+        )))
+    }
+    pub(crate) fn ranked(&self, endpoint_id: u32, prefer_ipv6: bool) -> Vec<Discover> {
+        let devices = self.0.lock().unwrap();
+        devices
+            .get(&endpoint_id)
+            .map(|known| known.ranked(prefer_ipv6))
+            .unwrap_or_default()
+    }
+    pub(crate) fn succeeded(&self, endpoint_id: u32, route: &Discover) {
+        if let Some(known) = self.0.lock().unwrap().get_mut(&endpoint_id) {
+            if known.routes.contains_key(route) {
+                known.last_good = Some(route.clone());
+            }
+        }
+    }
+}
+// mdns-sd already reports services whose records outlived their TTL as removed.
+fn expires(medium: MediumType) -> bool {
+    medium == MediumType::Bluetooth
+}
 
 #[derive(Clone)]
 pub struct DiscoveringHandle {
     context: Context,
     send: Sender<DiscoveryEvent>,
-    tx_map: HashMap<u32, Sender<Discover>>,
+    devices: KnownDevices,
 }
 impl DiscoveringHandle {
     pub fn new(context: Context, send: Sender<DiscoveryEvent>) -> Self {
-        Self {
+        let handle = Self {
             context,
             send,
-            tx_map: HashMap::new(),
+            devices: KnownDevices::default(),
+        };
+        let expiring = handle.clone();
+        handle.context.spawn(async move { expiring.expire().await });
+        handle
+    }
+    // Replace every route to a device over one medium.
+    pub fn resolved(&self, device: Device, medium: MediumType, routes: Vec<Discover>) {
+        let now = Instant::now();
+        let endpoint_id = device.endpoint_id;
+        let mut devices = self.devices.0.lock().unwrap();
+        match devices.entry(endpoint_id) {
+            Entry::Vacant(entry) => {
+                if routes.is_empty() {
+                    return;
+                }
+                let handle = DiscoveryHandle::new(
+                    device.clone(),
+                    self.context.clone(),
+                    self.devices.clone(),
+                );
+                entry.insert(KnownDevice {
+                    device,
+                    routes: routes.into_iter().map(|route| (route, now)).collect(),
+                    last_good: None,
+                });
+                self.emit(DiscoveryEvent::Discovered(Box::new(handle)));
+            }
+            Entry::Occupied(mut entry) => {
+                let known = entry.get_mut();
+                let before = known.route_set();
+                known.routes.retain(|route, _| route.medium() != medium);
+                for route in routes {
+                    known.routes.insert(route, now);
+                }
+                if known.routes.is_empty() {
+                    entry.remove();
+                    self.emit(DiscoveryEvent::Removed(endpoint_id));
+                } else if known.device != device || known.route_set() != before {
+                    known.device = device;
+                    self.emit(DiscoveryEvent::Updated(
+                        known.device.clone(),
+                        known.mediums(),
+                    ));
+                }
+            }
+        }
+    }
+    pub fn lost_where(&self, mut lost: impl FnMut(&Discover) -> bool) {
+        self.retain_routes(|_, route, _| !lost(route));
+    }
+    pub fn lost_medium(&self, endpoint_id: u32, medium: MediumType) {
+        self.retain_routes(|id, route, _| id != endpoint_id || route.medium() != medium);
+    }
+    fn emit(&self, event: DiscoveryEvent) {
+        if self.send.send(event).is_err() {
+            debug!("Nobody is listening for discovery events");
         }
     }
-    pub async fn discovered(&mut self, device: Device, discovery: Discover) {
-        let send = self.tx_map.entry(device.endpoint_id).or_insert_with(|| {
-            let (tx, rx) = flume::unbounded();
-            let handle = DiscoveryHandle::new(device, self.context.clone(), rx);
-            self.send.send(DiscoveryEvent::Discovered(handle)).unwrap();
-            tx
+    fn retain_routes(&self, mut keep: impl FnMut(u32, &Discover, &Instant) -> bool) {
+        let mut devices = self.devices.0.lock().unwrap();
+        devices.retain(|id, known| {
+            let count = known.routes.len();
+            known.routes.retain(|route, seen| keep(*id, route, seen));
+            if known.routes.is_empty() {
+                self.emit(DiscoveryEvent::Removed(*id));
+                false
+            } else {
+                if known.routes.len() != count {
+                    self.emit(DiscoveryEvent::Updated(
+                        known.device.clone(),
+                        known.mediums(),
+                    ));
+                }
+                true
+            }
         });
-        send.send_async(discovery).await.unwrap();
+    }
+    async fn expire(self) {
+        let expiry = self.context.config.discovery_expiry;
+        let mut ticker = interval((expiry / 2).max(Duration::from_secs(1)));
+        while !self.send.is_disconnected() {
+            select! {
+                _ = self.context.stopping().cancelled() => break,
+                _ = ticker.tick() => (),
+            }
+            let now = Instant::now();
+            self.retain_routes(|_, route, seen| {
+                !expires(route.medium()) || now.duration_since(*seen) < expiry
+            });
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        net::{Ipv6Addr, SocketAddr},
+    };
+
+    use super::*;
+    use crate::{mediums::wlan::WlanDiscovery, Config, DeviceType};
+
+    fn route(port: u16) -> Discover {
+        Discover::new(WlanDiscovery::from(SocketAddr::from((
+            [127, 0, 0, 1],
+            port,
+        ))))
+    }
+    #[tokio::test]
+    async fn test_lifecycle() {
+        let (tx, rx) = flume::unbounded();
+        let context = Context::from(Config::with_dest(temp_dir()));
+        let handle = DiscoveringHandle::new(context, tx);
+        let mut device = Device {
+            endpoint_id: 1,
+            device_name: "phone".into(),
+            device_type: DeviceType::Phone,
+        };
+        handle.resolved(device.clone(), MediumType::Wlan, vec![route(1)]);
+        assert!(matches!(rx.try_recv(), Ok(DiscoveryEvent::Discovered(_))));
+        handle.resolved(device.clone(), MediumType::Wlan, vec![route(2)]);
+        assert!(matches!(rx.try_recv(), Ok(DiscoveryEvent::Updated(..))));
+        handle.resolved(device.clone(), MediumType::Wlan, vec![route(2)]);
+        assert!(rx.try_recv().is_err());
+        device.device_name = "renamed".into();
+        handle.resolved(device.clone(), MediumType::Wlan, vec![route(2)]);
+        match rx.try_recv() {
+            Ok(DiscoveryEvent::Updated(updated, mediums)) => {
+                assert_eq!(updated, device);
+                assert_eq!(mediums, vec![MediumType::Wlan]);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        handle.lost_medium(1, MediumType::Wlan);
+        assert!(matches!(rx.try_recv(), Ok(DiscoveryEvent::Removed(1))));
+    }
+    #[tokio::test]
+    async fn test_ranking() {
+        let (tx, _rx) = flume::unbounded();
+        let context = Context::from(Config::with_dest(temp_dir()));
+        let handle = DiscoveringHandle::new(context, tx);
+        let device = Device {
+            endpoint_id: 2,
+            device_name: "laptop".into(),
+            device_type: DeviceType::Laptop,
+        };
+        let v6 = Discover::new(WlanDiscovery::from(SocketAddr::from((
+            Ipv6Addr::LOCALHOST,
+            1,
+        ))));
+        handle.resolved(device, MediumType::Wlan, vec![v6.clone(), route(1)]);
+        let devices = &handle.devices;
+        assert_eq!(devices.ranked(2, false), vec![route(1), v6.clone()]);
+        assert_eq!(devices.ranked(2, true), vec![v6.clone(), route(1)]);
+        devices.succeeded(2, &v6);
+        assert_eq!(devices.ranked(2, false), vec![v6, route(1)]);
+        assert!(devices.ranked(3, false).is_empty());
+    }
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (tx, _rx) = flume::unbounded();
+        let context = Context::from(Config::with_dest(temp_dir()));
+        DiscoveringHandle::new(context.clone(), tx);
+        let shutdown = tokio::time::timeout(Duration::from_secs(5), context.shutdown());
+        shutdown.await.unwrap();
     }
 }
@@ -1,41 +1,234 @@
+use std::net::SocketAddr;
+
 use flume::Receiver;
-use tracing::info;
+use openssl::sha::sha256;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    join, select,
+};
+use tracing::{error, info, warn};
 
+#[cfg(feature = "bluetooth")]
+use crate::mediums::bt::Bluetooth;
+#[cfg(feature = "mdns")]
+use crate::mediums::wlan::lookup;
 use crate::{
-    mediums::{bt::Bluetooth, wlan::Wlan, Medium},
-    Config, Context, DiscoveryEvent, ReceiveEvent, RustdropResult,
+    api::TransferTracker,
+    core::RustdropError,
+    mediums::{recieve, send_over, wlan::Wlan, wlan::WlanDiscovery, Discover, Medium, MediumType},
+    Config, Context, Device, DiscoveryEvent, DiscoveryHandle, Outgoing, QrCode, ReceiveEvent,
+    RustdropResult, Transfer, TransferOutcome,
 };
 
-use super::DiscoveringHandle;
+use super::{DiscoveringHandle, KnownDevices};
 pub struct Rustdrop {
     context: Context,
-    bluetooth: Bluetooth,
-    wlan: Wlan,
+    mediums: Vec<Box<dyn Medium>>,
+    unavailable: Vec<(MediumType, String)>,
 }
 impl Rustdrop {
+    // Starts the mediums selected in the config, skipping the ones which fail.
     pub async fn new(config: Config) -> RustdropResult<Self> {
         let context = Context::from(config);
-        Ok(Self {
-            wlan: Wlan::new(context.clone()),
-            bluetooth: Bluetooth::new(context.clone()).await?,
-            context,
-        })
+        context.rotate_periodically();
+        let mut rustdrop = Self {
+            context: context.clone(),
+            mediums: Vec::new(),
+            unavailable: Vec::new(),
+        };
+        for medium in context.config.mediums.iter().copied() {
+            if let Err(e) = rustdrop.add_builtin(medium).await {
+                warn!("Unable to start {:?}: {}", medium, e);
+                rustdrop.unavailable.push((medium, e.to_string()));
+            }
+        }
+        Ok(rustdrop)
+    }
+    async fn add_builtin(&mut self, medium: MediumType) -> RustdropResult<()> {
+        let context = self.context.clone();
+        match medium {
+            MediumType::Wlan => self.add_medium(Wlan::new(context).await?),
+            #[cfg(feature = "bluetooth")]
+            MediumType::Bluetooth => self.add_medium(Bluetooth::new(context).await?),
+            _ => Err(RustdropError::InvalidConfig(format!(
+                "{:?} is not built in",
+                medium
+            )))?,
+        }
+        Ok(())
+    }
+    // Mediums selected in the config which failed to start, and why.
+    pub fn unavailable(&self) -> &[(MediumType, String)] {
+        &self.unavailable
+    }
+    // Mediums added before receiving or discovering starts are used alongside the built in ones.
+    pub fn add_medium(&mut self, medium: impl Medium + 'static) {
+        self.mediums.push(Box::new(medium));
+    }
+    pub fn context(&self) -> &Context {
+        &self.context
     }
     pub async fn start_recieving(&mut self) -> RustdropResult<Receiver<ReceiveEvent>> {
         let (tx, rx) = flume::unbounded();
         info!("Running server");
-        self.wlan.start_recieving(tx.clone()).await?;
-        self.bluetooth.start_recieving(tx).await?;
+        let mut started = false;
+        for medium in self.mediums.iter_mut() {
+            match medium.start_recieving(tx.clone()).await {
+                Ok(()) => started = true,
+                Err(e) => warn!("Unable to receive over a medium: {}", e),
+            }
+        }
+        if !started {
+            Err(RustdropError::InvalidConfig(
+                "No medium to receive over".into(),
+            ))?;
+        }
         Ok(rx)
     }
     pub async fn discover(&mut self) -> RustdropResult<Receiver<DiscoveryEvent>> {
         let (tx, rx) = flume::unbounded();
         let handle = DiscoveringHandle::new(self.context.clone(), tx);
-        self.wlan.discover(handle.clone()).await?;
-        self.bluetooth.discover(handle).await?;
+        for peer in self.context.config.peers.iter().copied() {
+            let context = self.context.clone();
+            let handle = handle.clone();
+            self.context.spawn(async move {
+                match probe(context, peer).await {
+                    Ok(device) => handle.resolved(device, MediumType::Wlan, vec![route(peer)]),
+                    Err(e) => warn!("Unable to reach {}: {}", peer, e),
+                }
+            });
+        }
+        for medium in self.mediums.iter_mut() {
+            if let Err(e) = medium.discover(handle.clone()).await {
+                warn!("Unable to discover over a medium: {}", e);
+            }
+        }
         Ok(rx)
     }
+    // Reach a device by address, for when it cannot be discovered.
+    pub async fn connect(&self, addr: SocketAddr) -> RustdropResult<DiscoveryHandle> {
+        let device = probe(self.context.clone(), addr).await?;
+        let devices = KnownDevices::with_device(device.clone(), route(addr));
+        Ok(DiscoveryHandle::new(device, self.context.clone(), devices))
+    }
+    // Receive over a connection the application accepted itself, such as a forwarded socket.
+    pub fn receive_on<R, W>(&self, reader: R, writer: W) -> Receiver<ReceiveEvent>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tx, rx) = flume::unbounded();
+        let context = self.context.clone();
+        self.context.spawn(recieve(reader, writer, context, tx));
+        rx
+    }
+    // Send over a connection the application made itself.
+    pub fn send_over<R, W>(&self, reader: R, writer: W, outgoing: Outgoing) -> Transfer
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tracker, transfer) = TransferTracker::new();
+        let context = self.context.clone();
+        self.context.spawn(async move {
+            let cancelled = tracker.cancelled();
+            let (tx, rx) = flume::unbounded();
+            let sending = send_over(reader, writer, context, outgoing, tx);
+            let outcome = select! {
+                _ = cancelled.cancelled() => TransferOutcome::Cancelled,
+                (res, _) = async { join!(sending, tracker.forward(rx)) } => match res {
+                    Ok(()) => TransferOutcome::Failed("Disconnected before finishing".into()),
+                    Err(e) => {
+                        error!("Unable to send: {}", e);
+                        TransferOutcome::Failed(e.to_string())
+                    }
+                },
+            };
+            tracker.finish(outcome);
+        });
+        transfer
+    }
+    // A code for senders to scan, their shares are accepted without asking.
+    pub fn qr_code(&self) -> QrCode {
+        self.context.qr_code()
+    }
     pub async fn shutdown(self) {
         self.context.shutdown().await;
     }
 }
+fn route(addr: SocketAddr) -> Discover {
+    Discover::new(WlanDiscovery::from(addr))
+}
+// The name and type come from the endpoint info the device advertises, when it answers for it.
+async fn probe(context: Context, addr: SocketAddr) -> RustdropResult<Device> {
+    let device_type = route(addr).probe(context).await?;
+    let device = Device {
+        endpoint_id: manual_endpoint_id(addr),
+        device_name: addr.to_string(),
+        device_type,
+    };
+    #[cfg(feature = "mdns")]
+    let device = match lookup(addr.ip()).await {
+        Ok(info) => Device {
+            device_type: info.devtype(),
+            device_name: info.name,
+            ..device
+        },
+        Err(e) => {
+            info!("No endpoint info from {}: {}", addr, e);
+            device
+        }
+    };
+    Ok(device)
+}
+// Receivers never tell us their endpoint id, so derive a stable one from the address.
+fn manual_endpoint_id(addr: SocketAddr) -> u32 {
+    let hash = sha256(addr.to_string().as_bytes());
+    u32::from_be_bytes(hash[..4].try_into().unwrap())
+}
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use tokio::{
+        fs,
+        io::{duplex, split},
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::test_util::{read_when_written, temp_dir};
+
+    async fn instance(dest: PathBuf) -> Rustdrop {
+        let mut config = Config::with_dest(dest);
+        config.mediums = Vec::new();
+        Rustdrop::new(config).await.unwrap()
+    }
+    #[tokio::test]
+    async fn test_session_over_stream() {
+        let dir = temp_dir("rustdrop_over_stream").await;
+        let path = dir.join("hello.txt");
+        fs::write(&path, "Hello over a pipe").await.unwrap();
+        let receiver = instance(dir.join("received")).await;
+        let sender = instance(dir.clone()).await;
+        let (client, server) = duplex(64 * 1024);
+
+        let (rx, tx) = split(server);
+        let requests = receiver.receive_on(rx, tx);
+        tokio::spawn(async move {
+            while let Ok(event) = requests.recv_async().await {
+                if let ReceiveEvent::PairingRequest(request) = event {
+                    request.respond(true);
+                }
+            }
+        });
+        let mut outgoing = Outgoing::default();
+        outgoing.add_file(path).unwrap();
+        let (rx, tx) = split(client);
+        let transfer = sender.send_over(rx, tx, outgoing);
+        let outcome = timeout(Duration::from_secs(10), transfer.outcome()).await;
+        assert_eq!(outcome.unwrap(), TransferOutcome::Completed);
+        let received = dir.join("received").join("hello.txt");
+        assert_eq!(read_when_written(received).await, "Hello over a pipe");
+    }
+}
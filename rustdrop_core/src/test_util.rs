@@ -0,0 +1,48 @@
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use flume::Receiver;
+use tokio::{fs, time::timeout};
+
+use crate::{DiscoveryEvent, DiscoveryHandle};
+
+const WAIT: Duration = Duration::from_secs(5);
+static DIRS: AtomicUsize = AtomicUsize::new(0);
+
+// An empty directory which no other test, nor a run of the tests running alongside, uses.
+pub(crate) async fn temp_dir(name: &str) -> PathBuf {
+    let count = DIRS.fetch_add(1, Ordering::Relaxed);
+    let dir = env::temp_dir().join(format!("{}_{}_{}", name, process::id(), count));
+    let _ = fs::remove_dir_all(&dir).await;
+    fs::create_dir_all(&dir).await.unwrap();
+    dir
+}
+// Received files are created before anything is written to them.
+pub(crate) async fn read_when_written(path: impl AsRef<Path>) -> String {
+    let content = timeout(WAIT, async {
+        loop {
+            match fs::read_to_string(&path).await {
+                Ok(content) if !content.is_empty() => return content,
+                _ => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    });
+    content.await.unwrap()
+}
+pub(crate) async fn next<T: fmt::Debug>(events: &Receiver<T>) -> T {
+    match timeout(WAIT, events.recv_async()).await {
+        Ok(Ok(event)) => event,
+        other => panic!("No event {:?}", other),
+    }
+}
+pub(crate) async fn discovered(events: &Receiver<DiscoveryEvent>) -> DiscoveryHandle {
+    match next(events).await {
+        DiscoveryEvent::Discovered(found) => *found,
+        other => panic!("Unexpected event {:?}", other),
+    }
+}
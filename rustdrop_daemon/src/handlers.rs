@@ -2,7 +2,9 @@ use arboard::Clipboard;
 use ashpd::desktop::notification::{Button, Notification, NotificationProxy, Priority};
 use futures::StreamExt;
 use opener::{open, open_browser};
-use rustdrop::{IncomingText, PairingRequest, ReceiveEvent};
+use rustdrop::{
+    IncomingText, IncomingWifi, PairingRequest, ReceiveEvent, ReceivedTransfer, RejectionReason,
+};
 
 use crate::consts::ID;
 async fn handle_pairing_request(request: PairingRequest) {
@@ -29,6 +31,65 @@ async fn handle_pairing_request(request: PairingRequest) {
     };
     request.respond(action)
 }
+async fn handle_rejection(reason: RejectionReason) {
+    let proxy = NotificationProxy::new().await.unwrap();
+    let notif = Notification::new("Nearby Sharing")
+        .body(Some(&*format!("Refused an incoming share. {}", reason)))
+        .priority(Priority::Normal);
+    proxy.add_notification(ID, notif).await.unwrap();
+}
+async fn handle_auto_accepted(body: String) {
+    let proxy = NotificationProxy::new().await.unwrap();
+    let notif = Notification::new("Nearby Sharing")
+        .body(Some(&*body))
+        .priority(Priority::Normal);
+    proxy.add_notification(ID, notif).await.unwrap();
+}
+async fn handle_completed(completed: ReceivedTransfer) {
+    let Some(first) = completed.files.first() else {
+        return;
+    };
+    // Files kept somewhere other than a folder can't be opened
+    let Some(path) = first.path.clone() else {
+        return;
+    };
+    let folder = path.parent().unwrap().to_path_buf();
+    let body = match &completed.files[..] {
+        [file] => format!("Received {} from {}", file.name, completed.sender),
+        files => format!("Received {} files from {}", files.len(), completed.sender),
+    };
+    let mut notif = Notification::new("Nearby Sharing")
+        .body(Some(&*body))
+        .priority(Priority::Normal)
+        .button(Button::new("Show in folder", "folder"));
+    if completed.files.len() == 1 {
+        notif = notif.button(Button::new("Open", "open"));
+    }
+    let proxy = NotificationProxy::new().await.unwrap();
+    proxy.add_notification(ID, notif).await.unwrap();
+    let action = proxy
+        .receive_action_invoked()
+        .await
+        .unwrap()
+        .next()
+        .await
+        .expect("Stream exhausted");
+    match action.name() {
+        "open" => open(path).unwrap(),
+        "folder" => open(folder).unwrap(),
+        _ => (),
+    }
+}
+async fn handle_wifi(wifi: IncomingWifi) {
+    let mut clipboard = Clipboard::new().unwrap();
+    clipboard.set_text(wifi.password).unwrap();
+    let proxy = NotificationProxy::new().await.unwrap();
+    let body = format!("Copied the password of {} to the clipboard", wifi.ssid);
+    let notif = Notification::new("Nearby Sharing")
+        .body(Some(&*body))
+        .priority(Priority::Normal);
+    proxy.add_notification(ID, notif).await.unwrap();
+}
 async fn handle_url(text: IncomingText) {
     open_browser(text.text).unwrap()
 }
@@ -47,7 +108,13 @@ pub async fn handle_event(event: ReceiveEvent) {
             rustdrop::TextType::PhoneNumber => handle_phone(text).await,
             _ => todo!(),
         },
-        ReceiveEvent::Wifi(_) => todo!(),
+        ReceiveEvent::Wifi(wifi) => handle_wifi(wifi).await,
         ReceiveEvent::PairingRequest(request) => handle_pairing_request(request).await,
+        ReceiveEvent::AutoRejected(reason) => handle_rejection(reason).await,
+        ReceiveEvent::AutoAccepted(body) => handle_auto_accepted(body).await,
+        ReceiveEvent::File(_) => (),
+        ReceiveEvent::TransferCompleted(completed) => {
+            tokio::spawn(handle_completed(completed));
+        }
     }
 }
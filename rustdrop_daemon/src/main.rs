@@ -1,3 +1,5 @@
+use std::env;
+
 use handlers::handle_event;
 use rustdrop::{Config, Rustdrop};
 use tokio::signal;
@@ -13,6 +15,9 @@ async fn main() {
     console_subscriber::init();
     let config = Config::default();
     let mut rustdrop = Rustdrop::new(config).await.unwrap();
+    if env::args().any(|arg| arg == "--qr") {
+        println!("{}", rustdrop.qr_code().to_text());
+    }
     let events = rustdrop.start_recieving().await.unwrap();
     tokio::spawn(async move {
         while let Ok(event) = events.recv_async().await {
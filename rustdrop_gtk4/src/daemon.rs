@@ -1,9 +1,7 @@
-
-
 use async_stream::stream;
 use flume::{Receiver, Sender};
 use futures::Stream;
-use rustdrop::{Config, DiscoveryEvent, DiscoveryHandle, Rustdrop};
+use rustdrop::{Config, DiscoveryEvent, Rustdrop};
 use tokio::task::JoinHandle;
 
 use crate::event_loop::runtime;
@@ -31,11 +29,11 @@ impl Default for DaemonHandle {
     }
 }
 impl DaemonHandle {
-    pub fn recv(&self) -> impl Stream<Item = DiscoveryHandle> {
+    pub fn recv(&self) -> impl Stream<Item = DiscoveryEvent> {
         let rx = self.rx.clone();
         stream! {
-            while let Ok(DiscoveryEvent::Discovered(handle)) = rx.recv_async().await {
-                yield handle;
+            while let Ok(event) = rx.recv_async().await {
+                yield event;
             }
         }
     }
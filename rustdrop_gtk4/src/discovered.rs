@@ -1,9 +1,9 @@
 use rustdrop::DiscoveryHandle;
 use std::sync::{Arc, Mutex};
 
-use adw::subclass::prelude::*;
+use adw::{prelude::*, subclass::prelude::*};
 use glib::Object;
-use rustdrop::Outgoing;
+use rustdrop::{Device, Outgoing};
 
 mod imp {
 
@@ -41,7 +41,8 @@ mod imp {
         #[template_callback]
         async fn handle_activate(&self) {
             let outgoing = self.outgoing_handle.get().unwrap().lock().unwrap().clone();
-            let rx = self
+            let total = outgoing.total_size();
+            let transfer = self
                 .handle
                 .get()
                 .unwrap()
@@ -49,8 +50,16 @@ mod imp {
                 .unwrap();
             self.progress.set_text(Some("Sending"));
             self.progress.set_fraction(0.25);
-            while let Ok(event) = rx.recv_async().await {
+            while let Ok(event) = transfer.events().recv_async().await {
                 match event {
+                    SenderEvent::Connected(route) => {
+                        self.progress
+                            .set_text(Some(&format!("Connected over {}", route)));
+                    }
+                    SenderEvent::Pin(pin) => {
+                        self.progress
+                            .set_text(Some(&format!("Connected, PIN {}", pin)));
+                    }
                     SenderEvent::Accepted() => {
                         self.progress.set_fraction(0.75);
                         self.progress.set_text(Some("Accepted"));
@@ -59,12 +68,18 @@ mod imp {
                         self.progress.set_text(Some("Awaiting Response"));
                         self.progress.set_fraction(0.5);
                     }
+                    SenderEvent::Progress(sent) if total > 0 => {
+                        let fraction = 0.75 + 0.25 * sent as f64 / total as f64;
+                        self.progress.set_fraction(fraction);
+                    }
+                    SenderEvent::Progress(_) => (),
                     SenderEvent::Finished() => {
                         self.progress.set_text(Some("Finished"));
                         self.progress.set_fraction(1.0);
                     }
-                    SenderEvent::Rejected() => {
-                        self.progress.set_text(Some("Rejected"));
+                    SenderEvent::Rejected(reason) => {
+                        self.progress
+                            .set_text(Some(&format!("Rejected: {:?}", reason)));
                         self.progress.set_fraction(1.0);
                         break;
                     }
@@ -95,4 +110,8 @@ impl DiscoveredRow {
         self.imp().handle.set(handle).unwrap();
         self.imp().outgoing_handle.set(outgoing).unwrap();
     }
+    pub fn update(&self, device: &Device) {
+        self.set_title(&device.device_name);
+        self.set_subtitle(&format!("{:?}", device.device_type));
+    }
 }
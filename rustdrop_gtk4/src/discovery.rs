@@ -45,6 +45,7 @@ mod imp {
 
     use std::{
         cell::OnceCell,
+        collections::HashMap,
         sync::{Arc, Mutex},
     };
 
@@ -52,7 +53,7 @@ mod imp {
     use futures::{pin_mut, StreamExt};
     use glib::clone;
     use gtk::ListBox;
-    use rustdrop::Outgoing;
+    use rustdrop::{DiscoveryEvent, Outgoing};
 
     use super::*;
     use crate::{daemon::DaemonHandle, discovered::DiscoveredRow};
@@ -94,14 +95,30 @@ mod imp {
                 .build();
             self.discovery.get().set_placeholder(Some(&placeholder));
             glib::spawn_future_local(clone!(@weak self as this => async move {
-            let discovery = this.discovery_handle.recv();
+                let discovery = this.discovery_handle.recv();
                 pin_mut!(discovery);
-                            while let Some(handle) = discovery.next().await {
-                                let row = DiscoveredRow::new(handle, this.outgoing_handle.get().unwrap().clone());
-
-                                this.discovery.append(&row);
+                let mut rows = HashMap::new();
+                while let Some(event) = discovery.next().await {
+                    match event {
+                        DiscoveryEvent::Discovered(handle) => {
+                            let endpoint_id = handle.device().endpoint_id;
+                            let row = DiscoveredRow::new(*handle, this.outgoing_handle.get().unwrap().clone());
+                            this.discovery.append(&row);
+                            rows.insert(endpoint_id, row);
+                        }
+                        DiscoveryEvent::Updated(device, _) => {
+                            if let Some(row) = rows.get(&device.endpoint_id) {
+                                row.update(&device);
+                            }
+                        }
+                        DiscoveryEvent::Removed(endpoint_id) => {
+                            if let Some(row) = rows.remove(&endpoint_id) {
+                                this.discovery.remove(&row);
                             }
-                        }));
+                        }
+                    }
+                }
+            }));
         }
         fn dispose(&self) {
             while let Some(child) = self.obj().first_child() {
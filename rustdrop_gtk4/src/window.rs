@@ -1,4 +1,4 @@
-use adw::{prelude::*, subclass::prelude::*, ActionRow, Application};
+use adw::{prelude::*, subclass::prelude::*, ActionRow, Application, MessageDialog};
 use glib::subclass::InitializingObject;
 use gtk::{CompositeTemplate, FileDialog};
 mod imp {
@@ -53,11 +53,24 @@ mod imp {
             let dialog = FileDialog::new();
             if let Ok(file) = dialog.open_future(Some(&self.obj().clone())).await {
                 let path = file.path().unwrap();
-                let name = path.to_str().unwrap();
-                let row = ActionRow::builder().title(name).build();
-                self.outgoing_handle.lock().unwrap().add_file(path);
-                self.outgoing.add_row(&row);
-                self.update_visibility();
+                let name = path.to_str().unwrap().to_string();
+                let res = self.outgoing_handle.lock().unwrap().add_file(path);
+                match res {
+                    Ok(()) => {
+                        let row = ActionRow::builder().title(name).build();
+                        self.outgoing.add_row(&row);
+                        self.update_visibility();
+                    }
+                    Err(e) => {
+                        let dialog = MessageDialog::new(
+                            Some(&*self.obj()),
+                            Some("Unable to add file"),
+                            Some(&e.to_string()),
+                        );
+                        dialog.add_response("close", "Close");
+                        dialog.present();
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,190 @@
+use std::{env, fmt::Display, fmt::Write, path::Path, process::ExitCode};
+
+use base64::{
+    alphabet,
+    engine::{general_purpose::GeneralPurpose, DecodePaddingMode, GeneralPurposeConfig},
+    prelude::BASE64_URL_SAFE_NO_PAD,
+    Engine,
+};
+use rustdrop::{
+    inspect::{self, Kind},
+    Device, DeviceType, Transcript,
+};
+
+const USAGE: &str = "Usage:
+  rustdrop-inspect decode <kind> <hex or base64>
+  rustdrop-inspect encode <kind> <endpoint id> <unknown|phone|tablet|laptop> <name>
+  rustdrop-inspect capture <file>
+
+Input made only of hex digits is read as hex, prefix it with base64: to read it as base64
+or with hex: to be explicit. Unprefixed input which decodes either way is reported.
+Kinds: mdns, endpoint, ble, ble-fast, ble-v2, ble-v2-header, bluetooth, offline, ukey2, sharing";
+// Names are advertised with or without padding, and with either alphabet.
+const INDIFFERENT: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const URL_SAFE: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, INDIFFERENT);
+const STANDARD: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, INDIFFERENT);
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let res = match args[..] {
+        ["decode", kind, input] => decode(kind, input),
+        ["encode", kind, endpoint_id, device_type, ref name @ ..] if !name.is_empty() => {
+            encode(kind, endpoint_id, device_type, &name.join(" "))
+        }
+        ["capture", path] => capture(Path::new(path)),
+        _ => Err(USAGE.into()),
+    };
+    match res {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+fn decode(kind: &str, input: &str) -> Result<String, String> {
+    let kind: Kind = kind.parse().map_err(fail)?;
+    let (first, other) = parse_input(input)?;
+    match (inspect::decode(kind, &first), other) {
+        (Ok(decoded), Some(other)) if inspect::decode(kind, &other).is_ok() => Ok(format!(
+            "{}\n\nThe input is also valid base64, prefix it with base64: to read it as such",
+            decoded
+        )),
+        (Err(_), Some(other)) if inspect::decode(kind, &other).is_ok() => {
+            inspect::decode(kind, &other).map_err(fail)
+        }
+        (res, _) => res.map_err(fail),
+    }
+}
+fn encode(kind: &str, endpoint_id: &str, device_type: &str, name: &str) -> Result<String, String> {
+    let kind: Kind = kind.parse().map_err(fail)?;
+    let endpoint_id: [u8; 4] = endpoint_id
+        .as_bytes()
+        .try_into()
+        .map_err(|_| "Endpoint ids are four characters")?;
+    let device = Device {
+        endpoint_id: u32::from_be_bytes(endpoint_id),
+        device_name: name.into(),
+        device_type: parse_device_type(device_type)?,
+    };
+    let raw = inspect::encode(kind, &device).map_err(fail)?;
+    Ok(format!(
+        "base64: {}\nhex: {}",
+        BASE64_URL_SAFE_NO_PAD.encode(&raw),
+        to_hex(&raw)
+    ))
+}
+fn capture(path: &Path) -> Result<String, String> {
+    let transcript = Transcript::load(path).map_err(fail)?;
+    let records: Vec<String> = transcript.records.iter().map(inspect::describe).collect();
+    Ok(records.join("\n\n"))
+}
+fn parse_device_type(device_type: &str) -> Result<DeviceType, String> {
+    Ok(match device_type.to_lowercase().as_str() {
+        "unknown" => DeviceType::Unknown,
+        "phone" => DeviceType::Phone,
+        "tablet" => DeviceType::Tablet,
+        "laptop" => DeviceType::Laptop,
+        _ => Err(format!("Unknown device type {}", device_type))?,
+    })
+}
+// Whatever follows the first dot of an mDNS instance name is left out.
+// Hex digits are valid base64 too, so unprefixed hex is also returned as base64 when it decodes.
+fn parse_input(input: &str) -> Result<(Vec<u8>, Option<Vec<u8>>), String> {
+    if let Some(hex) = input.strip_prefix("hex:") {
+        return Ok((from_hex(hex)?, None));
+    }
+    if let Some(base64) = input.strip_prefix("base64:") {
+        return Ok((from_base64(base64)?, None));
+    }
+    let input = input.trim().split('.').next().unwrap_or_default();
+    if is_hex(input) {
+        Ok((from_hex(input)?, from_base64(input).ok()))
+    } else {
+        Ok((from_base64(input)?, None))
+    }
+}
+// Hex dumps are often split up by spaces or colons.
+fn hex_digits(input: &str) -> impl Iterator<Item = char> + '_ {
+    input.chars().filter(|c| !c.is_whitespace() && *c != ':')
+}
+fn is_hex(input: &str) -> bool {
+    hex_digits(input).count().is_multiple_of(2) && hex_digits(input).all(|c| c.is_ascii_hexdigit())
+}
+fn from_hex(input: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = hex_digits(input)
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<_>>()
+        .ok_or("Invalid hex")?;
+    if !digits.len().is_multiple_of(2) {
+        Err("Hex input has an odd number of digits")?;
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+fn from_base64(input: &str) -> Result<Vec<u8>, String> {
+    let input = input.trim();
+    URL_SAFE
+        .decode(input)
+        .or_else(|_| STANDARD.decode(input))
+        .map_err(fail)
+}
+fn to_hex(raw: &[u8]) -> String {
+    raw.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{:02x}", byte);
+        out
+    })
+}
+fn fail(e: impl Display) -> String {
+    e.to_string()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(parse_input("hex:0a:ff").unwrap(), (vec![0x0a, 0xff], None));
+        assert_eq!(parse_input("base64:AAE").unwrap(), (vec![0, 1], None));
+        assert_eq!(parse_input("_-8.local").unwrap(), (vec![0xff, 0xef], None));
+        assert_eq!(parse_input("+/8=").unwrap(), (vec![0xfb, 0xff], None));
+        // Four hex digits are three bytes of base64
+        let (hex, base64) = parse_input("beef").unwrap();
+        assert_eq!(hex, vec![0xbe, 0xef]);
+        assert_eq!(base64, Some(vec![0x6d, 0xe7, 0x9f]));
+        assert_eq!(parse_input("abc").unwrap(), (vec![0x69, 0xb7], None));
+        assert!(parse_input("hex:abc").is_err());
+        assert!(parse_input("base64:!").is_err());
+    }
+    #[test]
+    fn test_decode() {
+        let encoded = encode("endpoint", "Ab12", "phone", "Test phone").unwrap();
+        let base64 = encoded
+            .lines()
+            .next()
+            .unwrap()
+            .strip_prefix("base64: ")
+            .unwrap();
+        let hex = encoded
+            .lines()
+            .nth(1)
+            .unwrap()
+            .strip_prefix("hex: ")
+            .unwrap();
+        for input in [base64, hex] {
+            let decoded = decode("endpoint", input).unwrap();
+            assert!(decoded.starts_with("name: Test phone\n"), "{}", decoded);
+        }
+        let decoded = decode("endpoint", &format!("hex:{}", hex)).unwrap();
+        assert!(!decoded.contains("also valid base64"));
+        assert!(decode("endpoint", "base64:").is_err());
+        assert!(decode("nothing", hex).is_err());
+    }
+}